@@ -0,0 +1,139 @@
+//! Reverse execution of the simulator. The state is saved every
+//! [`CHECKPOINT_INTERVAL`] cycles, and an earlier cycle is reached by
+//! restoring the latest checkpoint before it and replaying the cycles after
+//! the checkpoint, which is deterministic.
+
+use y86_sim::framework::{CpuSim, SimSnapshot};
+
+/// Number of cycles between two checkpoints.
+const CHECKPOINT_INTERVAL: u64 = 256;
+
+pub struct History {
+    /// checkpoint `i` is taken at cycle `i * CHECKPOINT_INTERVAL`
+    checkpoints: Vec<Box<dyn SimSnapshot>>,
+    /// the last cycle that has been simulated
+    frontier: u64,
+}
+
+impl History {
+    /// Start the history of a newly created simulator.
+    pub fn new(sim: &dyn CpuSim) -> Self {
+        Self {
+            checkpoints: vec![sim.snapshot()],
+            frontier: sim.cycle_count(),
+        }
+    }
+
+    /// Record a cycle after it is simulated. Return false if the cycle has
+    /// been simulated before, i.e. it is replayed after stepping back.
+    pub fn record(&mut self, sim: &dyn CpuSim) -> bool {
+        let cycle = sim.cycle_count();
+        if cycle <= self.frontier {
+            return false;
+        }
+        self.frontier = cycle;
+        if cycle.is_multiple_of(CHECKPOINT_INTERVAL) {
+            self.checkpoints.push(sim.snapshot());
+        }
+        true
+    }
+
+    /// Move the simulator to a cycle that has been simulated.
+    pub fn goto(&self, sim: &mut dyn CpuSim, cycle: u64) {
+        debug_assert!(cycle <= self.frontier);
+        let index = (cycle / CHECKPOINT_INTERVAL) as usize;
+        let checkpoint = &self.checkpoints[index.min(self.checkpoints.len() - 1)];
+        sim.restore(checkpoint.as_ref());
+        replay(sim, cycle);
+    }
+
+    /// Find the last cycle before `cycle` (excluding the initial cycle 0) at
+    /// which `pred` holds. The simulator is left at an unspecified cycle.
+    pub fn rfind(
+        &self,
+        sim: &mut dyn CpuSim,
+        cycle: u64,
+        mut pred: impl FnMut(&dyn CpuSim) -> bool,
+    ) -> Option<u64> {
+        let mut end = cycle;
+        for checkpoint in self.checkpoints.iter().rev() {
+            let start = checkpoint.cycle_count();
+            if start >= end {
+                continue;
+            }
+            sim.restore(checkpoint.as_ref());
+            let mut found = (start > 0 && pred(sim)).then_some(start);
+            while sim.cycle_count() + 1 < end {
+                sim.initiate_next_cycle();
+                sim.propagate_signals();
+                if pred(sim) {
+                    found = Some(sim.cycle_count());
+                }
+            }
+            if found.is_some() {
+                return found;
+            }
+            end = start;
+        }
+        None
+    }
+}
+
+/// Simulate the cycles up to `cycle` in the same way as the debugger.
+fn replay(sim: &mut dyn CpuSim, cycle: u64) {
+    while sim.cycle_count() < cycle {
+        sim.initiate_next_cycle();
+        sim.propagate_signals();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use y86_sim::{architectures::create_sim, framework::MemData};
+
+    use super::*;
+
+    const SRC: &str = "    irmovq $1, %rax
+    irmovq $0, %rbx
+loop:
+    addq %rax, %rbx
+    rmmovq %rbx, 0x800
+    jmp loop
+";
+
+    #[test]
+    fn test_history() {
+        let a = y86_sim::assemble(SRC, y86_sim::AssembleOption::default()).unwrap();
+        let mem = MemData::init(a.obj.init_mem());
+        let mut sim = create_sim("pipe_std".into(), mem.clone(), false);
+        let mut history = History::new(sim.as_ref());
+
+        let state = |sim: &dyn CpuSim| (sim.registers(), sim.program_counter(), mem.read()[0x800]);
+        let mut states = vec![state(sim.as_ref())];
+        for _ in 0..1000 {
+            sim.initiate_next_cycle();
+            sim.propagate_signals();
+            assert!(history.record(sim.as_ref()));
+            states.push(state(sim.as_ref()));
+        }
+        for cycle in [999, 600, 512, 511, 1, 0, 700] {
+            history.goto(sim.as_mut(), cycle);
+            assert_eq!(sim.cycle_count(), cycle);
+            assert_eq!(state(sim.as_ref()), states[cycle as usize]);
+        }
+        // replayed cycles are not recorded again
+        replay(sim.as_mut(), 701);
+        assert!(!history.record(sim.as_ref()));
+
+        let pc = states[1000].1;
+        let expected = (1..1000).rev().find(|&c| states[c as usize].1 == pc);
+        let found = history.rfind(sim.as_mut(), 1000, |sim| sim.program_counter() == pc);
+        assert_eq!(found, expected);
+        for cycle in [300, 256, 1] {
+            let found = history.rfind(sim.as_mut(), 1000, |sim| sim.cycle_count() == cycle);
+            assert_eq!(found, Some(cycle));
+        }
+        assert_eq!(history.rfind(sim.as_mut(), 1000, |_| false), None);
+        assert_eq!(history.rfind(sim.as_mut(), 1, |_| true), None);
+    }
+}
@@ -1,3 +1,4 @@
+mod history;
 mod server;
 
 use std::net::SocketAddr;
@@ -29,13 +30,11 @@ pub fn start_tcp_listener(port: u16, option: SimOption) -> anyhow::Result<()> {
         match s {
             Ok(s) => {
                 tracing::trace!("accepted connection");
-                std::thread::Builder::new()
-                    .stack_size(32 << 20)
-                    .spawn(move || {
-                        let server = server::DebugServer::new(s.try_clone().unwrap(), s, option);
-                        let r = server.start();
-                        tracing::trace!("connection closed, result: {:?}", r);
-                    })?;
+                std::thread::Builder::new().spawn(move || {
+                    let server = server::DebugServer::new(s.try_clone().unwrap(), s, option);
+                    let r = server.start();
+                    tracing::trace!("connection closed, result: {:?}", r);
+                })?;
             }
             Err(e) => {
                 tracing::error!("failed to accept connection: {:?}", e);
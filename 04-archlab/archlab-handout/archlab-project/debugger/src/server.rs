@@ -8,16 +8,19 @@ use dap::prelude::*;
 use serde::Deserialize;
 use y86_sim::{
     architectures::create_sim,
-    framework::{CpuSim, MemData},
+    framework::{CpuSim, MemData, MAX_MEM_SIZE},
+    test::Lockstep,
 };
 
-use crate::SimOption;
+use crate::{history::History, SimOption};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum RunProgKind {
     SingleStep,
     Run,
     InitialRun,
+    StepBack,
+    ReverseRun,
 }
 
 enum ServerStatus {
@@ -29,19 +32,22 @@ enum ServerStatus {
 }
 
 pub struct Inner {
-    source_path: PathBuf,
     source_info: y86_sim::SourceInfo,
-    source_name: String,
+    /// source files of the program, indexed by file id
+    sources: Vec<types::Source>,
     scopes: Vec<types::Scope>,
     stage_info: Vec<y86_sim::framework::StageInfo>,
     sim: Box<dyn CpuSim>,
+    /// compare the simulation with the ISA if enabled by the launch option
+    lockstep: Option<Lockstep>,
+    /// checkpoints for stepping back
+    history: History,
 }
 
 pub struct DebugServer<R: Read, W: Write> {
     inner: Option<Inner>,
-    /// Lines of breakpoints
-    breakpoints: Vec<types::Breakpoint>,
-    /// Path of the source file (this debugger only supports single source file)
+    /// Breakpoints with the file id of their sources
+    breakpoints: Vec<(usize, types::Breakpoint)>,
     server: dap::server::Server<R, W>,
     status: ServerStatus,
     sim_opt: SimOption,
@@ -61,6 +67,12 @@ const VAR_PRESENTATION_HINT: types::VariablePresentationHint = types::VariablePr
 #[derive(Deserialize)]
 struct LaunchOption {
     program: String,
+    /// stop at the first instruction whose effect differs from the ISA
+    #[serde(default)]
+    lockstep: bool,
+    /// size of the memory, 64 KiB by default
+    #[serde(default, rename = "memSize")]
+    mem_size: Option<usize>,
 }
 
 impl<R: Read, W: Write> DebugServer<R, W> {
@@ -80,23 +92,46 @@ impl<R: Read, W: Write> DebugServer<R, W> {
             .ok_or(anyhow::anyhow!("program data not initialized"))
     }
 
-    fn init_program(&mut self, program: PathBuf) -> anyhow::Result<()> {
+    fn init_program(&mut self, options: &LaunchOption) -> anyhow::Result<()> {
+        let program = PathBuf::from(&options.program);
         tracing::info!("initializing program: {}", program.display());
 
         let src = std::fs::read_to_string(&program)?;
-        let a = y86_sim::assemble(&src, y86_sim::AssembleOption::default())?;
+        let file_name = program.display().to_string();
+        let mut option = y86_sim::AssembleOption::default().set_file_name(file_name);
+        if let Some(size) = options.mem_size {
+            if !(1..=MAX_MEM_SIZE).contains(&size) {
+                bail!("invalid memSize {size}, expect a size from 1 to {MAX_MEM_SIZE:#x}");
+            }
+            option = option.set_mem_size(size);
+        }
+        let a = if program.extension().is_some_and(|ext| ext == "yo") {
+            y86_sim::ObjectExt::parse_yo_with_option(&src, option)?
+        } else {
+            y86_sim::assemble(&src, option)?
+        };
 
         let mem = MemData::init(a.obj.init_mem());
-        let sim = create_sim(self.sim_opt.arch.clone(), mem, false);
-        let source_path = program.clone();
+        let sim = create_sim(self.sim_opt.arch.clone(), mem.clone(), false);
+        let lockstep = options
+            .lockstep
+            .then(|| Lockstep::new(a.obj.init_mem(), mem));
+        let history = History::new(sim.as_ref());
         let source_info = a.source;
-        let source_name = program.file_name().unwrap().to_string_lossy().to_string();
-        let main_source = types::Source {
-            name: Some(source_name.clone()),
-            path: Some(source_path.display().to_string()),
-            presentation_hint: Some(types::PresentationHint::Normal),
-            ..Default::default()
-        };
+        let sources: Vec<_> = source_info
+            .files()
+            .iter()
+            .map(|file| {
+                let path = PathBuf::from(file);
+                types::Source {
+                    name: path.file_name().map(|n| n.to_string_lossy().to_string()),
+                    path: Some(path.display().to_string()),
+                    presentation_hint: Some(types::PresentationHint::Normal),
+                    ..Default::default()
+                }
+            })
+            .collect();
+        let main_source = sources[0].clone();
 
         let mut scopes: Vec<_> = sim
             .get_stage_info()
@@ -127,29 +162,39 @@ impl<R: Read, W: Write> DebugServer<R, W> {
         let stage_info = sim.get_stage_info();
 
         self.inner = Some(Inner {
-            source_path,
             source_info,
-            source_name,
+            sources,
             scopes,
             stage_info,
             sim,
+            lockstep,
+            history,
         });
 
         Ok(())
     }
 
     fn main_source(&self) -> types::Source {
-        types::Source {
-            name: self.inner.as_ref().map(|i| i.source_name.clone()),
-            path: self
-                .inner
-                .as_ref()
-                .map(|p| p.source_path.display().to_string()),
-            presentation_hint: Some(types::PresentationHint::Normal),
-            ..Default::default()
+        match &self.inner {
+            Some(inner) => inner.sources[0].clone(),
+            None => types::Source {
+                presentation_hint: Some(types::PresentationHint::Normal),
+                ..Default::default()
+            },
         }
     }
 
+    /// Find the file id of the source path given by the client.
+    fn file_id(&self, path: &str) -> Option<usize> {
+        let canonical = |p: &str| std::fs::canonicalize(p).unwrap_or_else(|_| PathBuf::from(p));
+        let path = canonical(path);
+        self.inner
+            .as_ref()?
+            .sources
+            .iter()
+            .position(|s| s.path.as_deref().is_some_and(|p| canonical(p) == path))
+    }
+
     fn handle_request(&mut self, req: Request) -> anyhow::Result<(Response, ServerStatus)> {
         tracing::trace!(?req);
 
@@ -160,7 +205,7 @@ impl<R: Read, W: Write> DebugServer<R, W> {
                 };
                 let options: LaunchOption = serde_json::from_value(data.clone())?;
 
-                self.init_program(PathBuf::from(options.program))?;
+                self.init_program(&options)?;
 
                 Ok((req.success(ResponseBody::Launch), ServerStatus::ServeReq))
             }
@@ -181,18 +226,20 @@ impl<R: Read, W: Write> DebugServer<R, W> {
                     .path
                     .clone()
                     .ok_or(anyhow::anyhow!("missing source name"))?;
-                let source = self.main_source();
-                if Some(source_path) != source.path {
-                    bail!("source path mismatch");
-                }
+                let Some(file) = self.file_id(&source_path) else {
+                    bail!("source `{source_path}` is not a part of the program");
+                };
 
                 let inner = self.inner()?;
+                let source = inner.sources[file].clone();
 
                 let bps: Vec<types::Breakpoint> = breakpoints
                     .iter()
                     .map(|b| {
                         let verified = true;
-                        let ln = inner.source_info.get_line(b.line).unwrap();
+                        let Some(ln) = inner.source_info.get_line(file, b.line) else {
+                            return types::Breakpoint::default();
+                        };
                         let message = ln.addr.map(|a| format!("addr: {:#x}", a));
 
                         let Some(addr) = ln.addr else {
@@ -211,7 +258,10 @@ impl<R: Read, W: Write> DebugServer<R, W> {
                     })
                     .collect();
 
-                self.breakpoints = bps.clone();
+                // breakpoints are set per source file
+                self.breakpoints.retain(|(f, _)| *f != file);
+                self.breakpoints
+                    .extend(bps.iter().map(|bp| (file, bp.clone())));
 
                 Ok((
                     req.success(ResponseBody::SetBreakpoints(
@@ -261,17 +311,18 @@ impl<R: Read, W: Write> DebugServer<R, W> {
                     ));
                 }
                 let inner = self.inner()?;
+                let (file, line) = inner
+                    .source_info
+                    .get_line_number_by_addr(inner.sim.program_counter())
+                    .unwrap_or_default();
                 // currently we don't have stack trace, thus we return a single frame
                 Ok((
                     req.success(ResponseBody::StackTrace(responses::StackTraceResponse {
                         stack_frames: vec![types::StackFrame {
                             id: STACK_FRAME_ID,
                             name: "current".to_string(),
-                            source: Some(self.main_source()),
-                            line: inner
-                                .source_info
-                                .get_line_number_by_addr(inner.sim.program_counter())
-                                .unwrap_or_default(),
+                            source: inner.sources.get(file).cloned(),
+                            line,
                             can_restart: Some(false),
                             presentation_hint: Some(types::StackFramePresentationhint::Normal),
                             ..Default::default()
@@ -360,6 +411,24 @@ impl<R: Read, W: Write> DebugServer<R, W> {
                     ServerStatus::RunProg(RunProgKind::Run),
                 ))
             }
+            Command::StepBack(args) => {
+                if args.thread_id != THREAD_ID {
+                    bail!("invalid thread id");
+                }
+                Ok((
+                    req.success(ResponseBody::StepBack),
+                    ServerStatus::RunProg(RunProgKind::StepBack),
+                ))
+            }
+            Command::ReverseContinue(args) => {
+                if args.thread_id != THREAD_ID {
+                    bail!("invalid thread id");
+                }
+                Ok((
+                    req.success(ResponseBody::ReverseContinue),
+                    ServerStatus::RunProg(RunProgKind::ReverseRun),
+                ))
+            }
             _ => {
                 bail!("ydb: not implemented");
             }
@@ -378,6 +447,7 @@ impl<R: Read, W: Write> DebugServer<R, W> {
         };
         let rsp = req.success(ResponseBody::Initialize(types::Capabilities {
             supports_configuration_done_request: Some(true),
+            supports_step_back: Some(true),
             ..Default::default()
         }));
 
@@ -437,6 +507,9 @@ impl<R: Read, W: Write> DebugServer<R, W> {
             self.status = ServerStatus::ServeReq;
             return Ok(());
         }
+        if matches!(kind, RunProgKind::StepBack | RunProgKind::ReverseRun) {
+            return self.run_back(kind);
+        }
 
         let inner = self
             .inner
@@ -469,17 +542,36 @@ impl<R: Read, W: Write> DebugServer<R, W> {
             tracing::trace!("cycle count: {}", sim.cycle_count());
             sim.initiate_next_cycle();
             sim.propagate_signals();
+            // cycles replayed after stepping back have been checked
+            let checked = !inner.history.record(sim.as_ref());
 
             let pc = sim.program_counter();
 
-            if let Some(bp) = self.breakpoints.iter().find(|bp| {
-                let Some(bp_ln) = bp.line else { return false };
-                inner
-                    .source_info
-                    .get_line_number_by_addr(pc)
-                    .map(|ln| ln == bp_ln)
-                    .unwrap_or(false)
-            }) {
+            if let Some(lockstep) = inner.lockstep.as_mut().filter(|_| !checked) {
+                let result = match sim.is_terminate() {
+                    true => lockstep.finish(sim.as_ref()),
+                    false => lockstep.check(sim.as_ref()),
+                };
+                if let Err(divergence) = result {
+                    tracing::info!("{divergence}");
+                    // report only the first divergence
+                    inner.lockstep = None;
+                    self.server
+                        .send_event(Event::Stopped(events::StoppedEventBody {
+                            reason: types::StoppedEventReason::Exception,
+                            description: Some("Diverge from the ISA".to_string()),
+                            thread_id: Some(THREAD_ID),
+                            preserve_focus_hint: Some(false),
+                            text: Some(divergence.to_string()),
+                            all_threads_stopped: None,
+                            hit_breakpoint_ids: None,
+                        }))?;
+                    self.status = ServerStatus::ServeReq;
+                    break;
+                }
+            }
+
+            if let Some(bp) = find_breakpoint(&self.breakpoints, &inner.source_info, pc) {
                 let bp_id = bp.id.ok_or(anyhow::anyhow!("breakpoint id not set"))?;
                 tracing::trace!("hit breakpoint: line = {:?}", bp.line);
                 self.server
@@ -529,6 +621,60 @@ impl<R: Read, W: Write> DebugServer<R, W> {
         Ok(())
     }
 
+    /// Move back to the previous cycle, or to the last cycle hitting a
+    /// breakpoint (or the beginning if there is none).
+    fn run_back(&mut self, kind: RunProgKind) -> anyhow::Result<()> {
+        let inner = self
+            .inner
+            .as_mut()
+            .ok_or(anyhow::anyhow!("program data not initialized"))?;
+        let sim = inner.sim.as_mut();
+
+        let cycle = sim.cycle_count();
+        let hit = match kind {
+            RunProgKind::ReverseRun => inner.history.rfind(sim, cycle, |sim| {
+                find_breakpoint(&self.breakpoints, &inner.source_info, sim.program_counter())
+                    .is_some()
+            }),
+            _ => None,
+        };
+        let target = hit.unwrap_or(match kind {
+            RunProgKind::StepBack => cycle.saturating_sub(1),
+            _ => 0,
+        });
+        inner.history.goto(sim, target);
+        let pc = sim.program_counter();
+        tracing::trace!("step back to cycle {target}");
+
+        let (reason, description, hit_breakpoint_ids) = if hit.is_some() {
+            let bp = find_breakpoint(&self.breakpoints, &inner.source_info, pc)
+                .ok_or(anyhow::anyhow!("breakpoint not found"))?;
+            let bp_id = bp.id.ok_or(anyhow::anyhow!("breakpoint id not set"))?;
+            let reason = types::StoppedEventReason::Breakpoint;
+            (reason, "Stop at breakpoint", Some(vec![bp_id]))
+        } else if target == 0 {
+            (types::StoppedEventReason::Entry, "Stop at beginning", None)
+        } else {
+            (
+                types::StoppedEventReason::Step,
+                "Stop at previous step",
+                None,
+            )
+        };
+        let body = events::StoppedEventBody {
+            reason,
+            description: Some(description.to_string()),
+            thread_id: Some(THREAD_ID),
+            preserve_focus_hint: Some(false),
+            text: Some(format!("pc = {pc:#x}, cycle count = {target}")),
+            all_threads_stopped: None,
+            hit_breakpoint_ids,
+        };
+        self.server.send_event(Event::Stopped(body))?;
+        self.status = ServerStatus::ServeReq;
+        Ok(())
+    }
+
     pub fn start(mut self) -> anyhow::Result<()> {
         self.init()?;
 
@@ -550,3 +696,16 @@ impl<R: Read, W: Write> DebugServer<R, W> {
         }
     }
 }
+
+/// Find the breakpoint at the line of `pc`.
+fn find_breakpoint<'a>(
+    breakpoints: &'a [(usize, types::Breakpoint)],
+    source_info: &y86_sim::SourceInfo,
+    pc: u64,
+) -> Option<&'a types::Breakpoint> {
+    let line = source_info.get_line_number_by_addr(pc)?;
+    breakpoints
+        .iter()
+        .find(|(file, bp)| bp.line.is_some_and(|ln| line == (*file, ln)))
+        .map(|(_, bp)| bp)
+}
@@ -1,6 +1,13 @@
 use anyhow::{bail, Context};
 use rand::prelude::*;
-use y86_sim::{isa::reg_code, utils::get_u64, AssembleOption};
+use y86_sim::{
+    isa::{reg_code, Stat},
+    utils::get_u64,
+    AssembleOption, ObjectExt,
+};
+
+/// Path of the ncopy implementation, relative to the project root.
+const NCOPY_PATH: &str = "misc/ncopy.ys";
 
 fn gen_i64s(rng: &mut SmallRng, len: usize) -> Vec<i64> {
     rng.sample_iter(rand::distributions::Standard)
@@ -9,11 +16,14 @@ fn gen_i64s(rng: &mut SmallRng, len: usize) -> Vec<i64> {
 }
 
 fn render_nums(data: &[i64]) -> String {
-    data.iter()
-        .map(|&num| num as u64)
-        .map(|num| format!(".quad 0x{:016x}", num))
-        .collect::<Vec<_>>()
-        .join("\n")
+    if data.is_empty() {
+        return String::new();
+    }
+    let nums: Vec<_> = data
+        .iter()
+        .map(|&num| format!("{:#x}", num as u64))
+        .collect();
+    format!(".quad {}", nums.join(", "))
 }
 
 /// This function computes the score rate of based on the metrics of one's
@@ -60,7 +70,9 @@ pub fn grade_ncopy(tty_out: bool) -> anyhow::Result<(f64, u64)> {
     // fix seed for reproducibility
     let mut rng = SmallRng::from_seed([0; 32]);
 
-    let source = std::fs::read_to_string("misc/ncopy.ys")?;
+    if !std::path::Path::new(NCOPY_PATH).exists() {
+        bail!("could not find {NCOPY_PATH}");
+    }
 
     let Some(tester) = y86_sim::test::SimTester::new("ncopy") else {
         bail!(
@@ -81,7 +93,9 @@ pub fn grade_ncopy(tty_out: bool) -> anyhow::Result<(f64, u64)> {
         let data = gen_i64s(&mut rng, len);
 
         // This source file call the ncopy function to copy data from data_to_copy to
-        // data_dst the return value of ncopy is the number of positive numbers
+        // data_dst the return value of ncopy is the number of positive numbers.
+        // The ncopy function is included so that errors are reported with its
+        // own line numbers.
         let src = format!(
             r##"
             .pos 0
@@ -98,7 +112,7 @@ pub fn grade_ncopy(tty_out: bool) -> anyhow::Result<(f64, u64)> {
             nop
             nop
         
-        {source}
+            .include "{NCOPY_PATH}"
 
         # do not fall through to the end of the source!
         # Otherwise you will encounter this trap :)
@@ -193,13 +207,26 @@ pub fn grade_ncopy(tty_out: bool) -> anyhow::Result<(f64, u64)> {
     Ok((avg_cpe, sim_cycle_cost))
 }
 
+/// Load a program from a `.ys` source file or a pre-assembled `.yo` file.
+fn load_program(path: &str) -> anyhow::Result<ObjectExt> {
+    let source = std::fs::read_to_string(path)?;
+    let a = if path.ends_with(".yo") {
+        ObjectExt::parse_yo_with_name(&source, path)?
+    } else {
+        y86_sim::assemble(&source, AssembleOption::default().set_file_name(path))?
+    };
+    Ok(a)
+}
+
 /// This function just checks if the given array is sorted. Make sure there's
 /// a symbol `array` in `misc/bubble.ys` that points to the array.
 pub fn grade_bubble(path: &str) -> anyhow::Result<()> {
-    let source = std::fs::read_to_string(path)?;
-    let a = y86_sim::assemble(&source, AssembleOption::default())?;
+    let a = load_program(path)?;
 
-    let res = y86_sim::isa::simulate(a.obj.init_mem(), false)?;
+    let res = y86_sim::isa::simulate(a.obj.init_mem(), false);
+    if res.stat != Stat::Hlt {
+        bail!("program stops with status {:?} at {:#x}", res.stat, res.pc);
+    }
     let array_addr = a
         .obj
         .symbols
@@ -211,10 +238,10 @@ pub fn grade_bubble(path: &str) -> anyhow::Result<()> {
     array.sort();
 
     // the length of the array is 6
-    for i in 0..array.len() {
+    for (i, expected) in array.into_iter().enumerate() {
         let val = get_u64(&res.bin[(array_addr + i * 8)..(array_addr + (i + 1) * 8)]);
-        if val != array[i] {
-            bail!("expected: 0x{:x}, got: 0x{:x}", array[i], val);
+        if val != expected {
+            bail!("expected: 0x{:x}, got: 0x{:x}", expected, val);
         }
     }
 
@@ -224,10 +251,12 @@ pub fn grade_bubble(path: &str) -> anyhow::Result<()> {
 /// This function only checks if the return value of the sum function is the
 /// sum of all elements in the list.
 pub fn grade_sum(path: &str) -> anyhow::Result<()> {
-    let source = std::fs::read_to_string(path)?;
-    let a = y86_sim::assemble(&source, AssembleOption::default())?;
+    let a = load_program(path)?;
 
-    let res = y86_sim::isa::simulate(a.obj.init_mem(), false)?;
+    let res = y86_sim::isa::simulate(a.obj.init_mem(), false);
+    if res.stat != Stat::Hlt {
+        bail!("program stops with status {:?} at {:#x}", res.stat, res.pc);
+    }
     let nums = [0x00d, 0x0e0, 0xf00];
     let ans = nums.into_iter().sum::<u64>();
     let out = res.regs[reg_code::RAX as usize];
@@ -243,7 +272,11 @@ pub fn grade_sum(path: &str) -> anyhow::Result<()> {
 ///
 /// - If `ext_iopq` is true, the architecture should support the `iopq`
 ///   instruction.
-pub fn grade_arch(arch: &str, ext_iopq: bool, tty_out: bool) -> anyhow::Result<()> {
+/// - If `ext_jm` is true, the architecture should support the `jm`
+///   instruction.
+///
+/// The arithmetic extension is checked if the architecture declares it.
+pub fn grade_arch(arch: &str, ext_iopq: bool, ext_jm: bool, tty_out: bool) -> anyhow::Result<()> {
     let paths = std::fs::read_dir("misc/y86-code")?
         .filter_map(Result::ok)
         .map(|entry| entry.path())
@@ -254,13 +287,7 @@ pub fn grade_arch(arch: &str, ext_iopq: bool, tty_out: bool) -> anyhow::Result<(
             if !ext_iopq && path.ends_with("asumi.ys") {
                 continue;
             }
-            // we do not support jm instruction
-            if path.ends_with("asumj.ys") {
-                continue;
-            }
-
-            // this program cause rsp to overflow
-            if path.ends_with("prog10.ys") {
+            if !ext_jm && path.ends_with("asumj.ys") {
                 continue;
             }
 
@@ -290,6 +317,22 @@ pub fn grade_arch(arch: &str, ext_iopq: bool, tty_out: bool) -> anyhow::Result<(
         }
     }
 
+    let Some(sim) = y86_sim::test::SimTester::new(arch) else {
+        bail!("architecture not found: {}", arch);
+    };
+    if sim.supports(y86_sim::isa::Extension::Arith) {
+        if tty_out {
+            println!("- testing arithmetic extension");
+        }
+        sim.test_arith(ext_iopq)?;
+    }
+    if sim.supports(y86_sim::isa::Extension::Exception) {
+        if tty_out {
+            println!("- testing exception extension");
+        }
+        sim.test_exception()?;
+    }
+
     Ok(())
 }
 
@@ -306,14 +349,10 @@ fn compare_arch(arch: &str, gt_arch: &str, tty_out: bool) -> anyhow::Result<()>
             if path.ends_with("asumi.ys") {
                 continue;
             }
-            // we do not support jm instruction
+            // the lab architectures do not support jm instruction
             if path.ends_with("asumj.ys") {
                 continue;
             }
-            // this program cause rsp to overflow
-            if path.ends_with("prog10.ys") {
-                continue;
-            }
             if tty_out {
                 println!("- testing {}", path.display());
             }
@@ -367,7 +406,7 @@ pub fn grade_pipe_placeholder(
         println!("testing {} ISA:", arch);
     }
     // check ISA correctness
-    grade_arch(arch, false, tty_out)?;
+    grade_arch(arch, false, false, tty_out)?;
 
     if let Some(gt_arch) = gt_arch {
         if tty_out {
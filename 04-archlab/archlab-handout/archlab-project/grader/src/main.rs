@@ -46,7 +46,7 @@ fn grade_autolab() {
 
     // Part B
     let seq_full_score = std::panic::catch_unwind(|| {
-        grader::grade_arch("seq_full", true, false)
+        grader::grade_arch("seq_full", true, false, false)
             .map(|_| PART_B_SEQ_FULL_SCORE)
             .unwrap_or(0)
     })
@@ -112,6 +112,9 @@ enum Subcommands {
         /// Whether to test the iopq instruction
         #[arg(short = 'i', long)]
         iopq: bool,
+        /// Whether to test the jm instruction
+        #[arg(short = 'j', long)]
+        jm: bool,
     },
 }
 
@@ -147,7 +150,9 @@ fn inner_main() -> anyhow::Result<()> {
             grade_it("misc/rsum.ys", || grader::grade_sum("misc/rsum.ys"));
         }
         Subcommands::PartB => {
-            grade_it("seq_full", || grader::grade_arch("seq_full", true, true));
+            grade_it("seq_full", || {
+                grader::grade_arch("seq_full", true, false, true)
+            });
 
             let names = y86_sim::architectures::arch_names();
 
@@ -179,12 +184,12 @@ fn inner_main() -> anyhow::Result<()> {
             let r = std::panic::catch_unwind(grade_autolab);
 
             // we got a panic!
-            if let Err(_) = r {
+            if r.is_err() {
                 println!("PartA-Correctness=0 PartB-Testbench=0 PartC-CPE=0 ParcC-AC=0 PartC-Performance=0");
             }
         }
-        Subcommands::Arch { arch, iopq } => {
-            grader::grade_arch(&arch, iopq, true).context("test architecture")?;
+        Subcommands::Arch { arch, iopq, jm } => {
+            grader::grade_arch(&arch, iopq, jm, true).context("test architecture")?;
         }
     }
 
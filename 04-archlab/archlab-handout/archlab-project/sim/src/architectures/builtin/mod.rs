@@ -1,4 +1,5 @@
 //! Builtin architectures
+pub mod pipe_exc;
 pub mod pipe_std;
 pub mod seq_plus_std;
 pub mod seq_std;
@@ -22,4 +23,143 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_arith() -> anyhow::Result<()> {
+        for arch in ["seq_std", "seq_plus_std", "pipe_std", "pipe_exc"] {
+            let tester = SimTester::new(arch).unwrap();
+            assert!(tester.supports(crate::isa::Extension::Arith));
+            tester.test_arith(false)?;
+        }
+        assert!(!SimTester::new("pipe_s4c")
+            .unwrap()
+            .supports(crate::isa::Extension::Arith));
+        Ok(())
+    }
+
+    #[test]
+    fn test_exception() -> anyhow::Result<()> {
+        let tester = SimTester::new("pipe_exc").unwrap();
+        assert!(tester.supports(crate::isa::Extension::Exception));
+        tester.test_exception()?;
+        tester.test_opq()?;
+        tester.test_cmov()?;
+        tester.test_jm()?;
+        Ok(())
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_trace_record() {
+        use crate::framework::{MemData, MEM_SIZE};
+
+        let mem = MemData::zeroed(MEM_SIZE);
+        let mut sim = crate::architectures::create_sim("pipe_std".into(), mem, false);
+        sim.propagate_signals();
+        let record = sim.trace_record();
+        assert_eq!(record["cycle"], 1);
+        assert_eq!(record["stages"]["w"]["stat"], "Bub");
+        assert_eq!(record["signals"]["f_stat"], "Hlt");
+        assert!(record["unit_in"]["reg_file"].is_object());
+    }
+
+    /// Run the CS:APP test programs, which include the `jm` instruction and
+    /// an invalid stack access (prog10.ys), on the builtin architectures.
+    #[test]
+    fn test_y86_code() -> anyhow::Result<()> {
+        let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("../misc/y86-code");
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            // asumi.ys needs the iopq extension
+            if path.ends_with("asumi.ys") {
+                continue;
+            }
+            let src = std::fs::read_to_string(&path)?;
+            for arch in ["seq_std", "seq_plus_std", "pipe_std", "pipe_exc"] {
+                SimTester::new(arch)
+                    .unwrap()
+                    .test_isa(&src)
+                    .map_err(|e| e.context(format!("{arch}: {}", path.display())))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Rerun a program from a snapshot, which should repeat the same cycles
+    /// and restore the memory shared with the caller.
+    #[test]
+    fn test_snapshot() -> anyhow::Result<()> {
+        use crate::framework::{CpuSim, MemData};
+
+        let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("../misc/y86-code");
+        let src = std::fs::read_to_string(dir.join("asum.ys"))?;
+        let a = crate::assemble(&src, crate::AssembleOption::default()).unwrap();
+
+        let run = |sim: &mut dyn CpuSim, mem: &MemData| {
+            let mut cycles = Vec::new();
+            while !sim.is_terminate() {
+                sim.step();
+                cycles.push(format!("{:?}", sim.get_stage_info()));
+            }
+            (
+                cycles,
+                sim.registers(),
+                sim.io_devices(),
+                mem.read().to_vec(),
+            )
+        };
+        for arch in ["seq_std", "pipe_std", "pipe_exc"] {
+            let mem = MemData::init(a.obj.init_mem());
+            let mut sim = crate::architectures::create_sim(arch.into(), mem.clone(), false);
+            for _ in 0..20 {
+                sim.step();
+            }
+            let snapshot = sim.snapshot();
+            assert_eq!(snapshot.cycle_count(), 20);
+
+            let expected = run(&mut *sim, &mem);
+            let cycle_count = sim.cycle_count();
+            mem.write().fill(0);
+            sim.restore(&*snapshot);
+            assert_eq!(sim.cycle_count(), 20);
+            assert_eq!(run(&mut *sim, &mem), expected, "{arch}");
+            assert_eq!(sim.cycle_count(), cycle_count);
+        }
+        Ok(())
+    }
+
+    /// Simulators can be moved to other threads and run in parallel.
+    #[test]
+    fn test_parallel() -> anyhow::Result<()> {
+        use crate::framework::MemData;
+
+        let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("../misc/y86-code");
+        let src = std::fs::read_to_string(dir.join("asum.ys"))?;
+        let a = crate::assemble(&src, crate::AssembleOption::default()).unwrap();
+        let expected = crate::isa::simulate(a.obj.init_mem(), false);
+
+        let sims = ["seq_std", "seq_plus_std", "pipe_std", "pipe_exc"].map(|arch| {
+            let mem = MemData::init(a.obj.init_mem());
+            (
+                crate::architectures::create_sim(arch.into(), mem.clone(), false),
+                mem,
+            )
+        });
+        std::thread::scope(|s| {
+            let handles = sims.map(|(mut sim, mem)| {
+                s.spawn(move || {
+                    while !sim.is_terminate() {
+                        sim.step();
+                    }
+                    (sim, mem)
+                })
+            });
+            for handle in handles {
+                let (sim, mem) = handle.join().unwrap();
+                assert_eq!(sim.registers(), expected.regs);
+                assert_eq!(*mem.read(), *expected.bin);
+            }
+        });
+        Ok(())
+    }
 }
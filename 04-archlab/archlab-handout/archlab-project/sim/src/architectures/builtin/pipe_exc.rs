@@ -0,0 +1,535 @@
+// PIPE with precise exceptions (the exception extension). An exception is
+// taken when the faulting instruction, or `trap`, reaches the write back
+// stage: the instruction has no effect, the younger instructions in the
+// pipeline are cancelled, and the handler is fetched in the same cycle.
+// `trap` and `iret` stall the fetch like `ret`, and `iret` reads EPC in the
+// memory stage.
+crate::define_stages! {
+    FetchStage f {
+        pred_pc: u64 = 0
+    }
+    DecodeStage d {
+        stat: Stat = Bub, icode: u8 = NOP, ifun: u8 = 0,
+        rA: u8 = RNONE, rB: u8 = RNONE,
+        valC: u64 = 0, valP: u64 = 0, epc: u64 = 0
+    }
+    ExecuteStage e {
+        stat: Stat = Bub, icode: u8 = NOP, ifun: u8 = 0,
+        valC: u64 = 0,
+        valA: u64 = 0, valB: u64 = 0,
+        dstE: u8 = RNONE, dstM: u8 = RNONE,
+        srcA: u8 = RNONE, srcB: u8 = RNONE, epc: u64 = 0
+    }
+    /// Memory Access Stage
+    MemoryStage m {
+        stat: Stat = Bub, icode: u8 = NOP, ifun: u8 = 0, cnd: bool = false,
+        valE: u64 = 0, valA: u64 = 0,
+        dstE: u8 = RNONE, dstM: u8 = RNONE, epc: u64 = 0
+    }
+    WritebackStage w {
+        stat: Stat = Bub, icode: u8 = NOP, ifun: u8 = 0, valE: u64 = 0,
+        valM: u64 = 0, dstE: u8 = RNONE, dstM: u8 = RNONE, epc: u64 = 0
+    }
+}
+
+sim_macro::hcl! {
+
+#![hardware = crate::architectures::hardware_exc]
+#![program_counter = f_pc]
+#![termination = prog_term]
+#![status = prog_stat]
+#![extensions(Arith, Exception)]
+#![stage_alias(F => f, D => d, E => e, M => m, W => w)]
+
+use Stat::*;
+
+:==============================: Fetch Stage :================================:
+
+// What address should instruction be fetched at
+u64 f_pc = [
+    // Exception taken in write back stage. Fetch the handler
+    exc.taken : exc.handler;
+    // Mispredicted branch. Fetch at incremented PC
+    M.icode == JX && !M.cnd : M.valA;
+    // Completion of RET, JM or IRET instruction
+    W.icode in { RET, JM, EXC } : W.valM;
+    // Default: Use predicted value of PC (default to 0)
+     1 : F.pred_pc;
+];
+
+@set_input(imem, {
+    pc: f_pc
+});
+
+// Determine icode of fetched instruction
+u8 f_icode = [
+    imem.error : NOP;
+    1 : imem.icode;
+];
+
+// Determine ifun
+u8 f_ifun = [
+    imem.error : 0xf; // FNONE;
+    1 : imem.ifun;
+];
+
+// Is instruction valid?
+bool instr_valid = f_icode in { NOP, HALT, CMOVX, IRMOVQ, RMMOVQ,
+    MRMOVQ, OPQ, JX, CALL, RET, PUSHQ, POPQ, JM } ||
+    f_icode == EXC && f_ifun in { TRAP, IRET };
+
+// Determine status code for fetched instruction
+Stat f_stat = [
+    imem.error : Adr;
+    !instr_valid : Ins;
+    f_icode == HALT : Hlt;
+    1 : Aok;
+];
+
+// Does fetched instruction require a regid byte?
+bool need_regids
+    = f_icode in { CMOVX, OPQ, PUSHQ, POPQ, IRMOVQ, RMMOVQ, MRMOVQ, JM };
+
+// Does fetched instruction require a constant word?
+bool need_valC = f_icode in { IRMOVQ, RMMOVQ, MRMOVQ, JX, CALL, JM };
+
+@set_input(pc_inc, {
+    need_valC: need_valC,
+    need_regids: need_regids,
+    old_pc: f_pc,
+});
+
+u64 f_valP =  pc_inc.new_pc;
+
+[u8; 9] f_align = imem.align;
+
+@set_input(ialign, {
+    align: f_align,
+    need_regids: need_regids,
+});
+
+u64 f_valC =  ialign.valC;
+u8 f_rA = ialign.rA;
+u8 f_rB = ialign.rB;
+
+// Predict next value of PC
+u64 f_pred_pc = [
+     f_icode in { JX, CALL } : f_valC;
+     1 : f_valP;
+];
+
+// Address saved to EPC if the instruction raises an exception. `trap`
+// returns to the following instruction, while a fault is retried.
+u64 f_epc = [
+    f_stat == Aok && f_icode == EXC && f_ifun == TRAP : f_valP;
+    1 : f_pc;
+];
+
+@set_stage(f, {
+    pred_pc: f_pred_pc,
+});
+
+@set_stage(d, {
+    icode: f_icode,
+    ifun: f_ifun,
+    stat: f_stat,
+    valC: f_valC,
+    valP: f_valP,
+    rA: f_rA,
+    rB: f_rB,
+    epc: f_epc,
+});
+
+:=======================: Decode and Write Back Stage :========================:
+
+// What register should be used as the A source?
+u8 d_srcA = [
+    D.icode in { CMOVX, RMMOVQ, OPQ, PUSHQ } : D.rA;
+    D.icode in { POPQ, RET } : RSP;
+    1 : RNONE; // Don't need register
+];
+
+// What register should be used as the B source?
+u8 d_srcB = [
+    D.icode in { OPQ, RMMOVQ, MRMOVQ, JM } : D.rB;
+    D.icode in { PUSHQ, POPQ, CALL, RET } : RSP;
+    1 : RNONE; // Don't need register
+];
+
+// What register should be used as the E destination?
+u8 d_dstE = [
+    D.icode in { CMOVX, IRMOVQ, OPQ } : D.rB;
+    D.icode in { PUSHQ, POPQ, CALL, RET } : RSP;
+    1 : RNONE; // Don't write any register
+];
+
+// What register should be used as the M destination?
+u8 d_dstM = [
+    D.icode in { MRMOVQ, POPQ } : D.rA;
+    1 : RNONE; // Don't write any register
+];
+
+u64 d_rvalA = reg_file.valA;
+u64 d_rvalB = reg_file.valB;
+
+// What should be the A value?
+// Forward into decode stage for valA
+u64 d_valA = [
+    D.icode in { CALL, JX } : D.valP; // Use incremented PC
+    d_srcA == RNONE : 0; // Don't forward to an absent register
+    d_srcA == e_dstE : e_valE; // Forward valE from execute
+    d_srcA == M.dstM : m_valM; // Forward valM from memory
+    d_srcA == M.dstE : M.valE; // Forward valE from memory
+    d_srcA == W.dstM : W.valM; // Forward valM from write back
+    d_srcA == W.dstE : W.valE; // Forward valE from write back
+    1 : d_rvalA; // Use value read from register file
+];
+
+u64 d_valB = [
+    d_srcB == RNONE : 0; // e.g. the absolute address of mrmovq
+    d_srcB == e_dstE : e_valE; // Forward valE from execute
+    d_srcB == M.dstM : m_valM; // Forward valM from memory
+    d_srcB == M.dstE : M.valE; // Forward valE from memory
+    d_srcB == W.dstM : W.valM; // Forward valM from write back
+    d_srcB == W.dstE : W.valE; // Forward valE from write back
+    1 : d_rvalB; // Use value read from register file
+];
+
+u64 d_valC = D.valC;
+u8 d_icode = D.icode;
+u8 d_ifun = D.ifun;
+Stat d_stat = D.stat;
+u64 d_epc = D.epc;
+
+@set_stage(e, {
+    icode: d_icode,
+    ifun: d_ifun,
+    stat: d_stat,
+    valC: d_valC,
+    srcA: d_srcA,
+    srcB: d_srcB,
+    valA: d_valA,
+    valB: d_valB,
+    dstE: d_dstE,
+    dstM: d_dstM,
+    epc: d_epc,
+});
+
+:==============================: Execute Stage :===============================:
+
+// Select input A to ALU
+u64 aluA = [
+    E.icode in { CMOVX, OPQ } : E.valA;
+    E.icode in { IRMOVQ, RMMOVQ, MRMOVQ, JM } : E.valC;
+    E.icode in { CALL, PUSHQ } : NEG_8;
+    E.icode in { RET, POPQ } : 8;
+    1 : 0; // Other instructions don't need ALU
+];
+
+// Select input B to ALU
+u64 aluB = [
+    E.icode in { RMMOVQ, MRMOVQ, OPQ, CALL, PUSHQ, RET, POPQ, JM } : E.valB;
+    E.icode in { CMOVX, IRMOVQ } : 0;
+    1 : 0; // Other instructions don't need ALU
+];
+
+// Set the ALU function
+u8 alufun = [
+    E.icode == OPQ : E.ifun;
+    1 : ADD;
+];
+
+@set_input(alu, {
+    a: aluA,
+    b: aluB,
+    fun: alufun,
+});
+
+// Division by zero and a function code without condition are invalid
+// instructions
+Stat e_stat = [
+    alu.error : Ins;
+    E.icode in { CMOVX, JX } && cond.error : Ins;
+    1 : E.stat;
+];
+
+// Should the condition codes be updated?
+bool set_cc = E.icode == OPQ &&
+    // State changes only during normal operation
+    !alu.error &&
+    !(m_stat in { Adr, Ins, Hlt }) && !(W.stat in { Adr, Ins, Hlt });
+
+u64 e_valE = alu.e;
+
+@set_input(reg_cc, {
+    a: aluA,
+    b: aluB,
+    e: e_valE,
+    opfun: alufun,
+    set_cc: set_cc,
+});
+
+ConditionCode cc = reg_cc.cc;
+u8 e_ifun = E.ifun;
+
+@set_input(cond, {
+    cc: cc,
+    condfun: e_ifun,
+});
+
+bool e_cnd = cond.cnd;
+
+// Generate valA in execute stage
+u64 e_valA = E.valA;    // Pass valA through stage
+
+// Set dstE to RNONE in event of not-taken conditional move
+u8 e_dstE = [
+    E.icode == CMOVX && !e_cnd : RNONE;
+    1 : E.dstE;
+];
+
+u8 e_dstM = E.dstM;
+u8 e_icode = E.icode;
+u64 e_epc = E.epc;
+
+@set_stage(m, {
+    stat: e_stat,
+    dstM: e_dstM,
+    icode: e_icode,
+    ifun: e_ifun,
+    dstE: e_dstE,
+    cnd: e_cnd,
+    valE: e_valE,
+    valA: e_valA,
+    epc: e_epc,
+});
+
+:===============================: Memory Stage :===============================:
+
+// Select memory address
+u64 mem_addr = [
+    M.icode in { RMMOVQ, PUSHQ, CALL, MRMOVQ, JM } : M.valE;
+    M.icode in { POPQ, RET } : M.valA;
+    // Other instructions don't need address
+];
+
+// Set read control signal
+bool mem_read = M.icode in { MRMOVQ, POPQ, RET, JM };
+
+// Set write control signal
+bool mem_write = M.icode in { RMMOVQ, PUSHQ, CALL };
+
+u64 mem_data = M.valA;
+
+@set_input(dmem, {
+    read: mem_read,
+    write: mem_write,
+    addr: mem_addr,
+    datain: mem_data,
+});
+
+// Update the status. The address of a bubble may be left from the faulting
+// instruction, so the error is checked only for memory accesses.
+Stat m_stat = [
+    (mem_read || mem_write) && dmem.error : Adr;
+    1 : M.stat;
+];
+
+u8 m_icode = M.icode;
+u8 m_ifun = M.ifun;
+
+// IRET reads the saved EPC instead of the memory
+u64 m_valM = [
+    M.icode == EXC : exc.saved_epc;
+    1 : dmem.dataout;
+];
+u64 m_valE = M.valE;
+u8 m_dstE = M.dstE;
+u8 m_dstM = M.dstM;
+u64 m_epc = M.epc;
+
+@set_stage(w, {
+    stat: m_stat,
+    icode: m_icode,
+    ifun: m_ifun,
+    valE: m_valE,
+    valM: m_valM,
+    dstE: m_dstE,
+    dstM: m_dstM,
+    epc: m_epc,
+});
+
+:=============================: Write Back Stage :=============================:
+
+// Does the instruction in write back stage raise an exception?
+bool w_trap = W.icode == EXC && W.ifun == TRAP && W.stat == Aok;
+bool w_raise = W.stat in { Adr, Ins } || w_trap;
+
+u64 w_cause = [
+    W.stat == Adr : CAUSE_ADR;
+    W.stat == Ins : CAUSE_INS;
+    1 : CAUSE_TRAP;
+];
+
+u64 w_epc = W.epc;
+
+@set_input(exc, {
+    raise: w_raise,
+    cause: w_cause,
+    epc: w_epc,
+});
+
+// The faulting instruction does not write back
+u8 w_dstE = [
+    w_raise : RNONE;
+    1 : W.dstE;
+];
+
+// Set E port value
+u64 w_valE = W.valE;
+
+u8 w_dstM = [
+    w_raise : RNONE;
+    1 : W.dstM;
+];
+
+// Set M port value
+u64 w_valM = W.valM;
+
+@set_input(reg_file, {
+    srcA: d_srcA,
+    srcB: d_srcB,
+    dstE: w_dstE,
+    dstM: w_dstM,
+    valM: w_valM,
+    valE: w_valE,
+});
+
+bool exc_taken = exc.taken;
+
+// Update processor status (used for outside monitoring)
+Stat prog_stat = [
+    exc_taken : Aok;
+    // trap without a handler
+    w_trap : Ins;
+    W.stat == Bub : Aok;
+    1 : W.stat;
+];
+
+bool prog_term = [
+    prog_stat in { Aok, Bub } : false;
+    1 : true
+];
+
+:========================: Pipeline Register Control :=========================:
+
+// Hazard of ret, jm, trap or iret passing through pipeline
+bool ret_hazard = D.icode in { RET, JM, EXC } || E.icode in { RET, JM, EXC } ||
+    M.icode in { RET, JM, EXC };
+
+// Conditions for a load/use hazard
+bool load_use = E.icode in { MRMOVQ, POPQ } && E.dstM in { d_srcA, d_srcB };
+
+// When an exception is taken, the handler is fetched in this cycle, and the
+// instructions in the decode and execute stages are cancelled.
+bool f_bubble = false;
+bool f_stall = !exc_taken && (load_use || ret_hazard);
+
+@set_stage(f, {
+    bubble: f_bubble,
+    stall: f_stall,
+});
+
+bool d_stall = !exc_taken && load_use;
+
+bool d_bubble = !exc_taken && (
+    // Mispredicted branch
+    (E.icode == JX && !e_cnd) ||
+    // Stalling at fetch while ret, jm, trap or iret passes through pipeline
+    // but not condition for a load/use hazard
+    !load_use && ret_hazard);
+
+@set_stage(d, {
+    stall: d_stall,
+    bubble: d_bubble,
+});
+
+bool e_stall = false;
+bool e_bubble = exc_taken ||
+    // Mispredicted branch
+    (E.icode == JX && !e_cnd) ||
+    load_use;
+
+@set_stage(e, {
+    stall: e_stall,
+    bubble: e_bubble,
+});
+
+// Start injecting bubbles as soon as exception passes through memory stage
+bool m_stall = false;
+bool m_bubble = exc_taken ||
+    m_stat in { Adr, Ins, Hlt } || W.stat in { Adr, Ins, Hlt };
+
+@set_stage(m, {
+    stall: m_stall,
+    bubble: m_bubble,
+});
+
+bool w_stall = !exc_taken && W.stat in { Adr, Ins, Hlt };
+bool w_bubble = false;
+
+@set_stage(w, {
+    stall: w_stall,
+    bubble: w_bubble,
+});
+}
+
+mod nofmt {
+    use super::*;
+    use crate::{
+        framework::PipeSim,
+        utils::{format_ctrl, format_icode},
+    };
+    impl PipeSim<Arch> {
+        // print state at the beginning of a cycle
+        pub fn print_state(&self) {
+            // For stage registers, outputs contains information for the following cycle
+
+            #[allow(non_snake_case)]
+            let PipeRegs {
+                f: _,
+                d: D,
+                e: E,
+                m: M,
+                w: W,
+            } = &self.cur_state;
+            let PipeRegs { f, d, e, m, w } = &self.nex_state;
+
+            println!(
+                r#"Stat    F {fstat}    D {dstat}    E {estat}    M {mstat}    W {wstat}
+icode   f {ficode} D {dicode} E {eicode} M {micode} W {wicode}
+Control F {fctrl:6} D {dctrl:6} E {ectrl:6} M {mctrl:6} W {wctrl:6}"#,
+                fstat = Aok,
+                dstat = D.stat,
+                estat = E.stat,
+                mstat = M.stat,
+                wstat = W.stat,
+                // stage control at the end of last cycle
+                // e.g. dctrl is computed in fetch stage. if dctrl is bubble,
+                // then in the next cycle, D.icode will be NOP.
+                // e. Controls are applied between cycles.
+                fctrl = format_ctrl(f.bubble, f.stall),
+                dctrl = format_ctrl(d.bubble, d.stall),
+                ectrl = format_ctrl(e.bubble, e.stall),
+                mctrl = format_ctrl(m.bubble, m.stall),
+                wctrl = format_ctrl(w.bubble, w.stall),
+                // ficode is actually computed value
+                ficode = format_icode(d.icode),
+                dicode = format_icode(D.icode),
+                eicode = format_icode(E.icode),
+                micode = format_icode(M.icode),
+                wicode = format_icode(W.icode),
+            );
+        }
+    }
+}
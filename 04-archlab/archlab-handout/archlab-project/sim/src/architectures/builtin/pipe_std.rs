@@ -47,6 +47,16 @@ sim_macro::hcl! {
 // be terminated.
 #![termination = prog_term]
 
+// Optionally specify a `Stat` intermediate signal of the program status. The
+// grader checks that the program terminates with the same status as the ISA.
+#![status = prog_stat]
+
+// Declare the ISA extensions implemented by this architecture, which are
+// checked by the grader in addition to the standard instructions. `Arith`
+// (mulq, divq, ...) is computed by the ALU as other OPQ functions, and a
+// division by zero is an invalid instruction reported by `alu.error`.
+#![extensions(Arith)]
+
 // This attribute defines the identifiers for pipeline registers. For "F => f", the
 // identifier `f` is the short name in [`crate::define_stages`], and `F` can be
 // arbitrarily chosen.
@@ -66,8 +76,8 @@ use Stat::*;
 u64 f_pc = [
     // Mispredicted branch. Fetch at incremented PC
     M.icode == JX && !M.cnd : M.valA;
-    // Completion of RET instruction
-    W.icode == RET : W.valM;
+    // Completion of RET or JM instruction
+    W.icode in { RET, JM } : W.valM;
     // Default: Use predicted value of PC (default to 0)
      1 : F.pred_pc;
 ];
@@ -91,7 +101,7 @@ u8 f_ifun = [
 
 // Is instruction valid?
 bool instr_valid = f_icode in { NOP, HALT, CMOVX, IRMOVQ, RMMOVQ,
-    MRMOVQ, OPQ, JX, CALL, RET, PUSHQ, POPQ };
+    MRMOVQ, OPQ, JX, CALL, RET, PUSHQ, POPQ, JM };
 
 // Determine status code for fetched instruction
 Stat f_stat = [
@@ -103,10 +113,10 @@ Stat f_stat = [
 
 // Does fetched instruction require a regid byte?
 bool need_regids
-    = f_icode in { CMOVX, OPQ, PUSHQ, POPQ, IRMOVQ, RMMOVQ, MRMOVQ };
+    = f_icode in { CMOVX, OPQ, PUSHQ, POPQ, IRMOVQ, RMMOVQ, MRMOVQ, JM };
 
 // Does fetched instruction require a constant word?
-bool need_valC = f_icode in { IRMOVQ, RMMOVQ, MRMOVQ, JX, CALL };
+bool need_valC = f_icode in { IRMOVQ, RMMOVQ, MRMOVQ, JX, CALL, JM };
 
 @set_input(pc_inc, {
     need_valC: need_valC,
@@ -158,7 +168,7 @@ u8 d_srcA = [
 
 // What register should be used as the B source?
 u8 d_srcB = [
-    D.icode in { OPQ, RMMOVQ, MRMOVQ } : D.rB;
+    D.icode in { OPQ, RMMOVQ, MRMOVQ, JM } : D.rB;
     D.icode in { PUSHQ, POPQ, CALL, RET } : RSP;
     1 : RNONE; // Don't need register
 ];
@@ -223,7 +233,7 @@ Stat d_stat = D.stat;
 // Select input A to ALU
 u64 aluA = [
     E.icode in { CMOVX, OPQ } : E.valA;
-    E.icode in { IRMOVQ, RMMOVQ, MRMOVQ } : E.valC;
+    E.icode in { IRMOVQ, RMMOVQ, MRMOVQ, JM } : E.valC;
     E.icode in { CALL, PUSHQ } : NEG_8;
     E.icode in { RET, POPQ } : 8;
     1 : 0; // Other instructions don't need ALU
@@ -231,7 +241,7 @@ u64 aluA = [
 
 // Select input B to ALU
 u64 aluB = [
-    E.icode in { RMMOVQ, MRMOVQ, OPQ, CALL, PUSHQ, RET, POPQ } : E.valB;
+    E.icode in { RMMOVQ, MRMOVQ, OPQ, CALL, PUSHQ, RET, POPQ, JM } : E.valB;
     E.icode in { CMOVX, IRMOVQ } : 0;
     1 : 0; // Other instructions don't need ALU
 ];
@@ -248,9 +258,18 @@ u8 alufun = [
     fun: alufun,
 });
 
+// Division by zero and a function code without condition are invalid
+// instructions
+Stat e_stat = [
+    alu.error : Ins;
+    E.icode in { CMOVX, JX } && cond.error : Ins;
+    1 : E.stat;
+];
+
 // Should the condition codes be updated?
 bool set_cc = E.icode == OPQ &&
     // State changes only during normal operation
+    !alu.error &&
     !(m_stat in { Adr, Ins, Hlt }) && !(W.stat in { Adr, Ins, Hlt });
 
 u64 e_valE = alu.e;
@@ -277,15 +296,16 @@ bool e_cnd = cond.cnd;
 // Generate valA in execute stage
 u64 e_valA = E.valA;    // Pass valA through stage
 
-// Set dstE to RNONE in event of not-taken conditional move
+// Set dstE to RNONE in event of not-taken conditional move or invalid
+// operation
 u8 e_dstE = [
     E.icode == CMOVX && !e_cnd : RNONE;
+    e_stat == Ins : RNONE;
     1 : E.dstE;
 ];
 
 u8 e_dstM = E.dstM;
 u8 e_icode = E.icode;
-Stat e_stat = E.stat;
 
 @set_stage(m, {
     stat: e_stat,
@@ -301,13 +321,13 @@ Stat e_stat = E.stat;
 
 // Select memory address
 u64 mem_addr = [
-    M.icode in { RMMOVQ, PUSHQ, CALL, MRMOVQ } : M.valE;
+    M.icode in { RMMOVQ, PUSHQ, CALL, MRMOVQ, JM } : M.valE;
     M.icode in { POPQ, RET } : M.valA;
     // Other instructions don't need address
 ];
 
 // Set read control signal
-bool mem_read = M.icode in { MRMOVQ, POPQ, RET };
+bool mem_read = M.icode in { MRMOVQ, POPQ, RET, JM };
 
 // Set write control signal
 bool mem_write = M.icode in { RMMOVQ, PUSHQ, CALL };
@@ -385,8 +405,8 @@ bool f_bubble = false;
 bool f_stall =
     // Conditions for a load/use hazard
     E.icode in { MRMOVQ, POPQ } && E.dstM in { d_srcA, d_srcB } ||
-    // Stalling at fetch while ret passes through pipeline
-    RET in {D.icode, E.icode, M.icode};
+    // Stalling at fetch while ret or jm passes through pipeline
+    D.icode in { RET, JM } || E.icode in { RET, JM } || M.icode in { RET, JM };
 
 @set_stage(f, {
     bubble: f_bubble,
@@ -402,10 +422,10 @@ bool d_stall =
 bool d_bubble =
     // Mispredicted branch
     (E.icode == JX && !e_cnd) ||
-    // Stalling at fetch while ret passes through pipeline
+    // Stalling at fetch while ret or jm passes through pipeline
     // but not condition for a load/use hazard
     !(E.icode in { MRMOVQ, POPQ } && E.dstM in { d_srcA, d_srcB }) &&
-      RET in {D.icode, E.icode, M.icode};
+      (D.icode in { RET, JM } || E.icode in { RET, JM } || M.icode in { RET, JM });
 
 @set_stage(d, {
     stall: d_stall,
@@ -14,6 +14,8 @@ sim_macro::hcl! {
 #![hardware = crate::architectures::hardware_seq]
 #![program_counter = pc]
 #![termination = prog_term]
+#![status = stat]
+#![extensions(Arith)]
 #![stage_alias(S => s)]
 
 use Stat::*;
@@ -28,6 +30,8 @@ u64 pc = [
     S.icode == JX && S.cnd : S.valC;
     // Completion of RET instruction.  Use value from stack
     S.icode == RET : S.valM;
+    // Memory-indirect jump.  Use value from memory
+    S.icode == JM : S.valM;
     // Default: Use incremented PC
     true : S.valP;
 ];
@@ -50,14 +54,14 @@ u8 ifun = [
 
 bool instr_valid = icode in // CMOVX is the same as RRMOVQ
     { NOP, HALT, CMOVX, IRMOVQ, RMMOVQ, MRMOVQ,
-    OPQ, JX, CALL, RET, PUSHQ, POPQ };
+    OPQ, JX, CALL, RET, PUSHQ, POPQ, JM };
 
 // Does fetched instruction require a regid byte?
 bool need_regids =
-    icode in { CMOVX, OPQ, PUSHQ, POPQ, IRMOVQ, RMMOVQ, MRMOVQ };
+    icode in { CMOVX, OPQ, PUSHQ, POPQ, IRMOVQ, RMMOVQ, MRMOVQ, JM };
 
 // Does fetched instruction require a constant word?
-bool need_valC = icode in { IRMOVQ, RMMOVQ, MRMOVQ, JX, CALL };
+bool need_valC = icode in { IRMOVQ, RMMOVQ, MRMOVQ, JX, CALL, JM };
 
 @set_input(pc_inc, {
     need_valC: need_valC,
@@ -86,7 +90,7 @@ u8 srcA = [
 
 // What register should be used as the B source?
 u8 srcB = [
-    icode in { OPQ, RMMOVQ, MRMOVQ } : ialign.rB;
+    icode in { OPQ, RMMOVQ, MRMOVQ, JM } : ialign.rB;
     icode in { PUSHQ, POPQ, CALL, RET } : RSP;
     true : RNONE; // Don't need register
 ];
@@ -98,6 +102,7 @@ u8 srcB = [
 
 // What register should be used as the E destination?
 u8 dstE = [
+    alu.error : RNONE; // Invalid operation, e.g. division by zero
     icode in { CMOVX } && cnd : ialign.rB;
     icode in { IRMOVQ, OPQ} : ialign.rB;
     icode in { PUSHQ, POPQ, CALL, RET } : RSP;
@@ -115,7 +120,7 @@ u8 dstM = [
 // Select input A to ALU
 u64 aluA = [
     icode in { CMOVX, OPQ } : reg_read.valA;
-    icode in { IRMOVQ, RMMOVQ, MRMOVQ } : ialign.valC;
+    icode in { IRMOVQ, RMMOVQ, MRMOVQ, JM } : ialign.valC;
     icode in { CALL, PUSHQ } : NEG_8;
     icode in { RET, POPQ } : 8;
     // Other instructions don't need ALU
@@ -124,7 +129,7 @@ u64 aluA = [
 // Select input B to ALU
 u64 aluB = [
     icode in { RMMOVQ, MRMOVQ, OPQ, CALL,
-              PUSHQ, RET, POPQ } : reg_read.valB;
+              PUSHQ, RET, POPQ, JM } : reg_read.valB;
     icode in { CMOVX, IRMOVQ } : 0;
     // Other instructions don't need ALU
 ];
@@ -141,8 +146,8 @@ u8 alufun = [
     fun: alufun,
 });
 
-// Should the condition codes be updated?
-bool set_cc = icode in { OPQ };
+// Should the condition codes be updated? Not for an invalid operation
+bool set_cc = icode in { OPQ } && !alu.error;
 
 u64 valE = alu.e;
 
@@ -166,14 +171,14 @@ bool cnd = cond.cnd;
 :===============================: Memory Stage :===============================:
 
 // Set read control signal
-bool mem_read = icode in { MRMOVQ, POPQ, RET };
+bool mem_read = icode in { MRMOVQ, POPQ, RET, JM };
 
 // Set write control signal
 bool mem_write = icode in { RMMOVQ, PUSHQ, CALL };
 
 // Select memory address
 u64 mem_addr = [
-    icode in { RMMOVQ, PUSHQ, CALL, MRMOVQ } : valE;
+    icode in { RMMOVQ, PUSHQ, CALL, MRMOVQ, JM } : valE;
     icode in { POPQ, RET } : reg_read.valA;
     // Other instructions don't need address
 ];
@@ -206,7 +211,8 @@ u64 valM = dmem.dataout;
 // Determine instruction status
 Stat stat = [
     imem.error || dmem.error : Adr;
-    !instr_valid : Ins;
+    !instr_valid || alu.error : Ins;
+    icode in { CMOVX, JX } && cond.error : Ins; // function code without condition
     icode == HALT : Hlt;
     true : Aok;
 ];
@@ -27,6 +27,16 @@ sim_macro::hcl! {
 // be terminated.
 #![termination = prog_term]
 
+// Optionally specify a `Stat` intermediate signal of the program status. The
+// grader checks that the program terminates with the same status as the ISA.
+#![status = stat]
+
+// Declare the ISA extensions implemented by this architecture, which are
+// checked by the grader in addition to the standard instructions. `Arith`
+// (mulq, divq, ...) is computed by the ALU as other OPQ functions, and a
+// division by zero is an invalid instruction reported by `alu.error`.
+#![extensions(Arith)]
+
 // This attribute defines the identifiers for pipeline registers. For "F => f", the
 // identifier `f` is the short name in [`crate::define_stages`], and `F` can be
 // arbitrarily chosen.
@@ -62,14 +72,14 @@ u8 ifun = [
 
 bool instr_valid = icode in // CMOVX is the same as RRMOVQ
     { NOP, HALT, CMOVX, IRMOVQ, RMMOVQ, MRMOVQ,
-    OPQ, JX, CALL, RET, PUSHQ, POPQ };
+    OPQ, JX, CALL, RET, PUSHQ, POPQ, JM };
 
 // Does fetched instruction require a regid byte?
 bool need_regids =
-    icode in { CMOVX, OPQ, PUSHQ, POPQ, IRMOVQ, RMMOVQ, MRMOVQ };
+    icode in { CMOVX, OPQ, PUSHQ, POPQ, IRMOVQ, RMMOVQ, MRMOVQ, JM };
 
 // Does fetched instruction require a constant word?
-bool need_valC = icode in { IRMOVQ, RMMOVQ, MRMOVQ, JX, CALL };
+bool need_valC = icode in { IRMOVQ, RMMOVQ, MRMOVQ, JX, CALL, JM };
 
 @set_input(pc_inc, {
     need_valC: need_valC,
@@ -97,7 +107,7 @@ u8 srcA = [
 
 // What register should be used as the B source?
 u8 srcB = [
-    icode in { OPQ, RMMOVQ, MRMOVQ } : ialign.rB;
+    icode in { OPQ, RMMOVQ, MRMOVQ, JM } : ialign.rB;
     icode in { PUSHQ, POPQ, CALL, RET } : RSP;
     true : RNONE; // Don't need register
 ];
@@ -109,6 +119,7 @@ u8 srcB = [
 
 // What register should be used as the E destination?
 u8 dstE = [
+    alu.error : RNONE; // Invalid operation, e.g. division by zero
     icode in { CMOVX } && cnd : ialign.rB;
     icode in { IRMOVQ, OPQ} : ialign.rB;
     icode in { PUSHQ, POPQ, CALL, RET } : RSP;
@@ -126,7 +137,7 @@ u8 dstM = [
 // Select input A to ALU
 u64 aluA = [
     icode in { CMOVX, OPQ } : reg_read.valA;
-    icode in { IRMOVQ, RMMOVQ, MRMOVQ } : ialign.valC;
+    icode in { IRMOVQ, RMMOVQ, MRMOVQ, JM } : ialign.valC;
     icode in { CALL, PUSHQ } : NEG_8;
     icode in { RET, POPQ } : 8;
     // Other instructions don't need ALU
@@ -135,7 +146,7 @@ u64 aluA = [
 // Select input B to ALU
 u64 aluB = [
     icode in { RMMOVQ, MRMOVQ, OPQ, CALL,
-              PUSHQ, RET, POPQ } : reg_read.valB;
+              PUSHQ, RET, POPQ, JM } : reg_read.valB;
     icode in { CMOVX, IRMOVQ } : 0;
     // Other instructions don't need ALU
 ];
@@ -152,8 +163,8 @@ u8 alufun = [
     fun: alufun,
 });
 
-// Should the condition codes be updated?
-bool set_cc = icode in { OPQ };
+// Should the condition codes be updated? Not for an invalid operation
+bool set_cc = icode in { OPQ } && !alu.error;
 
 u64 valE = alu.e;
 
@@ -177,14 +188,14 @@ bool cnd = cond.cnd;
 :===============================: Memory Stage :===============================:
 
 // Set read control signal
-bool mem_read = icode in { MRMOVQ, POPQ, RET };
+bool mem_read = icode in { MRMOVQ, POPQ, RET, JM };
 
 // Set write control signal
 bool mem_write = icode in { RMMOVQ, PUSHQ, CALL };
 
 // Select memory address
 u64 mem_addr = [
-    icode in { RMMOVQ, PUSHQ, CALL, MRMOVQ } : valE;
+    icode in { RMMOVQ, PUSHQ, CALL, MRMOVQ, JM } : valE;
     icode in { POPQ, RET } : reg_read.valA;
     // Other instructions don't need address
 ];
@@ -217,7 +228,8 @@ u64 valM = dmem.dataout;
 // Determine instruction status
 Stat stat = [
     imem.error || dmem.error : Adr;
-    !instr_valid : Ins;
+    !instr_valid || alu.error : Ins;
+    icode in { CMOVX, JX } && cond.error : Ins; // function code without condition
     icode == HALT : Hlt;
     true : Aok;
 ];
@@ -235,6 +247,8 @@ u64 new_pc = [
     icode == JX && cnd : ialign.valC;
     // Completion of RET instruction.  Use value from stack
     icode == RET : valM;
+    // Memory-indirect jump.  Use value from memory
+    icode == JM : valM;
     // Default: Use incremented PC
     true : valP;
 ];
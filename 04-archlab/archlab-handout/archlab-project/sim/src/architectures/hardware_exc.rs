@@ -0,0 +1,277 @@
+//! The units of `hardware_pipe.rs` with an exception unit, which implements
+//! the exception extension ([`crate::isa::Extension::Exception`]).
+
+use crate::{
+    define_units,
+    framework::{HardwareUnits, MemData, UnitsSnapshot},
+    isa::{
+        inst_code, inst_len,
+        reg_code::{self, *},
+        RegFile,
+    },
+    utils::get_u64,
+};
+
+/// A constant that represents the value -8.
+pub const NEG_8: u64 = -8i64 as u64;
+pub use crate::{
+    isa::{exc_cause::*, exc_fn::*, ConditionCode, Stat, CC_INIT},
+    lab::*,
+};
+
+define_units! {
+    InstructionMemory imem { // with split
+        .input(
+            /// The input pc is used to read the instruction from memory.
+            pc: u64
+        )
+        .output(
+            /// This signal is set to true if the address is invalid.
+            /// (i.e. the instruction is out of the memory range)
+            error: bool, icode: u8, ifun: u8,
+            /// The 9 bytes following the first byte, where the bytes out of
+            /// the memory are 0.
+            align: [u8; 9]
+        )
+        binary: MemData
+    } {
+        let binary: &[u8] = &binary.read();
+        // an invalid icode is fetched as a single byte
+        let len = binary.get(pc as usize).map(|b| inst_len(b >> 4).unwrap_or(1));
+        if len.is_none_or(|len| pc as usize + len > binary.len()) {
+            *error = true;
+        } else {
+            // the fetch continues after an exception
+            *error = false;
+            let pc = pc as usize;
+            let icode_ifun = binary[pc];
+            *icode = icode_ifun >> 4;
+            *ifun = icode_ifun & 0xf;
+            let mut window = [0; 10];
+            let end = binary.len().min(pc + 10);
+            window[..end - pc].copy_from_slice(&binary[pc..end]);
+            *align = window[1..].try_into().unwrap();
+        }
+
+        if *icode == inst_code::CALL {
+            tracing::info!("CALL instruction fetched");
+        }
+    }
+
+    /// If `need_regids` is set to true, this unit will extract the register
+    /// IDs from the first byte, and valC from the rest of the bytes.
+    /// Otherwise the valC is extracted from the first 8 bytes and the last byte
+    /// is ignored.
+    Align ialign {
+        .input(need_regids: bool, align: [u8; 9])
+        .output(rA: u8, rB: u8,
+            /// Constant value extracted from the instruction. If the instruction
+            /// does not need a constant value, this signal is meaningless.
+            valC: u64)
+    } {
+        let ra_rb = align[0];
+        let rest = if need_regids {
+            *rA = ra_rb >> 4;
+            *rB = ra_rb & 0xf;
+            &align[1..9]
+        } else {
+            *rA = RNONE;
+            *rB = RNONE;
+            &align[0..8]
+        };
+        *valC = get_u64(rest)
+    }
+
+    PCIncrement pc_inc {
+        .input(need_valC: bool, need_regids: bool, old_pc: u64)
+        .output(
+            /// The new PC value computed based on need_valC and need_regids.
+            new_pc: u64
+        )
+    } {
+        let mut x = old_pc + 1;
+        if need_regids { x += 1; }
+        if need_valC { x += 8; }
+        *new_pc = x;
+    }
+
+    /// The register file perform two tasks:
+    ///
+    /// 1. Write the value of the destination register `dstE` and `dstM`.
+    /// 2. Read the values of the source registers `srcA` and `srcB`.
+    ///
+    /// If the register is `RNONE`, the corresponding operation is not performed.
+    ///
+    /// The order of first write then read is important as it prevents the
+    /// structural hazard.
+    RegisterFile reg_file {
+        .input(srcA: u8, srcB: u8, dstE: u8, dstM: u8, valE: u64, valM: u64)
+        .output(valA: u64, valB: u64)
+        state: RegFile
+    } {
+        if dstE != RNONE {
+            tracing::info!("write back fron e: dstE = {}, valE = {:#x}", reg_code::name_of(dstE), valE);
+            state[dstE as usize] = valE;
+        }
+        if dstM != RNONE {
+            tracing::info!("write back fron m: dstM = {}, valM = {:#x}", reg_code::name_of(dstM), valM);
+            state[dstM as usize] = valM;
+        }
+
+        // if RNONE, set to 0 for better debugging
+        *valA = if srcA != RNONE { state[srcA as usize] } else { 0 };
+        *valB = if srcB != RNONE { state[srcB as usize] } else { 0 };
+        if dstE != RNONE {
+            tracing::info!("write back fron e: dstE = {}, valE = {:#x}", reg_code::name_of(dstE), valE);
+            state[dstE as usize] = valE;
+        }
+        if dstM != RNONE {
+            tracing::info!("write back fron m: dstM = {}, valM = {:#x}", reg_code::name_of(dstM), valM);
+            state[dstM as usize] = valM;
+        }
+    }
+
+    /// Compute `b op a`, including the operations of the arithmetic
+    /// extension. `error` is set for an invalid function or a division by
+    /// zero, which is an invalid instruction, and then the output is 0.
+    ArithmetcLogicUnit alu {
+        .input(a: u64, b: u64, fun: u8)
+        .output(e: u64, error: bool)
+    } {
+        let result = crate::isa::arithmetic_compute(a, b, fun);
+        *e = result.unwrap_or(0);
+        *error = result.is_none();
+    }
+
+    /// Given the input and output of the ALU, this unit calculate the
+    /// condition codes and update the cc register if required.
+    RegisterCC reg_cc {
+        .input(set_cc: bool, a: u64, b: u64, e: u64, opfun: u8)
+        .output(cc: ConditionCode)
+        inner_cc: ConditionCode
+    } {
+        if set_cc {
+            inner_cc.set(a, b, e, opfun);
+            tracing::info!("CC update: a = {:#x}, b = {:#x}, e = {:#x}, cc: {:?}, opfun: {}", a, b, e,
+                inner_cc, crate::isa::op_code::name_of(opfun));
+        }
+        *cc = *inner_cc;
+    }
+
+    /// Instructions like CMOVX or JX needs to check the condition code based
+    /// on the function code, which is simulated by this unit. `error` is set
+    /// for a function code without condition, and then `cnd` is false.
+    InstructionCondition cond {
+        .input(condfun: u8, cc: ConditionCode)
+        .output(cnd: bool, error: bool)
+    } {
+        *cnd = cc.test(condfun);
+        *error = condfun > crate::isa::cond_fn::G;
+    }
+
+    DataMemory dmem {
+        .input(addr: u64, datain: u64, read: bool, write: bool)
+        .output(
+            /// If `read == true`, this signal is the data read from memory.
+            /// Otherwise this signal is set to 0.
+            dataout: u64,
+            /// Indicate if the address is invalid.
+            error: bool
+        )
+        binary: MemData
+    } {
+        if !binary.is_valid(addr) {
+            *dataout = 0;
+            *error = true;
+            return
+        }
+        *error = false;
+        if write {
+            tracing::info!("write memory: addr = {:#x}, datain = {:#x}", addr, datain);
+            binary.store_u64(addr, datain);
+            *dataout = 0;
+        } else if read {
+            *dataout = binary.load_u64(addr);
+        }
+    }
+
+    /// If `raise` is set, enter the handler of the exception and save `epc`
+    /// and `cause` (see [`crate::mmio::ExceptionRegs`]). The exception is not
+    /// taken if the vector table is not set.
+    ExceptionUnit exc {
+        .input(raise: bool, cause: u64, epc: u64)
+        .output(
+            /// Whether the handler is entered.
+            taken: bool,
+            /// Address of the handler if the exception is taken.
+            handler: u64,
+            /// The saved EPC, which is the return address of `iret`.
+            saved_epc: u64
+        )
+        binary: MemData
+    } {
+        *taken = false;
+        *handler = 0;
+        if raise {
+            if let Some(addr) = binary.enter_handler(cause, epc) {
+                tracing::info!("enter handler: cause = {}, epc = {:#x}, handler = {:#x}", cause, epc, addr);
+                *taken = true;
+                *handler = addr;
+            }
+        }
+        *saved_epc = binary.io().exc.as_ref().map_or(0, |exc| exc.epc);
+    }
+}
+
+impl std::fmt::Display for Units {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let regs = crate::utils::format_reg_file(self.reg_file.state);
+        f.write_fmt(format_args!("{regs}\n{cc}", cc = self.reg_cc.inner_cc))
+    }
+}
+
+impl HardwareUnits for Units {
+    /// Init CPU harewre with given memory, and map the exception registers.
+    fn init(memory: MemData) -> Self {
+        memory.enable_exceptions();
+        Self {
+            imem: InstructionMemory {
+                binary: memory.clone(),
+            },
+            ialign: Align {},
+            pc_inc: PCIncrement {},
+            reg_file: RegisterFile { state: [0; 16] },
+            alu: ArithmetcLogicUnit {},
+            reg_cc: RegisterCC {
+                inner_cc: ConditionCode::default(),
+            },
+            cond: InstructionCondition {},
+            dmem: DataMemory {
+                binary: memory.clone(),
+            },
+            exc: ExceptionUnit { binary: memory },
+        }
+    }
+
+    fn register_file(&self) -> RegFile {
+        self.reg_file.state
+    }
+
+    fn io_devices(&self) -> crate::mmio::IoDevices {
+        self.dmem.binary.io().clone()
+    }
+
+    fn snapshot(&self) -> UnitsSnapshot {
+        UnitsSnapshot {
+            regs: self.reg_file.state,
+            cc: self.reg_cc.inner_cc,
+            mem: self.dmem.binary.snapshot(),
+        }
+    }
+
+    fn restore(&mut self, snapshot: &UnitsSnapshot) {
+        self.reg_file.state = snapshot.regs;
+        self.reg_cc.inner_cc = snapshot.cc;
+        self.dmem.binary.restore(&snapshot.mem);
+    }
+}
@@ -1,16 +1,16 @@
 //! This module defines hardware units used in the seq architecture.
 
-use std::{cell::RefCell, rc::Rc};
+use std::sync::{Arc, Mutex};
 
 use crate::{
     define_units,
-    framework::{HardwareUnits, MemData, MEM_SIZE},
+    framework::{HardwareUnits, MemData, UnitsSnapshot},
     isa::{
-        inst_code,
+        inst_code, inst_len,
         reg_code::{self, *},
         RegFile,
     },
-    utils::{get_u64, put_u64},
+    utils::get_u64,
 };
 
 /// A constant that represents the value -8.
@@ -28,20 +28,28 @@ define_units! {
         )
         .output(
             /// This signal is set to true if the address is invalid.
-            /// (i.e. the address is out of the memory range)
-            error: bool, icode: u8, ifun: u8, align: [u8; 9]
+            /// (i.e. the instruction is out of the memory range)
+            error: bool, icode: u8, ifun: u8,
+            /// The 9 bytes following the first byte, where the bytes out of
+            /// the memory are 0.
+            align: [u8; 9]
         )
         binary: MemData
     } {
-        let binary: &[u8; MEM_SIZE] = &binary.read();
-        if pc > MEM_SIZE as u64 - 10 {
+        let binary: &[u8] = &binary.read();
+        // an invalid icode is fetched as a single byte
+        let len = binary.get(pc as usize).map(|b| inst_len(b >> 4).unwrap_or(1));
+        if len.is_none_or(|len| pc as usize + len > binary.len()) {
             *error = true;
         } else {
             let pc = pc as usize;
             let icode_ifun = binary[pc];
             *icode = icode_ifun >> 4;
             *ifun = icode_ifun & 0xf;
-            *align = binary[pc+1..pc+10].try_into().unwrap();
+            let mut window = [0; 10];
+            let end = binary.len().min(pc + 10);
+            window[..end - pc].copy_from_slice(&binary[pc..end]);
+            *align = window[1..].try_into().unwrap();
         }
 
         if *icode == inst_code::CALL {
@@ -89,10 +97,10 @@ define_units! {
     RegisterFileRead reg_read {
         .input(srcA: u8, srcB: u8)
         .output(valA: u64, valB: u64)
-        state: Rc<RefCell<RegFile>>
+        state: Arc<Mutex<RegFile>>
     } {
         // if RNONE, set to 0 for better debugging
-        let state  = &mut state.borrow_mut();
+        let state = &mut state.lock().unwrap();
         *valA = if srcA != RNONE { state[srcA as usize] } else { 0 };
         *valB = if srcB != RNONE { state[srcB as usize] } else { 0 };
     }
@@ -100,9 +108,9 @@ define_units! {
     RegisterFileWrite reg_write {
         .input(dstE: u8, dstM: u8, valE: u64, valM: u64)
         .output()
-        state: Rc<RefCell<RegFile>>
+        state: Arc<Mutex<RegFile>>
     } {
-        let state  = &mut state.borrow_mut();
+        let state = &mut state.lock().unwrap();
         if dstE != RNONE {
             tracing::info!("write back fron e: dstE = {}, valE = {:#x}", reg_code::name_of(dstE), valE);
             state[dstE as usize] = valE;
@@ -113,11 +121,16 @@ define_units! {
         }
     }
 
+    /// Compute `b op a`, including the operations of the arithmetic
+    /// extension. `error` is set for an invalid function or a division by
+    /// zero, which is an invalid instruction, and then the output is 0.
     ArithmetcLogicUnit alu {
         .input(a: u64, b: u64, fun: u8)
-        .output(e: u64)
+        .output(e: u64, error: bool)
     } {
-        *e = crate::isa::arithmetic_compute(a, b, fun).unwrap_or(0);
+        let result = crate::isa::arithmetic_compute(a, b, fun);
+        *e = result.unwrap_or(0);
+        *error = result.is_none();
     }
 
     /// Given the input and output of the ALU, this unit calculate the
@@ -136,12 +149,14 @@ define_units! {
     }
 
     /// Instructions like CMOVX or JX needs to check the condition code based
-    /// on the function code, which is simulated by this unit.
+    /// on the function code, which is simulated by this unit. `error` is set
+    /// for a function code without condition, and then `cnd` is false.
     InstructionCondition cond {
         .input(condfun: u8, cc: ConditionCode)
-        .output(cnd: bool)
+        .output(cnd: bool, error: bool)
     } {
         *cnd = cc.test(condfun);
+        *error = condfun > crate::isa::cond_fn::G;
     }
 
     DataMemory dmem {
@@ -155,7 +170,7 @@ define_units! {
         )
         binary: MemData
     } {
-        if addr >= MEM_SIZE as u64 - 8 {
+        if !binary.is_valid(addr) {
             *dataout = 0;
             *error = true;
             return
@@ -163,18 +178,17 @@ define_units! {
         *error = false;
         if write {
             tracing::info!("write memory: addr = {:#x}, datain = {:#x}", addr, datain);
-            let section: &mut [u8] = &mut binary.write()[(addr as usize)..];
-            put_u64(section, datain);
+            binary.store_u64(addr, datain);
             *dataout = 0;
         } else if read {
-            *dataout = get_u64(&binary.read()[(addr as usize)..]);
+            *dataout = binary.load_u64(addr);
         }
     }
 }
 
 impl std::fmt::Display for Units {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let reg_file = self.reg_read.state.borrow();
+        let reg_file = self.reg_read.state.lock().unwrap();
         let regs = crate::utils::format_reg_file(*reg_file);
         f.write_fmt(format_args!("{regs}\n{cc}", cc = self.reg_cc.inner_cc))
     }
@@ -183,7 +197,7 @@ impl std::fmt::Display for Units {
 impl HardwareUnits for Units {
     /// Init CPU harewre with given memory.
     fn init(memory: MemData) -> Self {
-        let reg = Rc::new(RefCell::new([0; 16]));
+        let reg = Arc::new(Mutex::new([0; 16]));
         Self {
             imem: InstructionMemory {
                 binary: memory.clone(),
@@ -202,6 +216,24 @@ impl HardwareUnits for Units {
     }
 
     fn register_file(&self) -> RegFile {
-        *self.reg_read.state.borrow()
+        *self.reg_read.state.lock().unwrap()
+    }
+
+    fn io_devices(&self) -> crate::mmio::IoDevices {
+        self.dmem.binary.io().clone()
+    }
+
+    fn snapshot(&self) -> UnitsSnapshot {
+        UnitsSnapshot {
+            regs: *self.reg_read.state.lock().unwrap(),
+            cc: self.reg_cc.inner_cc,
+            mem: self.dmem.binary.snapshot(),
+        }
+    }
+
+    fn restore(&mut self, snapshot: &UnitsSnapshot) {
+        *self.reg_read.state.lock().unwrap() = snapshot.regs;
+        self.reg_cc.inner_cc = snapshot.cc;
+        self.dmem.binary.restore(&snapshot.mem);
     }
 }
@@ -1,4 +1,5 @@
 // All hardware modules
+pub mod hardware_exc;
 pub mod hardware_pipe;
 pub mod hardware_seq;
 
@@ -13,7 +14,7 @@ use crate::framework::{CpuSim, MemData, PipeSim};
 
 /// Get all architecture names
 pub fn arch_names() -> Vec<&'static str> {
-    let mut names = vec!["seq_std", "seq_plus_std", "pipe_std"];
+    let mut names = vec!["seq_std", "seq_plus_std", "pipe_std", "pipe_exc"];
     names.extend(extra::ARCH_NAMES);
     names
 }
@@ -23,6 +24,7 @@ pub fn create_sim(kind: String, memory: MemData, tty_out: bool) -> Box<dyn CpuSi
         "seq_std" => Box::new(PipeSim::<builtin::seq_std::Arch>::new(memory, tty_out)),
         "seq_plus_std" => Box::new(PipeSim::<builtin::seq_plus_std::Arch>::new(memory, tty_out)),
         "pipe_std" => Box::new(PipeSim::<builtin::pipe_std::Arch>::new(memory, tty_out)),
+        "pipe_exc" => Box::new(PipeSim::<builtin::pipe_exc::Arch>::new(memory, tty_out)),
         _ => extra::create_sim(kind, memory, tty_out),
     }
 }
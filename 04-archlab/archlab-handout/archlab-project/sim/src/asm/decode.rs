@@ -0,0 +1,312 @@
+//! Decode machine code back to y86 instructions.
+
+use std::{collections::BTreeMap, fmt::Display};
+
+use super::{Addr, CondFn, Inst, OpFn, Reg};
+use crate::{
+    isa::{exc_fn, inst_code, reg_code},
+    utils::get_u64,
+};
+
+fn reg_of(code: u8) -> Reg {
+    use Reg::*;
+    const REGS: [Reg; 16] = [
+        RAX, RCX, RDX, RBX, RSP, RBP, RSI, RDI, R8, R9, R10, R11, R12, R13, R14, RNONE,
+    ];
+    REGS[(code & 0xf) as usize]
+}
+
+fn cond_of(ifun: u8) -> Option<CondFn> {
+    use CondFn::*;
+    [YES, LE, L, E, NE, GE, G].get(ifun as usize).copied()
+}
+
+fn op_of(ifun: u8) -> Option<OpFn> {
+    use OpFn::*;
+    [ADD, SUB, AND, XOR, MUL, DIV, MOD, SAR, SHL, SHR]
+        .get(ifun as usize)
+        .copied()
+}
+
+/// Decode the instruction at the beginning of `bytes`, which is located at
+/// `addr` in the memory. Returns the instruction and its length in bytes.
+///
+/// The icode, ifun and register nibbles are validated like
+/// [`crate::isa::simulate`], so that an instruction is decoded if and only if
+/// the ISA executes it. The extended `iopq` and `jm` instructions and the
+/// exception extension are supported.
+pub fn decode(bytes: &[u8], addr: u64) -> anyhow::Result<(Inst<u64>, usize)> {
+    let Some(&first) = bytes.first() else {
+        anyhow::bail!("no instruction at {addr:#x}");
+    };
+    let icode = first >> 4;
+    let ifun = first & 0xf;
+    let len = match icode {
+        inst_code::HALT | inst_code::NOP | inst_code::RET | inst_code::EXC => 1,
+        inst_code::CMOVX | inst_code::OPQ | inst_code::PUSHQ | inst_code::POPQ => 2,
+        inst_code::JX | inst_code::CALL => 9,
+        inst_code::IRMOVQ
+        | inst_code::RMMOVQ
+        | inst_code::MRMOVQ
+        | inst_code::IOPQ
+        | inst_code::JM => 10,
+        _ => anyhow::bail!("invalid instruction code at {addr:#x}: {icode:#x}"),
+    };
+    if bytes.len() < len {
+        anyhow::bail!("instruction at {addr:#x} is truncated");
+    }
+    let name = inst_code::name_of(icode);
+    let invalid_ifun = || anyhow::anyhow!("invalid ifun for {name} at {addr:#x}: {ifun:#x}");
+    // only these instructions have function codes
+    let has_ifun = matches!(
+        icode,
+        inst_code::CMOVX | inst_code::JX | inst_code::OPQ | inst_code::IOPQ | inst_code::EXC
+    );
+    if ifun != 0 && !has_ifun {
+        return Err(invalid_ifun());
+    }
+
+    let regs = if len > 1 { bytes[1] } else { 0 };
+    let (ra, rb) = (reg_of(regs >> 4), reg_of(regs & 0xf));
+    if matches!(icode, inst_code::IRMOVQ | inst_code::IOPQ | inst_code::JM) && ra != Reg::RNONE {
+        anyhow::bail!("invalid register code at {addr:#x}: {:#x}", ra as u8);
+    }
+    let inst = match icode {
+        inst_code::HALT => Inst::HALT,
+        inst_code::NOP => Inst::NOP,
+        inst_code::RET => Inst::RET,
+        inst_code::CMOVX => Inst::CMOVX(cond_of(ifun).ok_or_else(invalid_ifun)?, ra, rb),
+        inst_code::OPQ => Inst::OPQ(op_of(ifun).ok_or_else(invalid_ifun)?, ra, rb),
+        inst_code::PUSHQ => Inst::PUSHQ(ra),
+        inst_code::POPQ => Inst::POPQ(ra),
+        inst_code::JX => Inst::JX(
+            cond_of(ifun).ok_or_else(invalid_ifun)?,
+            get_u64(&bytes[1..9]),
+        ),
+        inst_code::CALL => Inst::CALL(get_u64(&bytes[1..9])),
+        inst_code::IRMOVQ => Inst::IRMOVQ(rb, get_u64(&bytes[2..10])),
+        inst_code::RMMOVQ => Inst::RMMOVQ(ra, Addr(Some(get_u64(&bytes[2..10])), rb)),
+        inst_code::MRMOVQ => Inst::MRMOVQ(Addr(Some(get_u64(&bytes[2..10])), rb), ra),
+        inst_code::IOPQ => Inst::IOPQ(
+            op_of(ifun).ok_or_else(invalid_ifun)?,
+            get_u64(&bytes[2..10]),
+            rb,
+        ),
+        inst_code::JM => Inst::JM(get_u64(&bytes[2..10]), rb),
+        inst_code::EXC => match ifun {
+            exc_fn::TRAP => Inst::TRAP,
+            exc_fn::IRET => Inst::IRET,
+            _ => return Err(invalid_ifun()),
+        },
+        _ => unreachable!(),
+    };
+    Ok((inst, len))
+}
+
+fn reg_name(reg: Reg) -> &'static str {
+    match reg as u8 {
+        reg_code::RNONE => "%rnone",
+        code => {
+            const NAMES: [&str; 15] = [
+                "%rax", "%rcx", "%rdx", "%rbx", "%rsp", "%rbp", "%rsi", "%rdi", "%r8", "%r9",
+                "%r10", "%r11", "%r12", "%r13", "%r14",
+            ];
+            NAMES[code as usize]
+        }
+    }
+}
+
+fn cond_suffix(cond: CondFn) -> &'static str {
+    match cond {
+        CondFn::YES => "",
+        CondFn::LE => "le",
+        CondFn::L => "l",
+        CondFn::E => "e",
+        CondFn::NE => "ne",
+        CondFn::GE => "ge",
+        CondFn::G => "g",
+    }
+}
+
+fn op_name(op: OpFn) -> &'static str {
+    match op {
+        OpFn::ADD => "add",
+        OpFn::SUB => "sub",
+        OpFn::AND => "and",
+        OpFn::XOR => "xor",
+        OpFn::MUL => "mul",
+        OpFn::DIV => "div",
+        OpFn::MOD => "mod",
+        OpFn::SAR => "sar",
+        OpFn::SHL => "shl",
+        OpFn::SHR => "shr",
+    }
+}
+
+/// Format a value as a hexadecimal literal, where negative values keep their
+/// sign (e.g. `-0x8`) so that the output can be assembled again.
+fn signed_hex(value: u64) -> String {
+    if (value as i64) < 0 {
+        format!("-{:#x}", (value as i64).unsigned_abs())
+    } else {
+        format!("{value:#x}")
+    }
+}
+
+/// Assembly text of a decoded instruction, returned by
+/// [`Inst::display_with`].
+pub struct InstDisplay<'a> {
+    inst: &'a Inst<u64>,
+    labels: Option<&'a BTreeMap<u64, String>>,
+}
+
+impl InstDisplay<'_> {
+    /// Name of the destination of jump and call, using labels if available.
+    fn target(&self, dest: u64) -> String {
+        self.labels
+            .and_then(|labels| labels.get(&dest).cloned())
+            .unwrap_or_else(|| format!("{dest:#x}"))
+    }
+}
+
+impl Display for InstDisplay<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let addr = |Addr(dis, rb): &Addr<u64>| match dis {
+            // absolute address
+            Some(dis) if *rb == Reg::RNONE => signed_hex(*dis),
+            Some(0) | None => format!("({})", reg_name(*rb)),
+            Some(dis) => format!("{}({})", signed_hex(*dis), reg_name(*rb)),
+        };
+        match self.inst {
+            Inst::HALT => write!(f, "halt"),
+            Inst::NOP => write!(f, "nop"),
+            Inst::RET => write!(f, "ret"),
+            Inst::CMOVX(CondFn::YES, ra, rb) => {
+                write!(f, "rrmovq {}, {}", reg_name(*ra), reg_name(*rb))
+            }
+            Inst::CMOVX(c, ra, rb) => write!(
+                f,
+                "cmov{} {}, {}",
+                cond_suffix(*c),
+                reg_name(*ra),
+                reg_name(*rb)
+            ),
+            Inst::IRMOVQ(rb, v) => write!(f, "irmovq ${}, {}", signed_hex(*v), reg_name(*rb)),
+            Inst::RMMOVQ(ra, a) => write!(f, "rmmovq {}, {}", reg_name(*ra), addr(a)),
+            Inst::MRMOVQ(a, ra) => write!(f, "mrmovq {}, {}", addr(a), reg_name(*ra)),
+            Inst::OPQ(op, ra, rb) => {
+                write!(f, "{}q {}, {}", op_name(*op), reg_name(*ra), reg_name(*rb))
+            }
+            Inst::JX(CondFn::YES, dest) => write!(f, "jmp {}", self.target(*dest)),
+            Inst::JX(c, dest) => write!(f, "j{} {}", cond_suffix(*c), self.target(*dest)),
+            Inst::CALL(dest) => write!(f, "call {}", self.target(*dest)),
+            Inst::PUSHQ(ra) => write!(f, "pushq {}", reg_name(*ra)),
+            Inst::POPQ(ra) => write!(f, "popq {}", reg_name(*ra)),
+            Inst::IOPQ(op, v, rb) => write!(
+                f,
+                "i{}q ${}, {}",
+                op_name(*op),
+                signed_hex(*v),
+                reg_name(*rb)
+            ),
+            Inst::JM(v, rb) => write!(f, "jm ${}, {}", signed_hex(*v), reg_name(*rb)),
+            Inst::TRAP => write!(f, "trap"),
+            Inst::IRET => write!(f, "iret"),
+        }
+    }
+}
+
+impl Inst<u64> {
+    /// Display the instruction with the destinations of jump and call
+    /// replaced by labels, where `labels` maps addresses to names.
+    pub fn display_with<'a>(&'a self, labels: &'a BTreeMap<u64, String>) -> InstDisplay<'a> {
+        InstDisplay {
+            inst: self,
+            labels: Some(labels),
+        }
+    }
+}
+
+impl Display for Inst<u64> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        InstDisplay {
+            inst: self,
+            labels: None,
+        }
+        .fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::decode;
+    use crate::{assemble, AssembleOption};
+
+    #[test]
+    fn test_decode() {
+        let src = "
+main:
+    irmovq $0x200, %rsp
+    rrmovq %rdi, %rbx
+    cmovle %rax, %r14
+    rmmovq %rax, -0x8(%rsp)
+    mrmovq (%rdi), %rbx
+    rmmovq %rax, 0x100
+    jm $0x8, %rax
+    subq %rbx, %rax
+    iaddq $-0x1, %rcx
+    mulq %rbx, %rax
+    ishrq $0x3, %rcx
+    jne main
+    call main
+    pushq %rbp
+    popq %rbp
+    nop
+    ret
+    trap
+    iret
+    halt
+";
+        let a = assemble(src, AssembleOption::default()).unwrap();
+        let mem = a.obj.init_mem();
+        let labels: BTreeMap<u64, String> =
+            a.obj.symbols.iter().map(|(k, v)| (*v, k.clone())).collect();
+        for line in a.source.lines() {
+            let (Some(addr), Some(inst)) = (line.addr, &line.inst) else {
+                continue;
+            };
+            let (decoded, len) = decode(&mem[addr as usize..], addr).unwrap();
+            assert_eq!(len, inst.len());
+            assert_eq!(decoded.display_with(&labels).to_string(), line.src.trim());
+        }
+
+        assert_eq!(
+            decode(&[0x73, 0x10, 0, 0, 0, 0, 0, 0, 0], 0)
+                .unwrap()
+                .0
+                .to_string(),
+            "je 0x10"
+        );
+        // invalid icode, ifun, register and truncated instruction
+        assert!(decode(&[0xf0], 0).is_err());
+        assert!(decode(&[0xe2], 0).is_err());
+        assert!(decode(&[0xd0, 0x01, 0, 0, 0, 0, 0, 0, 0, 0], 0).is_err());
+        assert!(decode(&[0x01], 0).is_err());
+        assert!(decode(&[0x6a, 0x01], 0).is_err());
+        assert!(decode(&[0x30, 0x01, 0, 0, 0, 0, 0, 0, 0, 0], 0).is_err());
+        assert!(decode(&[0x30, 0xf1, 0], 0).is_err());
+        assert!(decode(&[], 0).is_err());
+
+        // a condition without mnemonic is rejected by both the decoder and the
+        // ISA
+        for inst in [[0x27, 0x03].as_slice(), &[0x7f, 0, 0, 0, 0, 0, 0, 0, 0]] {
+            let mut bin = vec![0; 0x100];
+            bin[..inst.len()].copy_from_slice(inst);
+            assert!(decode(&bin, 0).is_err());
+            let result = crate::isa::simulate(bin.into_boxed_slice(), false);
+            assert_eq!(result.stat, crate::isa::Stat::Ins);
+        }
+    }
+}
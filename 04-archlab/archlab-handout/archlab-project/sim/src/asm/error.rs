@@ -0,0 +1,317 @@
+//! Diagnostics reported by the assembler.
+//!
+//! Errors are collected for the whole source file instead of stopping at the
+//! first one. Use `format!("{}", errors)` to render them in plain text, or
+//! `format!("{:#}", errors)` to render them with terminal colors.
+
+use std::{fmt::Display, ops::Range};
+
+use crate::utils::{B, BLUB, REDB, YLWB};
+
+/// What went wrong during assembling.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AsmErrorKind {
+    /// The line does not match the syntax of y86 assembly.
+    Syntax(String),
+    /// The mnemonic of the line is neither an instruction nor a directive.
+    UnknownInstruction(String),
+    /// A symbol is used but never defined.
+    UndefinedSymbol(String),
+    /// A label or constant is defined more than once.
+    DuplicateLabel(String),
+    /// A numeric literal can not be represented in 64 bits.
+    InvalidNumber(String),
+    /// The argument of `.align` is not a power of 2.
+    InvalidAlign(u64),
+    /// Contents are placed outside of the memory.
+    AddressOutOfRange(u64),
+    /// A constant expression can not be evaluated, e.g. division by zero.
+    InvalidExpression(String),
+    /// The value of an expression does not fit in the size of the data.
+    ValueOutOfRange { value: u64, size: u8 },
+    /// Contents of two lines are placed at the same bytes.
+    Overlap { start: u64, end: u64 },
+    /// A directive is used in absolute assembly, e.g. `.section`.
+    RelocatableOnly(String),
+    /// Something can not be placed in relocatable objects.
+    NotRelocatable(String),
+}
+
+impl Display for AsmErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Syntax(msg) => write!(f, "{msg}"),
+            Self::UnknownInstruction(name) => write!(f, "unknown instruction `{name}`"),
+            Self::UndefinedSymbol(name) => write!(f, "undefined symbol `{name}`"),
+            Self::DuplicateLabel(name) => write!(f, "symbol `{name}` is defined multiple times"),
+            Self::InvalidNumber(s) => write!(f, "invalid numeric literal `{s}`"),
+            Self::InvalidAlign(n) => write!(f, "invalid align number {n}, expect a power of 2"),
+            Self::AddressOutOfRange(addr) => {
+                write!(f, "address {addr:#x} is out of the memory range")
+            }
+            Self::InvalidExpression(msg) => write!(f, "cannot resolve expression: {msg}"),
+            Self::ValueOutOfRange { value, size } => {
+                write!(f, "value {value:#x} does not fit in {size} byte(s)")
+            }
+            Self::Overlap { start, end } => {
+                write!(
+                    f,
+                    "bytes {start:#x}..{end:#x} are occupied by multiple lines"
+                )
+            }
+            Self::RelocatableOnly(name) => {
+                write!(f, "`{name}` is only supported in relocatable objects")
+            }
+            Self::NotRelocatable(what) => write!(f, "{what} cannot be relocated"),
+        }
+    }
+}
+
+/// An error located in a single line. The location of the line is attached
+/// later by [`AsmError::new`].
+#[derive(Debug, Clone)]
+pub(crate) struct LineError {
+    pub kind: AsmErrorKind,
+    /// byte range of the offending text in the line
+    pub range: Range<usize>,
+    pub suggestion: Option<String>,
+}
+
+impl LineError {
+    pub fn new(kind: AsmErrorKind, range: Range<usize>) -> Self {
+        Self {
+            kind,
+            range,
+            suggestion: None,
+        }
+    }
+
+    pub fn with_suggestion(mut self, suggestion: Option<String>) -> Self {
+        self.suggestion = suggestion;
+        self
+    }
+}
+
+/// Severity of a diagnostic. Warnings do not stop the assembling.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Severity {
+    #[default]
+    Error,
+    Warning,
+}
+
+/// A diagnostic with its location in the source file.
+#[derive(Debug, Clone)]
+pub struct AsmError {
+    pub kind: AsmErrorKind,
+    pub severity: Severity,
+    /// name of the source file
+    pub file: String,
+    /// line number (start from 1)
+    pub line: usize,
+    /// column number (start from 1, counted in chars)
+    pub column: usize,
+    /// number of chars to highlight (at least 1)
+    pub len: usize,
+    /// source text of the line
+    pub src: String,
+    /// help message, e.g. "did you mean `loop_end`?"
+    pub suggestion: Option<String>,
+    /// the generated line where the error is found, if the error is located
+    /// at the invocation of a macro or a loop
+    pub expansion: Option<ExpansionNote>,
+}
+
+/// A line generated by a macro or a loop, see [`AsmError::expansion`].
+#[derive(Debug, Clone)]
+pub struct ExpansionNote {
+    /// what is expanded, e.g. "macro `copy`" or "`.rept`"
+    pub origin: String,
+    /// text of the generated line
+    pub src: String,
+}
+
+impl AsmError {
+    pub(crate) fn new(file: &str, line: usize, src: &str, err: LineError) -> Self {
+        let start = err.range.start.min(src.len());
+        let end = err.range.end.clamp(start, src.len());
+        Self {
+            kind: err.kind,
+            severity: Severity::Error,
+            file: file.to_string(),
+            line,
+            column: src[..start].chars().count() + 1,
+            len: src[start..end].chars().count().max(1),
+            src: src.to_string(),
+            suggestion: err.suggestion,
+            expansion: None,
+        }
+    }
+
+    /// Locate an error in a generated line at the invocation, whose text is
+    /// `src`. The generated line is kept in [`AsmError::expansion`].
+    pub(crate) fn in_expansion(mut self, origin: &str, src: &str) -> Self {
+        let range = super::content_range(src);
+        self.column = src[..range.start].chars().count() + 1;
+        self.len = src[range].chars().count().max(1);
+        let generated = std::mem::replace(&mut self.src, src.to_string());
+        self.expansion = Some(ExpansionNote {
+            origin: origin.to_string(),
+            src: generated.trim().to_string(),
+        });
+        self
+    }
+
+    pub(crate) fn into_warning(mut self) -> Self {
+        self.severity = Severity::Warning;
+        self
+    }
+
+    fn render(&self, f: &mut std::fmt::Formatter<'_>, color: bool) -> std::fmt::Result {
+        let (e, b, g) = match (color, self.severity) {
+            (false, _) => Default::default(),
+            (true, Severity::Error) => (REDB, B, BLUB),
+            (true, Severity::Warning) => (YLWB, B, BLUB),
+        };
+        let title = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        let ln = self.line.to_string();
+        let pad = " ".repeat(ln.len());
+        // keep tabs so that the carets are aligned with the source text
+        let indent: String = self
+            .src
+            .chars()
+            .take(self.column - 1)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+
+        writeln!(f, "{e}{title}{e:#}{b}: {}{b:#}", self.kind)?;
+        writeln!(
+            f,
+            "{pad}{g}-->{g:#} {}:{}:{}",
+            self.file, self.line, self.column
+        )?;
+        writeln!(f, "{pad} {g}|{g:#}")?;
+        writeln!(f, "{g}{ln} |{g:#} {}", self.src)?;
+        write!(
+            f,
+            "{pad} {g}|{g:#} {indent}{e}{}{e:#}",
+            "^".repeat(self.len)
+        )?;
+        if let Some(s) = &self.suggestion {
+            write!(f, " {b}help{b:#}: {s}")?;
+        }
+        writeln!(f)?;
+        if let Some(note) = &self.expansion {
+            writeln!(f, "{pad} {g}|{g:#}")?;
+            writeln!(
+                f,
+                "{pad} {g}={g:#} {b}note{b:#}: in expansion of {}: `{}`",
+                note.origin, note.src
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for AsmError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.render(f, f.alternate())
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+/// All errors found while assembling a source file, in the order of lines.
+#[derive(Debug, Clone, Default)]
+pub struct AsmErrors {
+    pub errors: Vec<AsmError>,
+    /// name of the input file, which may include the files of the errors
+    pub file: String,
+}
+
+impl AsmErrors {
+    pub(crate) fn new(file: &str, errors: Vec<AsmError>) -> Self {
+        Self {
+            errors,
+            file: file.to_string(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.errors.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &AsmError> {
+        self.errors.iter()
+    }
+}
+
+impl Display for AsmErrors {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let color = f.alternate();
+        for err in &self.errors {
+            err.render(f, color)?;
+            writeln!(f)?;
+        }
+        let (e, b) = if color { (REDB, B) } else { Default::default() };
+        let file = &self.file;
+        match self.errors.len() {
+            1 => writeln!(
+                f,
+                "{e}error{e:#}{b}: could not assemble `{file}` due to 1 previous error{b:#}"
+            ),
+            n => writeln!(
+                f,
+                "{e}error{e:#}{b}: could not assemble `{file}` due to {n} previous errors{b:#}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for AsmErrors {}
+
+/// Levenshtein distance between two strings.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut prev = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let cur = row[j + 1];
+            row[j + 1] = if ca == *cb {
+                prev
+            } else {
+                prev.min(row[j]).min(cur) + 1
+            };
+            prev = cur;
+        }
+    }
+    row[b.len()]
+}
+
+/// Find the most similar name among the candidates, which is used to suggest
+/// a fix for typos.
+pub(crate) fn similar_name<'a>(
+    name: &str,
+    candidates: impl IntoIterator<Item = &'a str>,
+) -> Option<&'a str> {
+    let threshold = (name.chars().count() / 3).max(1);
+    candidates
+        .into_iter()
+        .map(|c| (edit_distance(name, c), c))
+        .filter(|(d, _)| *d <= threshold)
+        .min_by_key(|(d, _)| *d)
+        .map(|(_, c)| c)
+}
+
+/// Suggestion message for a similar name, if any.
+pub(crate) fn did_you_mean<'a>(
+    name: &str,
+    candidates: impl IntoIterator<Item = &'a str>,
+) -> Option<String> {
+    similar_name(name, candidates).map(|s| format!("did you mean `{s}`?"))
+}
@@ -1,11 +1,26 @@
 //! This module provides parsing utilities for the y86 assembly.
-use anyhow::{bail, Context, Result};
-use pest::Parser;
+mod decode;
+pub(crate) mod error;
+mod preprocess;
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use pest::{
+    pratt_parser::{Assoc, Op, PrattParser},
+    Parser,
+};
 use pest_derive::Parser;
 
+pub use decode::{decode, InstDisplay};
+use error::did_you_mean;
+pub(crate) use error::LineError;
+pub use error::{AsmError, AsmErrorKind, AsmErrors, ExpansionNote, Severity};
+
 use crate::{
+    framework::MEM_SIZE,
     isa::{self, reg_code},
-    object::{LineInfo, Object, ObjectExt},
+    link::{Binding, Reloc, RelocObject, Section, Symbol},
+    object::{LineInfo, Object, ObjectExt, SymbolMap},
     utils, SourceInfo,
 };
 
@@ -13,15 +28,154 @@ use crate::{
 #[grammar = "src/asm/grammer.pest"] // relative to src
 pub struct Y86AsmParser;
 
-pub fn parse(src: &str) -> Result<pest::iterators::Pair<'_, Rule>> {
-    Ok(Y86AsmParser::parse(Rule::main, src)
-        .context("fail to parse ys file")?
-        .next()
-        .unwrap())
+/// All instructions and directives, used to suggest fixes for typos.
+const MNEMONICS: &[&str] = &[
+    "halt", "nop", "ret", "rrmovq", "cmovle", "cmovl", "cmove", "cmovne", "cmovge", "cmovg",
+    "irmovq", "rmmovq", "mrmovq", "addq", "subq", "andq", "xorq", "iaddq", "isubq", "iandq",
+    "ixorq", "mulq", "divq", "modq", "sarq", "shlq", "shrq", "imulq", "idivq", "imodq", "isarq",
+    "ishlq", "ishrq", "jmp", "jle", "jl", "je", "jne", "jge", "jg", "jm", "call", "pushq", "popq",
+    "trap", "syscall", "iret", ".pos", ".byte", ".word", ".long", ".quad", ".align", ".equ",
+    ".set", ".macro", ".endm", ".rept", ".irp", ".endr", ".global", ".globl", ".section", ".zero",
+    ".fill", ".ascii", ".string", ".asciz",
+];
+
+/// Parse a single line of the source file.
+fn parse_line(src: &str) -> Result<ParseInput<'_>, LineError> {
+    match Y86AsmParser::parse(Rule::single_line, src) {
+        Ok(mut pairs) => {
+            let pair = pairs.next().unwrap().into_inner().next().unwrap();
+            Ok(ParseInput { pair })
+        }
+        Err(err) => Err(syntax_error(src, err)),
+    }
+}
+
+/// Find the label defined at the beginning of the line, which returns its
+/// byte offset and name.
+pub(crate) fn leading_label(src: &str) -> Option<(usize, &str)> {
+    let start = src.len() - src.trim_start().len();
+    let rest = &src[start..];
+    let len = rest
+        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+        .unwrap_or(rest.len());
+    let name = &rest[..len];
+    let is_label = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && rest[len..].starts_with(':');
+    is_label.then_some((start, name))
+}
+
+/// Whether the symbol is a numeric local label (e.g. `1` or `1$2`), which
+/// is not exported to the symbol table.
+pub(crate) fn is_local_label(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_digit())
+}
+
+/// Give each definition of a numeric local label a unique name (`1$2` for the
+/// second definition of `1`), and resolve the references `1b` and `1f` to
+/// them. References without such definitions are kept, which are reported as
+/// undefined symbols.
+fn resolve_local_labels(src_infos: &mut [LineInfo]) {
+    let local = |info: &LineInfo| info.label.clone().filter(|l| is_local_label(l));
+    let mut totals: BTreeMap<String, usize> = BTreeMap::new();
+    for label in src_infos.iter().filter_map(local) {
+        *totals.entry(label).or_default() += 1;
+    }
+    // number of definitions so far
+    let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+    for info in src_infos.iter_mut() {
+        if let Some(label) = local(info) {
+            let count = counts.entry(label.clone()).or_default();
+            *count += 1;
+            info.label = Some(format!("{label}${count}"));
+        }
+        let resolve = |name: &str| {
+            let (label, dir) = name.split_at(name.len().checked_sub(1)?);
+            let count = counts.get(label).copied().unwrap_or_default();
+            let index = match dir {
+                "b" if is_local_label(label) => count,
+                "f" if is_local_label(label) => count + 1,
+                _ => return None,
+            };
+            let total = totals.get(label).copied().unwrap_or_default();
+            (1..=total)
+                .contains(&index)
+                .then(|| format!("{label}${index}"))
+        };
+        for imm in info.imms_mut() {
+            imm.rename(&resolve);
+        }
+    }
+}
+
+/// Byte range of the meaningful content of the line (without indentation and
+/// comments).
+pub(crate) fn content_range(src: &str) -> std::ops::Range<usize> {
+    let code = src.split('#').next().unwrap_or_default().trim_end();
+    let start = code.len() - code.trim_start().len();
+    start..code.len()
+}
+
+/// Build a readable error from the error reported by pest.
+fn syntax_error(src: &str, err: pest::error::Error<Rule>) -> LineError {
+    let pos = match err.location {
+        pest::error::InputLocation::Pos(pos) => pos,
+        pest::error::InputLocation::Span((start, _)) => start,
+    };
+    // skip the label to find the mnemonic
+    let start = match leading_label(src) {
+        Some((offset, name)) => {
+            let after = offset + name.len() + 1;
+            after + (src[after..].len() - src[after..].trim_start().len())
+        }
+        None => src.len() - src.trim_start().len(),
+    };
+    let len = src[start..]
+        .find(|c: char| c.is_whitespace() || c == '#')
+        .unwrap_or(src.len() - start);
+    let name = &src[start..start + len];
+
+    if name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '.') && !MNEMONICS.contains(&name)
+    {
+        return LineError::new(
+            AsmErrorKind::UnknownInstruction(name.to_string()),
+            start..start + len,
+        )
+        .with_suggestion(did_you_mean(name, MNEMONICS.iter().copied()));
+    }
+
+    if !MNEMONICS.contains(&name) {
+        let end = src[pos..]
+            .find(|c: char| c.is_whitespace() || c == ',' || c == '#')
+            .map(|i| pos + i)
+            .unwrap_or(src.len());
+        return LineError::new(AsmErrorKind::Syntax("invalid syntax".to_string()), pos..end);
+    }
+    // pest may report the error at the beginning of the line, so we
+    // highlight all the operands instead
+    let content_end = content_range(src).end.max(start + len);
+    let operands = &src[start + len..content_end];
+    let operands_start = content_end - operands.trim_start().len();
+    LineError::new(
+        AsmErrorKind::Syntax(format!("invalid operands for `{name}`")),
+        operands_start..content_end,
+    )
+}
+
+/// Parse a numeric literal token.
+fn parse_num(pair: &pest::iterators::Pair<'_, Rule>) -> Result<u64, LineError> {
+    let s = pair.as_str();
+    let s = s.strip_prefix('$').unwrap_or(s);
+    let span = pair.as_span();
+    utils::parse_literal(s).ok_or_else(|| {
+        LineError::new(
+            AsmErrorKind::InvalidNumber(s.to_string()),
+            span.start()..span.end(),
+        )
+    })
 }
 
 /// registers
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[allow(clippy::upper_case_acronyms)]
 pub enum Reg {
     RAX = reg_code::RAX as isize,
@@ -73,7 +227,7 @@ impl From<pest::iterators::Pair<'_, Rule>> for Reg {
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[allow(clippy::upper_case_acronyms)]
 pub enum CondFn {
     /// jmp or rrmovq
@@ -86,13 +240,20 @@ pub enum CondFn {
     G = 6,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[allow(clippy::upper_case_acronyms)]
 pub enum OpFn {
     ADD = 0,
     SUB = 1,
     AND = 2,
     XOR = 3,
+    // arithmetic extension
+    MUL = 4,
+    DIV = 5,
+    MOD = 6,
+    SAR = 7,
+    SHL = 8,
+    SHR = 9,
 }
 
 impl From<&str> for CondFn {
@@ -117,66 +278,416 @@ impl From<&str> for CondFn {
 }
 
 impl OpFn {
+    /// Parse the name of the operation without the `i` prefix, e.g. `addq`.
     fn from_instname(value: &str) -> Self {
-        if value.contains("andq") {
-            Self::AND
-        } else if value.contains("addq") {
-            Self::ADD
-        } else if value.contains("subq") {
-            Self::SUB
-        } else if value.contains("xorq") {
-            Self::XOR
-        } else {
-            panic!("invalid")
+        match value {
+            "andq" => Self::AND,
+            "addq" => Self::ADD,
+            "subq" => Self::SUB,
+            "xorq" => Self::XOR,
+            "mulq" => Self::MUL,
+            "divq" => Self::DIV,
+            "modq" => Self::MOD,
+            "sarq" => Self::SAR,
+            "shlq" => Self::SHL,
+            "shrq" => Self::SHR,
+            _ => panic!("invalid"),
         }
     }
 }
 
 /// Address mode expression with optional displacement
-#[derive(Debug, Clone, Copy)]
-pub struct Addr(pub Option<u64>, pub Reg);
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Addr<ImmType>(pub Option<ImmType>, pub Reg);
 
-impl From<ParseInput<'_>> for Addr {
-    fn from(value: ParseInput<'_>) -> Self {
+impl Addr<Imm> {
+    fn parse(value: ParseInput<'_>) -> Result<Self, LineError> {
         let mut it = value.into_iter();
-        let num_or_reg = it.next().unwrap();
-        if num_or_reg.as_rule() == Rule::reg {
+        let expr_or_reg = it.next().unwrap();
+        if expr_or_reg.as_rule() == Rule::reg {
             // no displacement
-            let reg = Reg::from(num_or_reg.pair);
-            Self(None, reg)
+            let reg = Reg::from(expr_or_reg.pair);
+            Ok(Self(None, reg))
         } else {
-            let s = num_or_reg.as_str();
-            let num = utils::parse_literal(s).unwrap() as i64;
-            let reg = it.next_reg();
-            Self(Some(num as u64), reg)
+            let dis = Imm::parse(expr_or_reg)?;
+            // absolute address without a register
+            let reg = it.next().map_or(Reg::RNONE, |pair| Reg::from(pair.pair));
+            Ok(Self(Some(dis), reg))
         }
     }
 }
 
-/// Immediate values (can be raw number or address of label)
+/// Binary operators in constant expressions, from the lowest precedence to
+/// the highest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinOp {
+    Or,
+    And,
+    Shl,
+    /// arithmetic right shift
+    Shr,
+    Add,
+    Sub,
+    Mul,
+    /// signed division
+    Div,
+}
+
+/// Unary operators in constant expressions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnOp {
+    Neg,
+    Not,
+}
+
+/// Immediate values, which are constant expressions of numbers and labels.
+///
+/// Expressions are evaluated after all labels are resolved, see
+/// [`Imm::eval`].
 #[derive(Debug, Clone)]
 pub enum Imm {
     Num(i64),
-    Label(String),
+    /// Name of the label and its byte offset in the source line.
+    Label(String, usize),
+    /// Address of the current line, written as `.`.
+    Here,
+    Unary(UnOp, Box<Imm>),
+    /// Operator, operands and the byte range of the operator in the source
+    /// line.
+    Binary(BinOp, Box<Imm>, Box<Imm>, std::ops::Range<usize>),
 }
 
-impl From<ParseInput<'_>> for Imm {
-    fn from(value: ParseInput<'_>) -> Self {
-        if value.as_rule() == Rule::label {
-            Self::Label(value.as_str().to_string())
-        } else {
-            let s = value.as_str();
-            let s = s.strip_prefix('$').unwrap_or(s);
-            let num = utils::parse_literal(s).unwrap() as i64;
-            Self::Num(num)
+static EXPR_PARSER: std::sync::LazyLock<PrattParser<Rule>> = std::sync::LazyLock::new(|| {
+    PrattParser::new()
+        .op(Op::infix(Rule::op_or, Assoc::Left))
+        .op(Op::infix(Rule::op_and, Assoc::Left))
+        .op(Op::infix(Rule::op_shl, Assoc::Left) | Op::infix(Rule::op_shr, Assoc::Left))
+        .op(Op::infix(Rule::op_add, Assoc::Left) | Op::infix(Rule::op_sub, Assoc::Left))
+        .op(Op::infix(Rule::op_mul, Assoc::Left) | Op::infix(Rule::op_div, Assoc::Left))
+        .op(Op::prefix(Rule::op_neg) | Op::prefix(Rule::op_not))
+});
+
+impl Imm {
+    /// Parse an immediate (`$expr`), an expression or a label.
+    fn parse(value: ParseInput<'_>) -> Result<Self, LineError> {
+        match value.as_rule() {
+            Rule::imm => Self::parse(value.into_iter().next().unwrap()),
+            Rule::expr => Self::parse_expr(value.pair.into_inner()),
+            _ => Self::parse_primary(value.pair),
+        }
+    }
+
+    fn parse_primary(pair: pest::iterators::Pair<'_, Rule>) -> Result<Self, LineError> {
+        match pair.as_rule() {
+            Rule::label | Rule::local_ref => Ok(Self::Label(
+                pair.as_str().to_string(),
+                pair.as_span().start(),
+            )),
+            Rule::here => Ok(Self::Here),
+            Rule::expr => Self::parse_expr(pair.into_inner()),
+            _ => Ok(Self::Num(parse_num(&pair)? as i64)),
+        }
+    }
+
+    fn parse_expr(pairs: pest::iterators::Pairs<'_, Rule>) -> Result<Self, LineError> {
+        EXPR_PARSER
+            .map_primary(Self::parse_primary)
+            .map_prefix(|op, rhs| {
+                let op = match op.as_rule() {
+                    Rule::op_neg => UnOp::Neg,
+                    _ => UnOp::Not,
+                };
+                Ok(Self::Unary(op, Box::new(rhs?)))
+            })
+            .map_infix(|lhs, op, rhs| {
+                let range = op.as_span().start()..op.as_span().end();
+                let op = match op.as_rule() {
+                    Rule::op_or => BinOp::Or,
+                    Rule::op_and => BinOp::And,
+                    Rule::op_shl => BinOp::Shl,
+                    Rule::op_shr => BinOp::Shr,
+                    Rule::op_add => BinOp::Add,
+                    Rule::op_sub => BinOp::Sub,
+                    Rule::op_mul => BinOp::Mul,
+                    _ => BinOp::Div,
+                };
+                Ok(Self::Binary(op, Box::new(lhs?), Box::new(rhs?), range))
+            })
+            .parse(pairs)
+    }
+
+    /// Evaluate the expression. `here` is the address of the current line.
+    ///
+    /// All arithmetic wraps around on overflow. Dividing by zero or shifting
+    /// by 64 bits or more is an error.
+    pub(crate) fn eval(&self, sym: &SymbolMap, here: u64) -> Result<u64, LineError> {
+        Ok(match self {
+            Self::Num(n) => *n as u64,
+            Self::Label(label, offset) => sym.get(label).copied().ok_or_else(|| {
+                LineError::new(
+                    AsmErrorKind::UndefinedSymbol(label.clone()),
+                    *offset..*offset + label.len(),
+                )
+                .with_suggestion(did_you_mean(
+                    label,
+                    sym.keys()
+                        .map(String::as_str)
+                        .filter(|s| !is_local_label(s)),
+                ))
+            })?,
+            Self::Here => here,
+            Self::Unary(op, v) => {
+                let v = v.eval(sym, here)?;
+                match op {
+                    UnOp::Neg => v.wrapping_neg(),
+                    UnOp::Not => !v,
+                }
+            }
+            Self::Binary(op, lhs, rhs, range) => {
+                let a = lhs.eval(sym, here)?;
+                let b = rhs.eval(sym, here)?;
+                let invalid = |msg: String| {
+                    LineError::new(AsmErrorKind::InvalidExpression(msg), range.clone())
+                };
+                match op {
+                    BinOp::Or => a | b,
+                    BinOp::And => a & b,
+                    BinOp::Shl | BinOp::Shr if b >= 64 => {
+                        return Err(invalid(format!("shift amount {b} is out of range")))
+                    }
+                    BinOp::Shl => a << b,
+                    BinOp::Shr => ((a as i64) >> b) as u64,
+                    BinOp::Add => a.wrapping_add(b),
+                    BinOp::Sub => a.wrapping_sub(b),
+                    BinOp::Mul => a.wrapping_mul(b),
+                    BinOp::Div if b == 0 => return Err(invalid("division by zero".to_string())),
+                    BinOp::Div => (a as i64).wrapping_div(b as i64) as u64,
+                }
+            }
+        })
+    }
+
+    /// Parse an expression written by the [`Display`](std::fmt::Display) of
+    /// [`Imm`], e.g. in relocatable objects.
+    pub(crate) fn parse_str(src: &str) -> Result<Self, LineError> {
+        let pair = match Y86AsmParser::parse(Rule::expr, src) {
+            Ok(mut pairs) => pairs.next().unwrap(),
+            Err(err) => return Err(syntax_error(src, err)),
+        };
+        if pair.as_str().len() != src.len() {
+            let start = pair.as_str().len();
+            return Err(LineError::new(
+                AsmErrorKind::Syntax("unexpected token".to_string()),
+                start..src.len(),
+            ));
+        }
+        Self::parse_expr(pair.into_inner())
+    }
+
+    /// Names of all symbols used in the expression.
+    pub(crate) fn symbols(&self) -> Vec<&str> {
+        match self {
+            Self::Num(_) | Self::Here => Vec::new(),
+            Self::Label(name, _) => vec![name],
+            Self::Unary(_, v) => v.symbols(),
+            Self::Binary(_, lhs, rhs, _) => {
+                let mut names = lhs.symbols();
+                names.extend(rhs.symbols());
+                names
+            }
+        }
+    }
+
+    /// Whether the expression uses `.`.
+    pub(crate) fn uses_here(&self) -> bool {
+        match self {
+            Self::Num(_) | Self::Label(_, _) => false,
+            Self::Here => true,
+            Self::Unary(_, v) => v.uses_here(),
+            Self::Binary(_, lhs, rhs, _) => lhs.uses_here() || rhs.uses_here(),
+        }
+    }
+
+    /// Replace symbols by the expressions given by `f`, which returns `None`
+    /// to keep the symbol.
+    pub(crate) fn substitute(&self, f: &impl Fn(&str) -> Option<Imm>) -> Imm {
+        match self {
+            Self::Label(name, _) => f(name).unwrap_or_else(|| self.clone()),
+            Self::Num(_) | Self::Here => self.clone(),
+            Self::Unary(op, v) => Self::Unary(*op, Box::new(v.substitute(f))),
+            Self::Binary(op, lhs, rhs, range) => Self::Binary(
+                *op,
+                Box::new(lhs.substitute(f)),
+                Box::new(rhs.substitute(f)),
+                range.clone(),
+            ),
+        }
+    }
+
+    /// Rename the symbols in place, where `f` returns `None` to keep the name.
+    pub(crate) fn rename(&mut self, f: &impl Fn(&str) -> Option<String>) {
+        match self {
+            Self::Label(name, _) => {
+                if let Some(new_name) = f(name) {
+                    *name = new_name;
+                }
+            }
+            Self::Num(_) | Self::Here => {}
+            Self::Unary(_, v) => v.rename(f),
+            Self::Binary(_, lhs, rhs, _) => {
+                lhs.rename(f);
+                rhs.rename(f);
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for Imm {
+    /// Print the expression with all sub-expressions parenthesized, which can
+    /// be parsed by [`Imm::parse_str`].
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Num(n) if *n < 0 => write!(f, "-{:#x}", n.unsigned_abs()),
+            Self::Num(n) => write!(f, "{n:#x}"),
+            Self::Label(name, _) => write!(f, "{name}"),
+            Self::Here => write!(f, "."),
+            Self::Unary(UnOp::Neg, v) => write!(f, "-({v})"),
+            Self::Unary(UnOp::Not, v) => write!(f, "~({v})"),
+            Self::Binary(op, lhs, rhs, _) => {
+                let op = match op {
+                    BinOp::Or => "|",
+                    BinOp::And => "&",
+                    BinOp::Shl => "<<",
+                    BinOp::Shr => ">>",
+                    BinOp::Add => "+",
+                    BinOp::Sub => "-",
+                    BinOp::Mul => "*",
+                    BinOp::Div => "/",
+                };
+                write!(f, "({lhs} {op} {rhs})")
+            }
         }
     }
 }
 
+/// Contents of a data line.
+#[derive(Debug, Clone)]
+pub enum Data {
+    /// Values of expressions with their size in bytes (`.byte`, `.quad`,
+    /// etc.), placed one after another
+    Num(u8, Vec<Imm>),
+    /// `count` copies of a value of `size` bytes (`.fill`)
+    Fill { count: u64, size: u8, value: Imm },
+    /// Raw bytes, e.g. `.zero`, `.ascii` or read from a `.yo` file
+    Bytes(Vec<u8>),
+}
+
+impl Data {
+    /// Number of bytes occupied by the data.
+    pub fn size(&self) -> usize {
+        match self {
+            Data::Num(sz, values) => *sz as usize * values.len(),
+            Data::Fill { count, size, .. } => *count as usize * *size as usize,
+            Data::Bytes(bytes) => bytes.len(),
+        }
+    }
+
+    /// The expressions of the data, with their offsets and sizes in bytes.
+    pub(crate) fn fields(&self) -> Vec<(u64, u8, &Imm)> {
+        match self {
+            Data::Num(sz, values) => {
+                let offsets = (0..).step_by(*sz as usize);
+                offsets.zip(values).map(|(o, v)| (o, *sz, v)).collect()
+            }
+            Data::Fill { count, size, value } => (0..*count)
+                .map(|i| (i * *size as u64, *size, value))
+                .collect(),
+            Data::Bytes(_) => Vec::new(),
+        }
+    }
+}
+
+/// Decode the escape sequences in a string literal (with quotes), which
+/// starts at `offset` of the line.
+fn parse_string(s: &str, offset: usize) -> Result<Vec<u8>, LineError> {
+    let inner = &s[1..s.len() - 1];
+    let mut bytes = Vec::new();
+    let mut chars = inner.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        if c != '\\' {
+            let mut buf = [0; 4];
+            bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            continue;
+        }
+        let Some((_, e)) = chars.next() else {
+            unreachable!("a string can not end with a backslash")
+        };
+        let byte = match e {
+            'n' => b'\n',
+            't' => b'\t',
+            'r' => b'\r',
+            'a' => 0x07,
+            'b' => 0x08,
+            'f' => 0x0c,
+            'v' => 0x0b,
+            '\\' | '"' | '\'' => e as u8,
+            // up to 2 hexadecimal digits
+            'x' => {
+                let mut value = None;
+                for _ in 0..2 {
+                    match chars.next_if(|(_, c)| c.is_ascii_hexdigit()) {
+                        Some((_, d)) => {
+                            value = Some(value.unwrap_or(0) * 16 + d.to_digit(16).unwrap() as u8)
+                        }
+                        None => break,
+                    }
+                }
+                value.ok_or_else(|| {
+                    LineError::new(
+                        AsmErrorKind::Syntax("expect hexadecimal digits after `\\x`".to_string()),
+                        offset + 1 + i..offset + 3 + i,
+                    )
+                })?
+            }
+            // up to 3 octal digits
+            '0'..='7' => {
+                let mut value = e.to_digit(8).unwrap();
+                for _ in 0..2 {
+                    match chars.next_if(|(_, c)| matches!(c, '0'..='7')) {
+                        Some((_, d)) => value = value * 8 + d.to_digit(8).unwrap(),
+                        None => break,
+                    }
+                }
+                value as u8
+            }
+            _ => {
+                let start = offset + 1 + i;
+                return Err(LineError::new(
+                    AsmErrorKind::Syntax(format!("unknown escape sequence `\\{e}`")),
+                    start..start + 1 + e.len_utf8(),
+                ));
+            }
+        };
+        bytes.push(byte);
+    }
+    Ok(bytes)
+}
+
+/// Named constant defined by `.equ` or `.set`.
+#[derive(Debug, Clone)]
+pub struct Constant {
+    pub name: String,
+    pub expr: Imm,
+    /// defined by `.set`, which can be redefined by another `.set`
+    pub redefinable: bool,
+    /// value of the expression, available after assembling
+    pub value: Option<u64>,
+}
+
 /// Y86 instruction Set.
 ///
 /// During assembling, the type of immediate (`ImmType`) can change.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 #[allow(clippy::upper_case_acronyms)]
 pub enum Inst<ImmType: Clone> {
     HALT,
@@ -186,9 +697,9 @@ pub enum Inst<ImmType: Clone> {
     /// `irmovq rB, V`
     IRMOVQ(Reg, ImmType),
     /// `rmmovq rA, D(rB)`
-    RMMOVQ(Reg, Addr),
+    RMMOVQ(Reg, Addr<ImmType>),
     /// `mrmovq D(rB), rA`
-    MRMOVQ(Addr, Reg),
+    MRMOVQ(Addr<ImmType>, Reg),
     OPQ(OpFn, Reg, Reg),
     JX(CondFn, ImmType),
     CALL(ImmType),
@@ -196,18 +707,26 @@ pub enum Inst<ImmType: Clone> {
     PUSHQ(Reg),
     POPQ(Reg),
     IOPQ(OpFn, ImmType, Reg),
+    /// `jm V, rB`: jump to the address stored at `V(rB)`
+    JM(ImmType, Reg),
+    /// `trap` (or `syscall`): enter the exception handler
+    TRAP,
+    /// `iret`: return from the exception handler
+    IRET,
 }
 
 impl<ImmType: Clone> Inst<ImmType> {
+    /// Number of bytes of the encoded instruction.
+    #[allow(clippy::len_without_is_empty)]
     pub fn len(&self) -> usize {
         use Inst::*;
         match self {
-            HALT | RET | NOP => 1,
+            HALT | RET | NOP | TRAP | IRET => 1,
             OPQ(_, _, _) | CMOVX(_, _, _) | PUSHQ(_) | POPQ(_) => 2,
             JX(_, _) | CALL(_) => 9,
             IRMOVQ(_, _) | RMMOVQ(_, _) | MRMOVQ(_, _) => 10,
             // extended instructions
-            IOPQ(_, _, _) => 10,
+            IOPQ(_, _, _) | JM(_, _) => 10,
         }
     }
 
@@ -228,6 +747,8 @@ impl<ImmType: Clone> Inst<ImmType> {
             Inst::POPQ(_) => POPQ,
             // extended instructions
             Inst::IOPQ(_, _, _) => IOPQ,
+            Inst::JM(_, _) => JM,
+            Inst::TRAP | Inst::IRET => EXC,
         }
     }
 }
@@ -235,6 +756,9 @@ impl<ImmType: Clone> Inst<ImmType> {
 #[derive(Default)]
 pub struct AssembleOption {
     verbose: bool,
+    file_name: Option<String>,
+    allow_overlap: bool,
+    mem_size: Option<usize>,
 }
 
 impl AssembleOption {
@@ -242,6 +766,35 @@ impl AssembleOption {
         self.verbose = verbose;
         self
     }
+
+    /// Set the file name displayed in diagnostics.
+    pub fn set_file_name(mut self, name: impl Into<String>) -> Self {
+        self.file_name = Some(name.into());
+        self
+    }
+
+    /// Report overlapping contents as warnings instead of errors. Bytes of the
+    /// later line in the source take effect.
+    pub fn set_allow_overlap(mut self, allow_overlap: bool) -> Self {
+        self.allow_overlap = allow_overlap;
+        self
+    }
+
+    /// Set the size of the memory the program is placed in, which is
+    /// [`MEM_SIZE`] by default. Contents out of the memory are reported as
+    /// errors.
+    pub fn set_mem_size(mut self, mem_size: usize) -> Self {
+        self.mem_size = Some(mem_size);
+        self
+    }
+
+    pub(crate) fn file_name(&self) -> Option<&str> {
+        self.file_name.as_deref()
+    }
+
+    pub(crate) fn mem_size(&self) -> usize {
+        self.mem_size.unwrap_or(MEM_SIZE)
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -294,13 +847,376 @@ impl<'a> ParseInputs<'a> {
     }
 
     /// Parse next token as address
-    fn next_addr(&mut self) -> Addr {
-        Addr::from(self.next().unwrap())
+    fn next_addr(&mut self) -> Result<Addr<Imm>, LineError> {
+        Addr::parse(self.next().unwrap())
+    }
+
+    /// Parse next token as immediate value
+    fn next_imm(&mut self) -> Result<Imm, LineError> {
+        Imm::parse(self.next().unwrap())
+    }
+}
+
+/// Directives that are handled by the caller of [`assemble_line`], since they
+/// affect the whole file instead of a single line.
+enum Directive {
+    /// `.global`, with the names and their byte offsets in the line
+    Global(Vec<(String, usize)>),
+    /// `.section`, with the name and its byte range in the line
+    Section(String, std::ops::Range<usize>),
+    /// `.align`
+    Align(u64),
+}
+
+/// Parse a line of source code and fill in the line info. `cur_addr` is
+/// updated to the address of the next line, which should be in the memory of
+/// `mem_size` bytes.
+fn assemble_line(
+    src: &str,
+    cur_addr: &mut u64,
+    src_info: &mut LineInfo,
+    mem_size: u64,
+) -> Result<Option<Directive>, LineError> {
+    let mut line = parse_line(src)?.into_iter();
+    if let Some(pair) = line.pairs.peek() {
+        // set addr for instruction or label
+        src_info.addr = Some(*cur_addr);
+
+        if let Rule::label | Rule::local_label = pair.as_rule() {
+            src_info.label = Some(pair.as_str().to_string());
+            // consume this label
+            line.next();
+        }
+    }
+    // if this line has instruction
+    if let Some(pair) = line.next() {
+        let tok2 = pair.clone();
+        let mut it = pair.into_iter();
+        match tok2.as_rule() {
+            Rule::label => {
+                let span = tok2.as_span();
+                return Err(LineError::new(
+                    AsmErrorKind::Syntax("unexpected label".to_string()),
+                    span.start()..span.end(),
+                ));
+            }
+            Rule::i_single => {
+                src_info.inst = Some(match tok2.as_str() {
+                    "halt" => Inst::HALT,
+                    "nop" => Inst::NOP,
+                    "ret" => Inst::RET,
+                    "trap" | "syscall" => Inst::TRAP,
+                    "iret" => Inst::IRET,
+                    _ => panic!("invalid instruction"),
+                });
+                *cur_addr += 1
+            }
+            Rule::i_cmovq => {
+                let cond_fn = CondFn::from(it.next_str());
+                let reg_a = it.next_reg();
+                let reg_b = it.next_reg();
+                src_info.inst = Some(Inst::CMOVX(cond_fn, reg_a, reg_b));
+                *cur_addr += 2
+            }
+            Rule::i_mrmovq => {
+                let addr = it.next_addr()?;
+                let reg = it.next_reg();
+                src_info.inst = Some(Inst::MRMOVQ(addr, reg));
+                *cur_addr += 10
+            }
+            Rule::i_rmmovq => {
+                let reg = it.next_reg();
+                let addr = it.next_addr()?;
+                src_info.inst = Some(Inst::RMMOVQ(reg, addr));
+                *cur_addr += 10
+            }
+            Rule::i_irmovq => {
+                let imm = it.next_imm()?;
+                let reg = it.next_reg();
+                src_info.inst = Some(Inst::IRMOVQ(reg, imm));
+                *cur_addr += 10
+            }
+            Rule::i_opq => {
+                let op_fn = OpFn::from_instname(it.next_str());
+                let reg_a = it.next_reg();
+                let reg_b = it.next_reg();
+                src_info.inst = Some(Inst::OPQ(op_fn, reg_a, reg_b));
+                *cur_addr += 2
+            }
+            Rule::i_iopq => {
+                let op_fn = OpFn::from_instname(it.next_str());
+                let imm = it.next_imm()?;
+                let reg = it.next_reg();
+                src_info.inst = Some(Inst::IOPQ(op_fn, imm, reg));
+                *cur_addr += 10
+            }
+            Rule::i_jx => {
+                let cond_fn = CondFn::from(it.next_str());
+                let imm = it.next_imm()?;
+                src_info.inst = Some(Inst::JX(cond_fn, imm));
+                *cur_addr += 9
+            }
+            Rule::i_jm => {
+                let imm = it.next_imm()?;
+                let reg = it.next_reg();
+                src_info.inst = Some(Inst::JM(imm, reg));
+                *cur_addr += 10
+            }
+            Rule::i_call => {
+                let imm = it.next_imm()?;
+                src_info.inst = Some(Inst::CALL(imm));
+                *cur_addr += 9
+            }
+            Rule::i_pushq => {
+                let reg = it.next_reg();
+                src_info.inst = Some(Inst::PUSHQ(reg));
+                *cur_addr += 2
+            }
+            Rule::i_popq => {
+                let reg = it.next_reg();
+                src_info.inst = Some(Inst::POPQ(reg));
+                *cur_addr += 2
+            }
+            Rule::d_pos => {
+                let pair = it.next().unwrap();
+                let num = parse_num(&pair)?;
+                if num > mem_size {
+                    let span = pair.as_span();
+                    return Err(LineError::new(
+                        AsmErrorKind::AddressOutOfRange(num),
+                        span.start()..span.end(),
+                    ));
+                }
+                *cur_addr = num;
+                src_info.addr = Some(*cur_addr) // override
+            }
+            Rule::d_data => {
+                let sz = match tok2.as_str() {
+                    s if s.starts_with(".quad") => 8,
+                    s if s.starts_with(".long") => 4,
+                    s if s.starts_with(".word") => 2,
+                    s if s.starts_with(".byte") => 1,
+                    s => {
+                        return Err(LineError::new(
+                            AsmErrorKind::Syntax(format!("invalid data directive: {}", s)),
+                            tok2.as_span().start()..tok2.as_span().end(),
+                        ))
+                    }
+                };
+                let values = it
+                    .map(|pair| Imm::parse(pair))
+                    .collect::<Result<Vec<_>, _>>()?;
+                *cur_addr += sz as u64 * values.len() as u64;
+                src_info.data = Some(Data::Num(sz, values));
+            }
+            Rule::d_zero | Rule::d_fill => {
+                let pair = it.next().unwrap();
+                let count = parse_num(&pair)?;
+                let size = match it.next() {
+                    Some(pair) => match parse_num(&pair)? {
+                        size @ (1 | 2 | 4 | 8) => size as u8,
+                        size => {
+                            let span = pair.as_span();
+                            return Err(LineError::new(
+                                AsmErrorKind::Syntax(format!(
+                                    "invalid fill size {size}, expect 1, 2, 4 or 8"
+                                )),
+                                span.start()..span.end(),
+                            ));
+                        }
+                    },
+                    None => 1,
+                };
+                let total = count.saturating_mul(size as u64);
+                if total > mem_size {
+                    let span = pair.as_span();
+                    return Err(LineError::new(
+                        AsmErrorKind::AddressOutOfRange(cur_addr.saturating_add(total)),
+                        span.start()..span.end(),
+                    ));
+                }
+                src_info.data = Some(match (tok2.as_rule(), it.next()) {
+                    (Rule::d_fill, Some(pair)) => Data::Fill {
+                        count,
+                        size,
+                        value: Imm::parse(pair)?,
+                    },
+                    _ => Data::Bytes(vec![0; total as usize]),
+                });
+                *cur_addr += total;
+            }
+            Rule::d_ascii => {
+                let dir = tok2.as_str();
+                let zero = !dir.starts_with(".ascii") || dir.starts_with(".asciz");
+                let mut bytes = Vec::new();
+                for pair in it {
+                    bytes.extend(parse_string(pair.as_str(), pair.as_span().start())?);
+                    if zero {
+                        bytes.push(0);
+                    }
+                }
+                *cur_addr += bytes.len() as u64;
+                src_info.data = Some(Data::Bytes(bytes));
+            }
+            Rule::d_equ => {
+                let redefinable = tok2.as_str().starts_with(".set");
+                let name = it.next_str().to_string();
+                let expr = it.next_imm()?;
+                src_info.constant = Some(Constant {
+                    name,
+                    expr,
+                    redefinable,
+                    value: None,
+                });
+            }
+            Rule::d_align => {
+                let pair = it.next().unwrap();
+                let num = parse_num(&pair)?;
+                let span = pair.as_span();
+                if num.count_ones() != 1 {
+                    return Err(LineError::new(
+                        AsmErrorKind::InvalidAlign(num),
+                        span.start()..span.end(),
+                    ));
+                }
+                if !cur_addr.is_multiple_of(num) {
+                    // ceil
+                    *cur_addr = (*cur_addr / num * num).checked_add(num).ok_or_else(|| {
+                        LineError::new(
+                            AsmErrorKind::AddressOutOfRange(*cur_addr),
+                            span.start()..span.end(),
+                        )
+                    })?
+                }
+                src_info.addr = Some(*cur_addr); // override
+                return Ok(Some(Directive::Align(num)));
+            }
+            Rule::d_global => {
+                let names = it
+                    .map(|pair| (pair.as_str().to_string(), pair.as_span().start()))
+                    .collect();
+                return Ok(Some(Directive::Global(names)));
+            }
+            Rule::d_section => {
+                let pair = it.next().unwrap();
+                let span = pair.as_span();
+                let name = pair.as_str().to_string();
+                return Ok(Some(Directive::Section(name, span.start()..span.end())));
+            }
+            _ => unimplemented!(),
+        }
+    }
+    Ok(None)
+}
+
+/// Find the lines whose contents overlap, which returns the index of the later
+/// line, the index of the earlier line and the overlapped byte range. Only
+/// lines in the same section (given by `sections` for each line) can overlap.
+fn find_overlaps(src_infos: &[LineInfo], sections: &[usize]) -> Vec<(usize, usize, u64, u64)> {
+    let mut regions: Vec<_> = src_infos
+        .iter()
+        .enumerate()
+        .filter(|(_, info)| info.size() > 0)
+        .filter_map(|(index, info)| {
+            let start = info.addr?;
+            Some((sections[index], start, start + info.size() as u64, index))
+        })
+        .collect();
+    regions.sort();
+
+    let mut overlaps = Vec::new();
+    // the region which reaches the furthest so far in the section
+    let mut furthest: Option<(usize, u64, usize)> = None;
+    for (section, start, end, index) in regions {
+        if let Some((_, prev_end, prev)) = furthest.filter(|f| f.0 == section) {
+            if start < prev_end {
+                let (later, earlier) = (index.max(prev), index.min(prev));
+                overlaps.push((later, earlier, start, end.min(prev_end)));
+            }
+            if end <= prev_end {
+                continue;
+            }
+        }
+        furthest = Some((section, end, index));
+    }
+    overlaps
+}
+
+/// Evaluate the constants defined by `.equ` and `.set`, which returns the
+/// results indexed by line.
+///
+/// Constants are evaluated in the order of lines, so `.set` can refer to the
+/// previous value of the same constant. Since a constant may also refer to
+/// constants defined later, the unresolved ones are retried until no more
+/// progress can be made.
+fn resolve_constants(
+    src_infos: &[LineInfo],
+    labels: &SymbolMap,
+) -> BTreeMap<usize, Result<u64, LineError>> {
+    let mut results = BTreeMap::new();
+    let mut last_values = labels.clone();
+    loop {
+        let mut progress = false;
+        let mut scope = last_values.clone();
+        for (index, info) in src_infos.iter().enumerate() {
+            let Some(constant) = &info.constant else {
+                continue;
+            };
+            if !matches!(results.get(&index), Some(Ok(_))) {
+                let result = constant.expr.eval(&scope, info.addr.unwrap_or_default());
+                progress |= result.is_ok();
+                results.insert(index, result);
+            }
+            if let Some(Ok(value)) = results.get(&index) {
+                scope.insert(constant.name.clone(), *value);
+            }
+        }
+        last_values = scope;
+        if !progress {
+            return results;
+        }
     }
 }
 
 /// transform assembly code to binary object code
-pub fn assemble(src: &str, option: AssembleOption) -> Result<ObjectExt> {
+///
+/// All errors in the source file are collected and reported together.
+pub fn assemble(src: &str, option: AssembleOption) -> Result<ObjectExt, AsmErrors> {
+    assemble_impl(src, &option, false).map(|(obj, _)| obj)
+}
+
+/// Assemble the source file into a relocatable object, which can be linked
+/// with other objects by [`crate::link::link`].
+///
+/// Labels are placed at offsets in their sections. Symbols that are used but
+/// not defined are imported from other objects.
+pub fn assemble_relocatable(src: &str, option: AssembleOption) -> Result<RelocObject, AsmErrors> {
+    assemble_impl(src, &option, true).map(|(_, obj)| obj)
+}
+
+/// Replace the constants in the expression. Constants that depend on the
+/// locations of contents are replaced by their expressions, and others by
+/// their values.
+fn substitute_constants(
+    imm: &Imm,
+    exprs: &BTreeMap<String, Imm>,
+    values: &SymbolMap,
+    constants: &BTreeSet<String>,
+) -> Imm {
+    imm.substitute(&|name| {
+        exprs.get(name).cloned().or_else(|| {
+            constants
+                .contains(name)
+                .then(|| Imm::Num(values.get(name).copied().unwrap_or_default() as i64))
+        })
+    })
+}
+
+fn assemble_impl(
+    src: &str,
+    option: &AssembleOption,
+    relocatable: bool,
+) -> Result<(ObjectExt, RelocObject), AsmErrors> {
     macro_rules! verbo {
         ($e:expr) => {
             if option.verbose {
@@ -308,170 +1224,360 @@ pub fn assemble(src: &str, option: AssembleOption) -> Result<ObjectExt> {
             }
         };
     }
+    let file = option.file_name.as_deref().unwrap_or("<source>");
+    let mem_size = option.mem_size();
+    let mut errors = Vec::new();
     let mut src_infos = Vec::default();
-    let lines = ParseInput {
-        pair: parse(src).context("fail to assemble ys file")?,
-    };
     let mut cur_addr = u64::default();
+    // name, location counter and alignment of each section
+    let mut sections = vec![(".text".to_string(), 0, 1)];
+    let mut cur_section = 0;
+    // section of each line
+    let mut line_sections = Vec::new();
+    // names exported by `.global`, with the index and offset in the line
+    let mut globals = Vec::new();
 
-    for line in lines {
-        let src = line.pair.as_str().to_string();
-        let mut line = line.into_iter();
+    let expansion = preprocess::expand(src, file, option.file_name.is_some(), mem_size);
+    let files = expansion.files;
+    for (loc, src, err) in expansion.errors {
+        errors.push(AsmError::new(&files[loc.file], loc.line, &src, err));
+    }
+
+    for line in expansion.lines {
         let mut src_info = LineInfo {
+            file: line.loc.file,
+            line: line.loc.line,
             addr: None,
             inst: None,
             label: None,
             data: None,
-            src,
+            constant: None,
+            src: line.src,
         };
-        if let Some(pair) = line.pairs.peek() {
-            // set addr for instruction or label
-            src_info.addr = Some(cur_addr);
-
-            if let Rule::label = pair.as_rule() {
-                src_info.label = Some(pair.as_str().to_string());
-                // consume this label
-                line.next();
-            }
-        }
-        // if this line has instruction
-        if let Some(pair) = line.next() {
-            verbo!(&pair);
-            let tok2 = pair.clone();
-            let mut it = pair.into_iter();
-            match tok2.as_rule() {
-                Rule::label => bail!("unexpected label"),
-                Rule::i_single => {
-                    src_info.inst = Some(match tok2.as_str() {
-                        "halt" => Inst::HALT,
-                        "nop" => Inst::NOP,
-                        "ret" => Inst::RET,
-                        _ => panic!("invalid instruction"),
-                    });
-                    cur_addr += 1
-                }
-                Rule::i_cmovq => {
-                    let cond_fn = CondFn::from(it.next_str());
-                    let reg_a = it.next_reg();
-                    let reg_b = it.next_reg();
-                    src_info.inst = Some(Inst::CMOVX(cond_fn, reg_a, reg_b));
-                    cur_addr += 2
-                }
-                Rule::i_mrmovq => {
-                    let addr = it.next_addr();
-                    let reg = it.next_reg();
-                    src_info.inst = Some(Inst::MRMOVQ(addr, reg));
-                    cur_addr += 10
-                }
-                Rule::i_rmmovq => {
-                    let reg = it.next_reg();
-                    let addr = it.next_addr();
-                    src_info.inst = Some(Inst::RMMOVQ(reg, addr));
-                    cur_addr += 10
-                }
-                Rule::i_irmovq => {
-                    let imm = Imm::from(it.next().unwrap());
-                    let reg = it.next_reg();
-                    src_info.inst = Some(Inst::IRMOVQ(reg, imm));
-                    cur_addr += 10
-                }
-                Rule::i_opq => {
-                    let reg_a = it.next_reg();
-                    let reg_b = it.next_reg();
-                    let op_fn = OpFn::from_instname(tok2.as_str());
-                    src_info.inst = Some(Inst::OPQ(op_fn, reg_a, reg_b));
-                    cur_addr += 2
-                }
-                Rule::i_iopq => {
-                    let imm = Imm::from(it.next().unwrap());
-                    let reg = it.next_reg();
-                    let op_fn = OpFn::from_instname(tok2.as_str());
-                    src_info.inst = Some(Inst::IOPQ(op_fn, imm, reg));
-                    cur_addr += 10
-                }
-                Rule::i_jx => {
-                    let cond_fn = CondFn::from(it.next_str());
-                    let imm = Imm::from(it.next().unwrap());
-                    src_info.inst = Some(Inst::JX(cond_fn, imm));
-                    cur_addr += 9
+        if let Some(code) = &line.code {
+            let error = |err| AsmError::new(&files[line.loc.file], line.loc.line, code, err);
+            match assemble_line(code, &mut cur_addr, &mut src_info, mem_size as u64) {
+                Ok(None) => {}
+                Ok(Some(Directive::Align(num))) => {
+                    let align = &mut sections[cur_section].2;
+                    *align = num.max(*align);
                 }
-                Rule::i_call => {
-                    let imm = Imm::from(it.next().unwrap());
-                    src_info.inst = Some(Inst::CALL(imm));
-                    cur_addr += 9
+                Ok(Some(Directive::Global(names))) => {
+                    globals.extend(names.into_iter().map(|(n, o)| (n, src_infos.len(), o)));
                 }
-                Rule::i_pushq => {
-                    let reg = it.next_reg();
-                    src_info.inst = Some(Inst::PUSHQ(reg));
-                    cur_addr += 2
+                Ok(Some(Directive::Section(name, _))) if relocatable => {
+                    sections[cur_section].1 = cur_addr;
+                    cur_section = match sections.iter().position(|s| s.0 == name) {
+                        Some(index) => index,
+                        None => {
+                            sections.push((name, 0, 1));
+                            sections.len() - 1
+                        }
+                    };
+                    cur_addr = sections[cur_section].1;
+                    src_info.addr = Some(cur_addr);
                 }
-                Rule::i_popq => {
-                    let reg = it.next_reg();
-                    src_info.inst = Some(Inst::POPQ(reg));
-                    cur_addr += 2
-                }
-                Rule::d_pos => {
-                    let s = it.next_str();
-                    let num = utils::parse_literal(s).unwrap();
-                    cur_addr = num;
-                    src_info.addr = Some(cur_addr) // override
-                }
-                Rule::d_data => {
-                    let imm = Imm::from(it.next().unwrap());
-                    if tok2.as_str().starts_with(".quad") {
-                        src_info.data = Some((8, imm));
-                        cur_addr += 8;
-                    } else if tok2.as_str().starts_with(".long") {
-                        src_info.data = Some((4, imm));
-                        cur_addr += 4;
-                    } else if tok2.as_str().starts_with(".word") {
-                        src_info.data = Some((2, imm));
-                        cur_addr += 2;
-                    } else if tok2.as_str().starts_with(".byte") {
-                        src_info.data = Some((1, imm));
-                        cur_addr += 1;
-                    } else {
-                        bail!("invalid data directive: {}", tok2.as_str())
-                    }
-                }
-                Rule::d_align => {
-                    let s = it.next_str();
-                    let num = utils::parse_literal(s).unwrap();
-                    anyhow::ensure!(num.count_ones() == 1, "invalid align number");
-                    if cur_addr % num > 0 {
-                        cur_addr = cur_addr / num * num + num // ceil
+                Ok(Some(Directive::Section(_, range))) => errors.push(error(LineError::new(
+                    AsmErrorKind::RelocatableOnly(".section".to_string()),
+                    range,
+                ))),
+                Err(err) => {
+                    // keep the label defined to avoid reporting its uses
+                    if let Some((_, label)) = leading_label(code) {
+                        src_info.label = Some(label.to_string());
+                        src_info.addr = Some(cur_addr);
                     }
-                    src_info.addr = Some(cur_addr) // override
+                    errors.push(error(err));
                 }
-                _ => unimplemented!(),
             }
         }
         verbo!(&src_info);
         src_infos.push(src_info);
+        line_sections.push(cur_section);
     }
-    let mut obj = Object::default();
+
+    resolve_local_labels(&mut src_infos);
+    let mut obj = Object::new(mem_size);
+    // addresses of all labels, including numeric local labels
+    let mut labels = SymbolMap::new();
+    // name => (file, line number, whether it is defined by `.set`, whether it
+    // is a label)
+    let mut defined = BTreeMap::new();
+    let mut warnings = Vec::new();
     for info in &src_infos {
-        if let Some(label) = &info.label {
-            obj.symbols.insert(label.clone(), info.addr.unwrap());
+        let label = info.label.as_ref().map(|l| (l, false));
+        let constant = info.constant.as_ref().map(|c| (&c.name, c.redefinable));
+        for (name, redefinable) in label.into_iter().chain(constant) {
+            let is_label = info.label.as_ref() == Some(name);
+            match defined.get(name) {
+                Some(&(_, _, true, _)) if redefinable => {}
+                // a redefined label is only a warning, and the last definition
+                // is used as by CS:APP `yas`
+                Some(&(prev_file, prev, _, true)) if is_label => {
+                    let (offset, _) = leading_label(&info.src).unwrap_or_default();
+                    let err = LineError::new(
+                        AsmErrorKind::DuplicateLabel(name.clone()),
+                        offset..offset + name.len(),
+                    )
+                    .with_suggestion(Some(match prev_file == info.file {
+                        true => format!("also defined at line {prev}, the last definition is used"),
+                        false => format!(
+                            "also defined at {}:{prev}, the last definition is used",
+                            files[prev_file]
+                        ),
+                    }));
+                    let err = AsmError::new(&files[info.file], info.line, &info.src, err);
+                    warnings.push(err.into_warning());
+                    labels.insert(name.clone(), info.addr.unwrap());
+                }
+                Some(&(prev_file, prev, _, _)) => {
+                    let offset = match leading_label(&info.src) {
+                        Some((offset, label)) if label == name => offset,
+                        // the name of the constant follows the directive
+                        _ => {
+                            let dir = info.src.find(".equ").or(info.src.find(".set"));
+                            let dir = dir.map_or(0, |i| i + 4);
+                            dir + info.src[dir..].find(name.as_str()).unwrap_or_default()
+                        }
+                    };
+                    let err = LineError::new(
+                        AsmErrorKind::DuplicateLabel(name.clone()),
+                        offset..offset + name.len(),
+                    )
+                    .with_suggestion(Some(match prev_file == info.file {
+                        true => format!("first defined at line {prev}"),
+                        false => format!("first defined at {}:{prev}", files[prev_file]),
+                    }));
+                    errors.push(AsmError::new(&files[info.file], info.line, &info.src, err));
+                }
+                None => {
+                    defined.insert(name.clone(), (info.file, info.line, redefinable, is_label));
+                    if is_label {
+                        labels.insert(name.clone(), info.addr.unwrap());
+                    }
+                }
+            }
         }
     }
+    obj.symbols = labels.clone();
+    obj.symbols.retain(|name, _| !is_local_label(name));
     verbo!(&obj.symbols);
 
-    for it in &src_infos {
-        it.write_object(&mut obj)
+    let constant_names: BTreeSet<String> = src_infos
+        .iter()
+        .filter_map(|info| Some(info.constant.as_ref()?.name.clone()))
+        .collect();
+    // symbols imported from other objects, which are treated as 0 until linked
+    let mut imports = BTreeSet::new();
+    if relocatable {
+        let exprs = src_infos.iter().flat_map(|info| {
+            let values = info.value_fields().into_iter().map(|(_, _, imm)| imm);
+            values.chain(info.constant.as_ref().map(|c| &c.expr))
+        });
+        let used = exprs.flat_map(|imm| imm.symbols());
+        for name in used.chain(globals.iter().map(|(n, _, _)| n.as_str())) {
+            let defined = labels.contains_key(name) || constant_names.contains(name);
+            if !defined && !is_local_label(name) {
+                imports.insert(name.to_string());
+            }
+        }
+        for (name, index, offset) in &globals {
+            if constant_names.contains(name) {
+                let info = &src_infos[*index];
+                let err = LineError::new(
+                    AsmErrorKind::NotRelocatable(format!("constant `{name}`")),
+                    *offset..offset + name.len(),
+                )
+                .with_suggestion(Some("only labels can be exported".to_string()));
+                errors.push(AsmError::new(&files[info.file], info.line, &info.src, err));
+            }
+        }
     }
+    labels.extend(imports.iter().map(|name| (name.clone(), 0)));
 
-    Ok(ObjectExt {
-        obj,
-        source: SourceInfo::from(src_infos),
-    })
+    for (index, result) in resolve_constants(&src_infos, &labels) {
+        let info = &mut src_infos[index];
+        let constant = info.constant.as_mut().unwrap();
+        match result {
+            Ok(value) => {
+                constant.value = Some(value);
+                obj.constants.insert(constant.name.clone(), value);
+            }
+            Err(err) => errors.push(AsmError::new(&files[info.file], info.line, &info.src, err)),
+        }
+    }
+    verbo!(&obj.constants);
+
+    // constants used before their definitions take the last defined values
+    let mut scope = labels;
+    scope.extend(obj.constants.clone());
+    // contents of each section in relocatable objects
+    let mut section_objs: Vec<Object> = match relocatable {
+        true => sections.iter().map(|_| Object::new(mem_size)).collect(),
+        false => Vec::new(),
+    };
+    // expressions of constants that depend on the locations of contents
+    let mut reloc_exprs = BTreeMap::new();
+    if relocatable {
+        for constant in src_infos.iter().filter_map(|info| info.constant.as_ref()) {
+            let expr = substitute_constants(&constant.expr, &reloc_exprs, &scope, &constant_names);
+            if expr.uses_here() || expr.symbols().is_empty() {
+                reloc_exprs.remove(&constant.name);
+            } else {
+                reloc_exprs.insert(constant.name.clone(), expr);
+            }
+        }
+    }
+    let mut relocs = Vec::new();
+    for (index, it) in src_infos.iter().enumerate() {
+        let section = line_sections[index];
+        if let Some(constant) = &it.constant {
+            // avoid reporting uses of unresolved constants
+            scope.insert(constant.name.clone(), constant.value.unwrap_or_default());
+            if relocatable {
+                let expr =
+                    substitute_constants(&constant.expr, &reloc_exprs, &scope, &constant_names);
+                // constants using `.` are taken as distances in the section,
+                // e.g. `. - start`
+                if expr.uses_here() && expr.symbols().is_empty() {
+                    let err = LineError::new(
+                        AsmErrorKind::NotRelocatable("`.` in constants".to_string()),
+                        content_range(&it.src),
+                    );
+                    errors.push(AsmError::new(&files[it.file], it.line, &it.src, err));
+                } else if expr.uses_here() || expr.symbols().is_empty() {
+                    reloc_exprs.remove(&constant.name);
+                } else {
+                    reloc_exprs.insert(constant.name.clone(), expr);
+                }
+            }
+        }
+        let target = match relocatable {
+            true => &mut section_objs[section],
+            false => &mut obj,
+        };
+        if let Err(err) = it.write_object(target, &scope) {
+            errors.push(AsmError::new(&files[it.file], it.line, &it.src, err));
+        }
+        let Some(addr) = it.addr.filter(|_| relocatable) else {
+            continue;
+        };
+        for (field, size, imm) in it.value_fields() {
+            let expr = substitute_constants(imm, &reloc_exprs, &scope, &constant_names);
+            if expr.uses_here() || !expr.symbols().is_empty() {
+                relocs.push(Reloc {
+                    section,
+                    offset: addr + field,
+                    size,
+                    here: addr,
+                    expr,
+                });
+            }
+        }
+    }
+
+    for (index, other, start, end) in find_overlaps(&src_infos, &line_sections) {
+        let (info, other) = (&src_infos[index], &src_infos[other]);
+        let location = match info.file == other.file {
+            true => format!("line {}", other.line),
+            false => format!("{}:{}", files[other.file], other.line),
+        };
+        let err = LineError::new(
+            AsmErrorKind::Overlap { start, end },
+            content_range(&info.src),
+        )
+        .with_suggestion(Some(format!(
+            "overlaps with {location}: `{}`",
+            other.src.trim()
+        )));
+        let err = AsmError::new(&files[info.file], info.line, &info.src, err);
+        match option.allow_overlap {
+            true => warnings.push(err.into_warning()),
+            false => errors.push(err),
+        }
+    }
+
+    // errors in generated lines are reported at their invocations
+    let locate = |e: AsmError| {
+        let invocation = expansion
+            .invocations
+            .iter()
+            .find(|it| files[it.loc.file] == e.file && it.loc.line == e.line && it.src != e.src);
+        match invocation {
+            Some(it) => e.in_expansion(&it.origin, &it.src),
+            None => e,
+        }
+    };
+    let mut errors: Vec<_> = errors.into_iter().map(locate).collect();
+    warnings = warnings.into_iter().map(locate).collect();
+    let sort_key = |e: &AsmError| (files.iter().position(|f| *f == e.file), e.line);
+    if !errors.is_empty() {
+        errors.sort_by_key(sort_key);
+        return Err(AsmErrors::new(file, errors));
+    }
+    warnings.sort_by_key(sort_key);
+
+    let mut reloc_obj = RelocObject::default();
+    if relocatable {
+        for (index, (name, _, align)) in sections.iter().enumerate() {
+            let lines: Vec<LineInfo> = src_infos
+                .iter()
+                .zip(&line_sections)
+                .filter(|(_, s)| **s == index)
+                .map(|(info, _)| info.clone())
+                .collect();
+            let size = lines
+                .iter()
+                .filter_map(|info| Some(info.addr? + info.size() as u64))
+                .max()
+                .unwrap_or_default() as usize;
+            reloc_obj.sections.push(Section {
+                name: name.clone(),
+                align: *align,
+                bytes: section_objs[index].binary()[..size].to_vec(),
+                lines,
+            });
+        }
+        for (info, section) in src_infos.iter().zip(&line_sections) {
+            if let (Some(name), Some(addr)) = (&info.label, info.addr) {
+                let global = globals.iter().any(|(n, _, _)| n == name);
+                reloc_obj.symbols.push(Symbol {
+                    name: name.clone(),
+                    binding: if global {
+                        Binding::Global
+                    } else {
+                        Binding::Local
+                    },
+                    def: Some((*section, addr)),
+                });
+            }
+        }
+        reloc_obj
+            .symbols
+            .extend(imports.into_iter().map(|name| Symbol {
+                name,
+                binding: Binding::Global,
+                def: None,
+            }));
+        reloc_obj.relocs = relocs;
+        reloc_obj.warnings = warnings.clone();
+    }
+
+    Ok((
+        ObjectExt {
+            obj,
+            source: SourceInfo::new(src_infos, files),
+            warnings,
+        },
+        reloc_obj,
+    ))
 }
 
 #[cfg(test)]
 pub mod tests {
     use pest::Parser;
 
-    use super::{Rule, Y86AsmParser};
+    use super::{AsmErrorKind, Rule, Y86AsmParser};
 
     pub const RSUM_YS: &str = r#"
 # Weiyao Huang 2200012952
@@ -519,6 +1625,389 @@ ele3:
 stack: # start of stack
 "#;
 
+    #[test]
+    fn test_errors() {
+        let src = "    irmovq stack, %rsp\n    call sum_lst\nsum_list:\n    irmovqq $1, %rax\n    .quad 99999999999999999999\n    .pos 0x10000\nstack:\n    ret\n";
+        let errors = crate::assemble(src, crate::AssembleOption::default().set_file_name("a.ys"))
+            .err()
+            .unwrap();
+        let kinds: Vec<_> = errors.iter().map(|e| (e.line, e.kind.clone())).collect();
+        assert_eq!(
+            kinds,
+            [
+                (2, AsmErrorKind::UndefinedSymbol("sum_lst".into())),
+                (4, AsmErrorKind::UnknownInstruction("irmovqq".into())),
+                (
+                    5,
+                    AsmErrorKind::InvalidNumber("99999999999999999999".into())
+                ),
+                (8, AsmErrorKind::AddressOutOfRange(0x10000)),
+            ]
+        );
+        let e = &errors.errors[0];
+        assert_eq!((e.file.as_str(), e.column, e.len), ("a.ys", 10, 7));
+        assert_eq!(e.suggestion.as_deref(), Some("did you mean `sum_list`?"));
+
+        let rendered = errors.to_string();
+        assert!(rendered.contains(" --> a.ys:2:10\n"));
+        assert!(rendered.contains("  |          ^^^^^^^ help: did you mean `sum_list`?"));
+        assert!(rendered.ends_with("could not assemble `a.ys` due to 4 previous errors\n"));
+    }
+
+    #[test]
+    fn test_expressions() {
+        let src = "start:\n    irmovq $end-start, %rax\n    mrmovq data+8(%rdi), %rbx\n    iaddq (1 << 4) | 3, %rcx\n    .align 8\ndata:\n    .quad data+2*8\n    .quad . - data\n    .byte -1\nend:\n";
+        let r = crate::assemble(src, crate::AssembleOption::default()).unwrap();
+        let mem = r.obj.init_mem();
+        let quad = |addr: usize| crate::utils::get_u64(&mem[addr..addr + 8]);
+        assert_eq!(quad(2), 49);
+        assert_eq!(quad(12), 40);
+        assert_eq!(quad(22), 0x13);
+        assert_eq!((quad(32), quad(40), mem[48]), (48, 8, 0xff));
+
+        let src =
+            "    irmovq $1/(2-2), %rax\n    .byte 0x100\n    .quad end - 1\n    .quad 1 << 64\n";
+        let errors = crate::assemble(src, crate::AssembleOption::default())
+            .err()
+            .unwrap();
+        let kinds: Vec<_> = errors
+            .iter()
+            .map(|e| (e.line, e.column, e.kind.clone()))
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                (
+                    1,
+                    14,
+                    AsmErrorKind::InvalidExpression("division by zero".into())
+                ),
+                (
+                    2,
+                    5,
+                    AsmErrorKind::ValueOutOfRange {
+                        value: 0x100,
+                        size: 1
+                    }
+                ),
+                (3, 11, AsmErrorKind::UndefinedSymbol("end".into())),
+                (
+                    4,
+                    13,
+                    AsmErrorKind::InvalidExpression("shift amount 64 is out of range".into())
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_data() {
+        let src = r##"arr: .quad 1, arr, -1
+    .word 0x1234, 0x5678
+msg: .string "a\n\x41\101\"#", "b"
+    .ascii "c"
+    .zero 3
+    .fill 2, 4, 0x11223344
+    .fill 2
+end:
+"##;
+        let r = crate::assemble(src, crate::AssembleOption::default()).unwrap();
+        let mem = r.obj.init_mem();
+        let quad = |addr: usize| crate::utils::get_u64(&mem[addr..addr + 8]);
+        assert_eq!((quad(0), quad(8), quad(16)), (1, 0, u64::MAX));
+        assert_eq!(mem[24..28], [0x34, 0x12, 0x78, 0x56]);
+        assert_eq!(r.obj.symbols["msg"], 28);
+        assert_eq!(mem[28..37], *b"a\nAA\"#\0b\0");
+        assert_eq!(mem[37..41], [b'c', 0, 0, 0]);
+        assert_eq!(
+            mem[41..49],
+            [0x44, 0x33, 0x22, 0x11, 0x44, 0x33, 0x22, 0x11]
+        );
+        assert_eq!(r.obj.symbols["end"], 51);
+
+        let src = "    .string \"\\q\"\n    .fill 1, 9, 0\n    .quad\n    .zero 0x10001\n\
+                   .fill 3, 0, 1\n.fill 0xffffffffffffffff, 0, 1\n.fill 0x2001, 8, 1\n";
+        let errors = crate::assemble(src, crate::AssembleOption::default())
+            .err()
+            .unwrap();
+        let kinds: Vec<_> = errors.iter().map(|e| (e.line, e.column)).collect();
+        assert_eq!(
+            kinds,
+            [(1, 14), (2, 14), (3, 10), (4, 11), (5, 10), (6, 27), (7, 7)]
+        );
+    }
+
+    #[test]
+    fn test_local_labels() {
+        let src = "start:\n1:  iaddq $-1, %rcx\n    jne 1b\n    jmp 1f\n1:\n2:  .quad 1b, 2f, 2b\n2:  .equ len, 2b - 1b\n";
+        let r = crate::assemble(src, crate::AssembleOption::default()).unwrap();
+        let mem = r.obj.init_mem();
+        let quad = |addr: usize| crate::utils::get_u64(&mem[addr..addr + 8]);
+        assert_eq!((quad(11), quad(20)), (0, 28));
+        assert_eq!((quad(28), quad(36), quad(44)), (28, 52, 28));
+        assert_eq!(r.obj.constants["len"], 24);
+        // local labels are not exported
+        assert_eq!(r.obj.symbols.keys().collect::<Vec<_>>(), ["start"]);
+        assert_eq!(r.source.lines()[1].label.as_deref(), Some("1$1"));
+
+        let src = "1:  jmp 2b\n    jmp 1f\n";
+        let errors = crate::assemble(src, crate::AssembleOption::default())
+            .err()
+            .unwrap();
+        let kinds: Vec<_> = errors.iter().map(|e| (e.line, e.kind.clone())).collect();
+        assert_eq!(
+            kinds,
+            [
+                (1, AsmErrorKind::UndefinedSymbol("2b".into())),
+                (2, AsmErrorKind::UndefinedSymbol("1f".into())),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_jump_targets() {
+        let src = "    jmp 0x20\n    call 8 * 4\n    rmmovq %rax, 0x100\n    jm $8, %rbx\n";
+        let r = crate::assemble(src, crate::AssembleOption::default()).unwrap();
+        let mem = r.obj.init_mem();
+        assert_eq!(mem[..2], [0x70, 0x20]);
+        assert_eq!(mem[9..11], [0x80, 0x20]);
+        assert_eq!(mem[18..22], [0x40, 0x0f, 0x00, 0x01]);
+        assert_eq!(mem[28..31], [0xd0, 0xf3, 0x08]);
+        assert_eq!(r.source.lines()[3].inst.as_ref().unwrap().len(), 10);
+    }
+
+    #[test]
+    fn test_constants() {
+        let src = "    .equ STRIDE, 8 * UNROLL\n    .equ UNROLL, 4\n    .set N, 1\nstart:\n    iaddq $STRIDE, %rdi\n    irmovq N, %rax\n    .set N, N + 1\n    irmovq $N, %rbx\n";
+        let r = crate::assemble(src, crate::AssembleOption::default()).unwrap();
+        let mem = r.obj.init_mem();
+        let quad = |addr: usize| crate::utils::get_u64(&mem[addr..addr + 8]);
+        assert_eq!((quad(2), quad(12), quad(22)), (32, 1, 2));
+        assert_eq!(r.obj.constants["N"], 2);
+        assert!(!r.obj.symbols.contains_key("STRIDE"));
+        assert!(r.obj.symbols.contains_key("start"));
+        assert!(r
+            .to_string()
+            .contains("        = 0x20               |     .equ STRIDE"));
+
+        let src = "start:\n    .equ start, 1\n    .set N, M\n    .equ N, 2\n";
+        let errors = crate::assemble(src, crate::AssembleOption::default())
+            .err()
+            .unwrap();
+        let kinds: Vec<_> = errors
+            .iter()
+            .map(|e| (e.line, e.column, e.kind.clone()))
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                (2, 10, AsmErrorKind::DuplicateLabel("start".into())),
+                (3, 13, AsmErrorKind::UndefinedSymbol("M".into())),
+                (4, 10, AsmErrorKind::DuplicateLabel("N".into())),
+            ]
+        );
+
+        // a redefined label is a warning, and the last definition is used
+        let src = "    jmp a\na:\n    nop\na:\n    halt\n";
+        let r = crate::assemble(src, crate::AssembleOption::default()).unwrap();
+        let warnings: Vec<_> = r
+            .warnings
+            .iter()
+            .map(|e| (e.line, e.kind.clone()))
+            .collect();
+        assert_eq!(warnings, [(4, AsmErrorKind::DuplicateLabel("a".into()))]);
+        assert_eq!(r.obj.symbols["a"], 0xa);
+        assert_eq!(r.obj.init_mem()[1], 0xa);
+    }
+
+    #[test]
+    fn test_macros() {
+        let src = r"
+.macro copy src, dst, off=0
+    mrmovq \off(\src), %r8
+    andq %r8, %r8
+    jle skip
+    iaddq $1, %rax
+skip:
+    rmmovq %r8, \off(\dst)
+.endm
+start: copy %rdi, %rsi
+    copy %rdi, %rsi, 8
+    .rept 2
+    nop
+    .endr
+    .irp reg, %rbx, %rcx
+    pushq \reg
+    .endr
+";
+        let r = crate::assemble(src, crate::AssembleOption::default()).unwrap();
+        let mem = r.obj.init_mem();
+        let quad = |addr: usize| crate::utils::get_u64(&mem[addr..addr + 8]);
+        assert_eq!((quad(13), quad(54), quad(74)), (31, 72, 8));
+        assert_eq!(&mem[82..88], [0x10, 0x10, 0xa0, 0x3f, 0xa0, 0x1f]);
+        assert_eq!(r.obj.symbols["skip__1"], 31);
+        assert!(!r.obj.symbols.contains_key("skip"));
+
+        let line_of = |addr| r.source.get_line_number_by_addr(addr);
+        assert_eq!(
+            [0, 31, 72, 83, 86].map(line_of),
+            [10, 10, 11, 12, 15].map(|line| Some((0, line)))
+        );
+        assert_eq!(r.source.get_line(0, 11).unwrap().addr, Some(41));
+        assert_eq!(r.source.get_line(0, 3).unwrap().addr, None);
+
+        let src =
+            ".macro m a, b\n    irmovq \\a, \\b\n.endm\n    m 1\n    m 1, %rax, 2\n    .rept 2\n";
+        let errors = crate::assemble(src, crate::AssembleOption::default())
+            .err()
+            .unwrap();
+        let kinds: Vec<_> = errors
+            .iter()
+            .map(|e| (e.line, e.kind.to_string()))
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                (4, "missing argument `b` of macro `m`".into()),
+                (
+                    5,
+                    "macro `m` takes 2 argument(s) but 3 were given".to_string()
+                ),
+                (6, "`.rept` without matching `.endr`".into()),
+            ]
+        );
+
+        // errors in generated lines are shown at the invocation
+        let src = ".macro m v\n    irmovq \\v, %rax\n    jmp nowhere\n.endm\n    m $1\n";
+        let errors = crate::assemble(src, crate::AssembleOption::default())
+            .err()
+            .unwrap();
+        let e = &errors.errors[0];
+        assert_eq!((e.line, e.column, e.len), (5, 5, 4));
+        assert_eq!(e.src, "    m $1");
+        let note = e.expansion.as_ref().unwrap();
+        assert_eq!(
+            (note.origin.as_str(), note.src.as_str()),
+            ("macro `m`", "jmp nowhere")
+        );
+        assert!(e.to_string().contains(
+            "5 |     m $1\n  |     ^^^^\n  |\n  = note: in expansion of macro `m`: `jmp nowhere`\n"
+        ));
+
+        // loops which can never fit in the memory
+        let src = "    .rept -1\n    nop\n    .endr\n    .rept 0x10001\n    .endr\n\
+                   .rept 0x100\n.rept 0x100\n    nop\n    .endr\n.endr\n    halt\n";
+        let errors = crate::assemble(src, crate::AssembleOption::default())
+            .err()
+            .unwrap();
+        let kinds: Vec<_> = errors
+            .iter()
+            .map(|e| (e.line, e.kind.to_string()))
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                (1, "`.rept` count `-1` is negative".into()),
+                (4, "`.rept` count 65537 is larger than 65536".into()),
+                (6, "expansion generates more than 65536 lines".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_include() {
+        let dir = std::env::temp_dir().join(format!("y86-include-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("lib")).unwrap();
+        let write = |name: &str, src: &str| std::fs::write(dir.join(name), src).unwrap();
+        write("main.ys", ".include \"lib/a.ys\"\n    call f\n    halt\n");
+        write("lib/a.ys", "f:\n    .include \"b.ys\"\n    ret\n");
+        write("lib/b.ys", "    nop\n");
+        write(
+            "cycle.ys",
+            "    nop\n    .include \"cycle.ys\"\n    .include \"none.ys\"\n",
+        );
+
+        let main = dir.join("main.ys").display().to_string();
+        let r = crate::assemble(
+            &std::fs::read_to_string(&main).unwrap(),
+            crate::AssembleOption::default().set_file_name(&main),
+        )
+        .unwrap();
+        let files = r.source.files();
+        assert_eq!(files.len(), 3);
+        assert!(files[2].ends_with("b.ys"));
+        let loc = |addr| r.source.get_line_number_by_addr(addr);
+        assert_eq!(
+            [0, 1, 2, 11].map(loc),
+            [(1, 1), (1, 3), (0, 2), (0, 3)].map(Some)
+        );
+        assert_eq!(r.source.get_line(2, 1).unwrap().addr, Some(0));
+        assert!(r
+            .to_string()
+            .contains("0x0000: 10                   |     nop\n"));
+
+        let cycle = dir.join("cycle.ys").display().to_string();
+        let errors = crate::assemble(
+            &std::fs::read_to_string(&cycle).unwrap(),
+            crate::AssembleOption::default().set_file_name(&cycle),
+        )
+        .err()
+        .unwrap();
+        let kinds: Vec<_> = errors
+            .iter()
+            .map(|e| (e.line, e.kind.to_string()))
+            .collect();
+        assert_eq!(kinds.len(), 2);
+        assert!(kinds[0].1.ends_with("cycle.ys` is included recursively"));
+        assert!(kinds[1].1.starts_with("could not read"));
+
+        // the summary names the input file instead of the included one
+        write("bad.ys", ".include \"lib/c.ys\"\n    halt\n");
+        write("lib/c.ys", "    jmp nowhere\n");
+        let bad = dir.join("bad.ys").display().to_string();
+        let errors = crate::assemble(
+            &std::fs::read_to_string(&bad).unwrap(),
+            crate::AssembleOption::default().set_file_name(&bad),
+        )
+        .err()
+        .unwrap();
+        assert!(errors.errors[0].file.ends_with("c.ys"));
+        assert!(errors.to_string().ends_with(&format!(
+            "could not assemble `{bad}` due to 1 previous error\n"
+        )));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_overlap() {
+        let src = "    irmovq $1, %rax\n    .pos 0x4\n    .quad 0x1234\n    .pos 0x8\n    halt\n";
+        let errors = crate::assemble(src, crate::AssembleOption::default())
+            .err()
+            .unwrap();
+        let kinds: Vec<_> = errors.iter().map(|e| (e.line, e.kind.clone())).collect();
+        assert_eq!(
+            kinds,
+            [
+                (3, AsmErrorKind::Overlap { start: 4, end: 10 }),
+                (5, AsmErrorKind::Overlap { start: 8, end: 9 }),
+            ]
+        );
+        assert_eq!(
+            errors.errors[0].suggestion.as_deref(),
+            Some("overlaps with line 1: `irmovq $1, %rax`")
+        );
+
+        let option = crate::AssembleOption::default().set_allow_overlap(true);
+        let r = crate::assemble(src, option).unwrap();
+        assert_eq!(r.warnings.len(), 2);
+        assert_eq!(r.warnings[0].severity, crate::Severity::Warning);
+        // the later line takes effect
+        assert_eq!(
+            r.obj.init_mem()[..10],
+            [0x30, 0xf0, 1, 0, 0x34, 0x12, 0, 0, 0, 0]
+        );
+    }
+
     #[test]
     fn test_parser() {
         let lines = Y86AsmParser::parse(Rule::main, RSUM_YS)
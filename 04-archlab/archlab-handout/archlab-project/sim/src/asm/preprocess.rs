@@ -0,0 +1,510 @@
+//! Preprocessing of the source: included files (`.include "file.ys"`), macros
+//! (`.macro`/`.endm`) and loops (`.rept`/`.irp`/`.endr`).
+//!
+//! Expansion is performed on the text of lines before parsing. Every
+//! generated line keeps the location of the invocation in the source files,
+//! so that the listing and the debugger can map it back.
+//!
+//! Included files are resolved relative to the directory of the including
+//! file, or the current directory if the source is not read from a file.
+//!
+//! ```text
+//! .macro copy src, dst, off=0
+//!     mrmovq \off(\src), %r8
+//!     rmmovq %r8, \off(\dst)
+//! .endm
+//!     copy %rdi, %rsi, 8
+//! ```
+//!
+//! Labels defined in a macro body (or a loop body) are local to each
+//! expansion: they are renamed to `label__N`, where `N` is the number of the
+//! expansion, which can also be written as `\@` in the body.
+
+use std::{collections::HashMap, path::PathBuf};
+
+use super::{leading_label, AsmErrorKind, LineError};
+use crate::utils;
+
+/// Maximum depth of nested expansions, which prevents infinite recursion.
+const MAX_DEPTH: usize = 64;
+
+/// Location of a line in the source files.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Loc {
+    /// index of the file in [`Expansion::files`]
+    pub file: usize,
+    /// line number (start from 1)
+    pub line: usize,
+}
+
+/// A line after expansion.
+#[derive(Debug, Clone)]
+pub(crate) struct ExpandedLine {
+    /// Generated lines take the location of the outermost invocation.
+    pub loc: Loc,
+    /// text displayed in the listing
+    pub src: String,
+    /// text to be assembled, `None` for lines of macro definitions and loops
+    pub code: Option<String>,
+}
+
+/// An error of expansion, with the location and text of the line.
+pub(crate) type ExpandError = (Loc, String, LineError);
+
+/// A macro or a loop expanded in the source files, whose location is taken by
+/// the generated lines.
+#[derive(Debug, Clone)]
+pub(crate) struct Invocation {
+    pub loc: Loc,
+    /// what is expanded, e.g. "macro `copy`" or "`.rept`"
+    pub origin: String,
+    /// text of the invocation line
+    pub src: String,
+}
+
+/// Result of preprocessing.
+#[derive(Debug, Default)]
+pub(crate) struct Expansion {
+    pub lines: Vec<ExpandedLine>,
+    pub errors: Vec<ExpandError>,
+    /// names of the source files, the first one is the main file
+    pub files: Vec<String>,
+    /// outermost invocations, nested ones are not recorded
+    pub invocations: Vec<Invocation>,
+}
+
+struct Macro {
+    /// name and default value of parameters
+    params: Vec<(String, Option<String>)>,
+    body: Vec<String>,
+}
+
+#[derive(Default)]
+struct Expander {
+    macros: HashMap<String, Macro>,
+    /// number of expansions so far
+    counter: usize,
+    /// whether the main file is read from the file system
+    from_file: bool,
+    /// canonical paths of the files being included, used to detect cycles
+    include_stack: Vec<PathBuf>,
+    /// maximum number of lines generated by expansions, which is the memory
+    /// size since a larger program can never fit in the memory
+    max_lines: usize,
+    /// whether the expansions have generated too many lines
+    overflowed: bool,
+    out: Expansion,
+}
+
+/// Split the source into lines with their locations.
+fn split_lines(src: &str, file: usize) -> Vec<(Loc, String)> {
+    src.split('\n')
+        .enumerate()
+        .map(|(index, s)| {
+            let loc = Loc {
+                file,
+                line: index + 1,
+            };
+            (loc, s.strip_suffix('\r').unwrap_or(s).to_string())
+        })
+        .collect()
+}
+
+/// Expand all included files, macros and loops in the source file, which
+/// should generate at most `mem_size` lines.
+pub(crate) fn expand(src: &str, file_name: &str, from_file: bool, mem_size: usize) -> Expansion {
+    let mut expander = Expander {
+        from_file,
+        max_lines: mem_size,
+        ..Default::default()
+    };
+    expander.out.files.push(file_name.to_string());
+    if from_file {
+        if let Ok(path) = std::fs::canonicalize(file_name) {
+            expander.include_stack.push(path);
+        }
+    }
+    expander.process(&split_lines(src, 0), 0);
+    expander.out
+}
+
+/// Split the line into the optional leading label, the mnemonic and the rest
+/// of code (without comments).
+fn split_line(src: &str) -> (Option<&str>, &str, &str) {
+    let code = src.split('#').next().unwrap_or_default();
+    let (label, rest) = match leading_label(code) {
+        Some((offset, name)) => (Some(name), &code[offset + name.len() + 1..]),
+        None => (None, code),
+    };
+    let rest = rest.trim();
+    let len = rest.find(char::is_whitespace).unwrap_or(rest.len());
+    (label, &rest[..len], rest[len..].trim())
+}
+
+fn is_ident(s: &str) -> bool {
+    s.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Split comma separated arguments.
+fn split_args(s: &str) -> Vec<String> {
+    if s.is_empty() {
+        return Vec::new();
+    }
+    s.split(',').map(|a| a.trim().to_string()).collect()
+}
+
+/// Replace `\name` with the value of the argument and `\@` with `counter`.
+/// Unknown names are kept as is, since they may belong to an inner loop.
+fn substitute(src: &str, args: &[(String, String)], counter: usize) -> String {
+    let mut out = String::with_capacity(src.len());
+    let mut rest = src;
+    while let Some(pos) = rest.find('\\') {
+        out.push_str(&rest[..pos]);
+        rest = &rest[pos + 1..];
+        if let Some(after) = rest.strip_prefix('@') {
+            out.push_str(&counter.to_string());
+            rest = after;
+            continue;
+        }
+        let len = rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(rest.len());
+        match args.iter().find(|(name, _)| name == &rest[..len]) {
+            Some((_, value)) => {
+                out.push_str(value);
+                rest = &rest[len..];
+            }
+            None => out.push('\\'),
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Rename the labels defined in the body, so that each expansion has its own
+/// labels.
+fn rename_local_labels(body: &mut [String], counter: usize) {
+    let locals: Vec<String> = body
+        .iter()
+        .filter_map(|s| leading_label(s).map(|(_, name)| name.to_string()))
+        .collect();
+    if locals.is_empty() {
+        return;
+    }
+    for src in body.iter_mut() {
+        let mut out = String::with_capacity(src.len());
+        let mut chars = src.char_indices().peekable();
+        let mut prev = ' ';
+        while let Some((start, c)) = chars.next() {
+            if c == '#' {
+                out.push_str(&src[start..]);
+                break;
+            }
+            if !(c.is_ascii_alphanumeric() || c == '_') {
+                out.push(c);
+                prev = c;
+                continue;
+            }
+            let mut end = start + c.len_utf8();
+            while let Some(&(i, c)) = chars.peek() {
+                if !(c.is_ascii_alphanumeric() || c == '_') {
+                    break;
+                }
+                end = i + c.len_utf8();
+                chars.next();
+            }
+            let word = &src[start..end];
+            // skip registers, directives and parameters
+            if !matches!(prev, '%' | '.' | '\\') && locals.iter().any(|l| l == word) {
+                out.push_str(&format!("{word}__{counter}"));
+            } else {
+                out.push_str(word);
+            }
+            prev = 'a';
+        }
+        *src = out;
+    }
+}
+
+impl Expander {
+    fn error(&mut self, loc: Loc, src: &str, kind: AsmErrorKind) {
+        let err = LineError::new(kind, super::content_range(src));
+        self.out.errors.push((loc, src.to_string(), err));
+    }
+
+    /// Find the line index of the end of the block started at `start`, with
+    /// nested blocks skipped.
+    fn block_end(lines: &[(Loc, String)], start: usize, end_mnemonic: &str) -> Option<usize> {
+        let mut depth = 0;
+        for (index, (_, src)) in lines.iter().enumerate().skip(start + 1) {
+            match split_line(src).1 {
+                ".macro" | ".rept" | ".irp" => depth += 1,
+                ".endm" | ".endr" if depth > 0 => depth -= 1,
+                m if m == end_mnemonic => return Some(index),
+                _ => {}
+            }
+        }
+        None
+    }
+
+    /// Record an invocation in the source files.
+    fn record(&mut self, loc: Loc, src: &str, origin: String, depth: usize) {
+        if depth == 0 {
+            self.out.invocations.push(Invocation {
+                loc,
+                origin,
+                src: src.to_string(),
+            });
+        }
+    }
+
+    /// Emit a line which is not assembled. Only lines in the source file are
+    /// listed.
+    fn emit_listing(&mut self, loc: Loc, src: &str, depth: usize) {
+        if depth == 0 {
+            self.out.lines.push(ExpandedLine {
+                loc,
+                src: src.to_string(),
+                code: None,
+            });
+        }
+    }
+
+    /// Expand the body of a macro or a loop once.
+    fn expand_body(
+        &mut self,
+        loc: Loc,
+        src: &str,
+        body: &[String],
+        args: &[(String, String)],
+        depth: usize,
+    ) {
+        if self.overflowed {
+            return;
+        }
+        if self.out.lines.len() > self.max_lines {
+            self.overflowed = true;
+            let msg = format!("expansion generates more than {} lines", self.max_lines);
+            return self.error(loc, src, AsmErrorKind::Syntax(msg));
+        }
+        self.counter += 1;
+        let mut body: Vec<String> = body
+            .iter()
+            .map(|s| substitute(s, args, self.counter))
+            .collect();
+        rename_local_labels(&mut body, self.counter);
+        let body: Vec<(Loc, String)> = body.into_iter().map(|s| (loc, s)).collect();
+        self.process(&body, depth + 1);
+    }
+
+    fn process(&mut self, lines: &[(Loc, String)], depth: usize) {
+        if depth > MAX_DEPTH {
+            if let Some((loc, src)) = lines.first() {
+                let msg = "macro expansion is too deep".to_string();
+                self.error(*loc, src, AsmErrorKind::Syntax(msg));
+            }
+            return;
+        }
+        let mut index = 0;
+        while index < lines.len() {
+            let (loc, src) = (lines[index].0, lines[index].1.as_str());
+            let (label, mnemonic, rest) = split_line(src);
+            index += 1;
+
+            let end_mnemonic = match mnemonic {
+                ".macro" => ".endm",
+                ".rept" | ".irp" => ".endr",
+                ".endm" | ".endr" => {
+                    let msg = format!("unexpected `{mnemonic}`");
+                    self.error(loc, src, AsmErrorKind::Syntax(msg));
+                    self.emit_listing(loc, src, depth);
+                    continue;
+                }
+                ".include" => {
+                    self.emit_listing(loc, src, depth);
+                    self.include(loc, src, rest, depth);
+                    continue;
+                }
+                m if self.macros.contains_key(m) => {
+                    self.invoke(loc, src, label, m, rest, depth);
+                    continue;
+                }
+                _ => {
+                    self.out.lines.push(ExpandedLine {
+                        loc,
+                        src: src.to_string(),
+                        code: Some(src.to_string()),
+                    });
+                    continue;
+                }
+            };
+
+            // collect the body of the block
+            let start = index - 1;
+            let Some(end) = Self::block_end(lines, start, end_mnemonic) else {
+                let msg = format!("`{mnemonic}` without matching `{end_mnemonic}`");
+                self.error(loc, src, AsmErrorKind::Syntax(msg));
+                for (loc, src) in &lines[start..] {
+                    self.emit_listing(*loc, src, depth);
+                }
+                return;
+            };
+            for (loc, src) in &lines[start..=end] {
+                self.emit_listing(*loc, src, depth);
+            }
+            index = end + 1;
+            let body: Vec<String> = lines[start + 1..end]
+                .iter()
+                .map(|(_, s)| s.clone())
+                .collect();
+
+            match mnemonic {
+                ".macro" => self.define(loc, src, rest, body),
+                ".rept" => match utils::parse_literal(rest) {
+                    Some(_) if rest.starts_with('-') => {
+                        let msg = format!("`.rept` count `{rest}` is negative");
+                        self.error(loc, src, AsmErrorKind::Syntax(msg))
+                    }
+                    Some(count) if count > self.max_lines as u64 => {
+                        let msg =
+                            format!("`.rept` count {count} is larger than {}", self.max_lines);
+                        self.error(loc, src, AsmErrorKind::Syntax(msg))
+                    }
+                    Some(count) => {
+                        self.record(loc, src, "`.rept`".to_string(), depth);
+                        for _ in 0..count {
+                            self.expand_body(loc, src, &body, &[], depth);
+                        }
+                    }
+                    None => {
+                        let kind = AsmErrorKind::InvalidNumber(rest.to_string());
+                        self.error(loc, src, kind)
+                    }
+                },
+                _ => {
+                    let mut args = split_args(rest).into_iter();
+                    let name = args.next().unwrap_or_default();
+                    if !is_ident(&name) {
+                        let msg = format!("invalid `.irp` symbol `{name}`");
+                        self.error(loc, src, AsmErrorKind::Syntax(msg));
+                        continue;
+                    }
+                    self.record(loc, src, "`.irp`".to_string(), depth);
+                    for value in args {
+                        self.expand_body(loc, src, &body, &[(name.clone(), value)], depth);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Define a macro from the `.macro` line and its body.
+    fn define(&mut self, loc: Loc, src: &str, rest: &str, body: Vec<String>) {
+        let mut words = rest.split(|c: char| c == ',' || c.is_whitespace());
+        let name = words.next().unwrap_or_default();
+        if !is_ident(name) {
+            let msg = format!("invalid macro name `{name}`");
+            return self.error(loc, src, AsmErrorKind::Syntax(msg));
+        }
+        let mut params = Vec::new();
+        for word in words.filter(|w| !w.is_empty()) {
+            let (param, default) = match word.split_once('=') {
+                Some((p, d)) => (p, Some(d.to_string())),
+                None => (word, None),
+            };
+            if !is_ident(param) {
+                let msg = format!("invalid macro parameter `{param}`");
+                return self.error(loc, src, AsmErrorKind::Syntax(msg));
+            }
+            params.push((param.to_string(), default));
+        }
+        self.macros.insert(name.to_string(), Macro { params, body });
+    }
+
+    fn invoke(
+        &mut self,
+        loc: Loc,
+        src: &str,
+        label: Option<&str>,
+        name: &str,
+        rest: &str,
+        depth: usize,
+    ) {
+        // the label of the invocation line points to the first generated line
+        match label {
+            Some(label) => self.out.lines.push(ExpandedLine {
+                loc,
+                src: match depth {
+                    0 => src.to_string(),
+                    _ => format!("{label}:"),
+                },
+                code: Some(format!("{label}:")),
+            }),
+            None => self.emit_listing(loc, src, depth),
+        }
+
+        let mac = &self.macros[name];
+        let values = split_args(rest);
+        if values.len() > mac.params.len() {
+            let msg = format!(
+                "macro `{name}` takes {} argument(s) but {} were given",
+                mac.params.len(),
+                values.len()
+            );
+            return self.error(loc, src, AsmErrorKind::Syntax(msg));
+        }
+        let mut args = Vec::new();
+        for (i, (param, default)) in mac.params.iter().enumerate() {
+            match values.get(i).filter(|v| !v.is_empty()).or(default.as_ref()) {
+                Some(value) => args.push((param.clone(), value.clone())),
+                None => {
+                    let msg = format!("missing argument `{param}` of macro `{name}`");
+                    return self.error(loc, src, AsmErrorKind::Syntax(msg));
+                }
+            }
+        }
+        let body = mac.body.clone();
+        self.record(loc, src, format!("macro `{name}`"), depth);
+        self.expand_body(loc, src, &body, &args, depth);
+    }
+
+    /// Process the lines of an included file in place.
+    fn include(&mut self, loc: Loc, src: &str, rest: &str, depth: usize) {
+        let Some(name) = rest.strip_prefix('"').and_then(|r| r.strip_suffix('"')) else {
+            let msg = "expect a quoted file name".to_string();
+            return self.error(loc, src, AsmErrorKind::Syntax(msg));
+        };
+        let including = PathBuf::from(&self.out.files[loc.file]);
+        let path = match loc.file {
+            // the main file may not be a real file
+            0 if !self.from_file => PathBuf::from(name),
+            _ => including.parent().unwrap_or(&including).join(name),
+        };
+        let content = std::fs::read_to_string(&path)
+            .and_then(|content| Ok((std::fs::canonicalize(&path)?, content)));
+        let (canonical, content) = match content {
+            Ok(r) => r,
+            Err(err) => {
+                let msg = format!("could not read `{}`: {err}", path.display());
+                return self.error(loc, src, AsmErrorKind::Syntax(msg));
+            }
+        };
+        if self.include_stack.contains(&canonical) {
+            let msg = format!("`{}` is included recursively", path.display());
+            return self.error(loc, src, AsmErrorKind::Syntax(msg));
+        }
+
+        let name = path.display().to_string();
+        let file = match self.out.files.iter().position(|f| *f == name) {
+            Some(file) => file,
+            None => {
+                self.out.files.push(name);
+                self.out.files.len() - 1
+            }
+        };
+        self.include_stack.push(canonical);
+        self.process(&split_lines(&content, file), depth);
+        self.include_stack.pop();
+    }
+}
@@ -1,7 +1,11 @@
 use anyhow::{Context, Result};
 use binutils::{clap, verbose};
 use clap::Parser;
-use y86_sim::{assemble, AssembleOption};
+use y86_sim::{
+    assemble, assemble_relocatable,
+    map::{SymbolMapListing, XrefListing},
+    utils, AssembleOption,
+};
 
 /// Y86 assembler written in Rust.
 #[derive(Parser, Debug)]
@@ -16,18 +20,86 @@ struct Args {
     /// Path to the input .ya file
     input: String,
 
-    /// Output filename (default is input%.yo)
+    /// Output filename (default is input%.yo, or input%.o with `-c`)
     ///
     /// Specify this option to write the assembled results to a file. This
     /// option is conflict with `run`.
     #[arg(short = 'o', long)]
     output: Option<String>,
 
+    /// Assemble into a relocatable object, which can be linked by `yld`
+    #[arg(short = 'c')]
+    relocatable: bool,
+
+    /// Write the symbol map (address, size, section and definition of each
+    /// label) to the file, or to stdout if it is `-`
+    #[arg(long, value_name = "FILE", conflicts_with = "relocatable")]
+    map: Option<String>,
+
+    /// Write the lines referencing each label to the file, or to stdout if it
+    /// is `-`
+    #[arg(long, value_name = "FILE", conflicts_with = "relocatable")]
+    xref: Option<String>,
+
+    /// Write the symbol map and the cross references in JSON. If both are
+    /// written to the same file, they are written as a single object
+    /// `{"symbols": ..., "xref": ...}`
+    #[arg(long)]
+    json: bool,
+
+    /// Report overlapping contents as warnings instead of errors
+    #[arg(long)]
+    allow_overlap: bool,
+
+    /// Size of the memory the program is placed in, e.g. `0x20000` (64 KiB by default)
+    #[arg(long, value_name = "SIZE", value_parser = utils::parse_mem_size)]
+    mem_size: Option<usize>,
+
     /// Print logs during simulation
     #[command(flatten)]
     verbose: verbose::Verbosity,
 }
 
+/// Write the contents to the file, or to stdout if the path is `-`.
+fn write_output(path: &str, contents: &str) -> Result<()> {
+    if path == "-" {
+        print!("{contents}");
+        return Ok(());
+    }
+    std::fs::write(path, contents).with_context(|| format!("could not write file `{path}`"))
+}
+
+/// Write the symbol map and the cross references requested by `args`.
+fn write_listings(args: &Args, map: &SymbolMapListing, xref: &XrefListing) -> Result<()> {
+    // write them together if they are written to the same file
+    if let (Some(path), Some(xref_path)) = (&args.map, &args.xref) {
+        if path == xref_path {
+            let contents = match args.json {
+                true => {
+                    let json =
+                        serde_json::json!({ "symbols": map.to_json(), "xref": xref.to_json() });
+                    format!("{json:#}\n")
+                }
+                false => format!("{map}\n{xref}"),
+            };
+            return write_output(path, &contents);
+        }
+    }
+    if let Some(path) = &args.map {
+        match args.json {
+            true => write_output(path, &format!("{:#}\n", map.to_json()))?,
+            false => write_output(path, &map.to_string())?,
+        }
+    }
+    if let Some(path) = &args.xref {
+        match args.json {
+            true => write_output(path, &format!("{:#}\n", xref.to_json()))?,
+            false => write_output(path, &xref.to_string())?,
+        }
+    }
+    Ok(())
+}
+
 fn main() -> Result<()> {
     let args = Args::parse();
     let verbose_asm = args
@@ -39,17 +111,44 @@ fn main() -> Result<()> {
 
     let content = std::fs::read_to_string(&args.input)
         .with_context(|| format!("could not read file `{}`", args.input))?;
-    let a = assemble(&content, AssembleOption::default().set_verbose(verbose_asm))?;
+    let option = AssembleOption::default()
+        .set_verbose(verbose_asm)
+        .set_file_name(&args.input)
+        .set_allow_overlap(args.allow_overlap);
+    let option = match args.mem_size {
+        Some(size) => option.set_mem_size(size),
+        None => option,
+    };
+    // the rendered output, the listings of an absolute object (`--map` and
+    // `--xref` are rejected with `-c`) and the warnings
+    let result = match args.relocatable {
+        true => assemble_relocatable(&content, option).map(|a| (a.to_string(), None, a.warnings)),
+        false => assemble(&content, option)
+            .map(|a| (a.to_string(), Some((a.symbol_map(), a.xref())), a.warnings)),
+    };
+    let (output, listings, warnings) = match result {
+        Ok(a) => a,
+        Err(errors) => {
+            eprint!("{errors:#}");
+            std::process::exit(1);
+        }
+    };
+    for warning in &warnings {
+        eprintln!("{warning:#}");
+    }
 
-    let output_path = if let Some(path) = args.output {
-        path
+    let output_path = if let Some(path) = &args.output {
+        path.clone()
     } else {
         let mut path = std::path::PathBuf::from(&args.input);
-        path.set_extension("yo");
+        path.set_extension(if args.relocatable { "o" } else { "yo" });
         path.to_string_lossy().to_string()
     };
-    std::fs::write(&output_path, format!("{}", a))
-        .with_context(|| format!("could not write file `{}`", &output_path))?;
-    println!("writing to file `{}`", &output_path);
+    write_output(&output_path, &output)?;
+    // stdout may be used by the outputs
+    eprintln!("writing to file `{}`", &output_path);
+    if let Some((map, xref)) = listings {
+        write_listings(&args, &map, &xref)?;
+    }
     Ok(())
 }
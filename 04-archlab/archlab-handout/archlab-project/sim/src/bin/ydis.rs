@@ -0,0 +1,196 @@
+use std::collections::BTreeMap;
+
+use anyhow::{Context, Result};
+use binutils::{clap, verbose};
+use clap::Parser;
+use y86_sim::{
+    asm,
+    utils::{self, get_u64},
+    AssembleOption, ObjectExt,
+};
+
+/// Y86 disassembler written in Rust.
+#[derive(Parser, Debug)]
+#[command(
+    author,
+    version,
+    long_about = None,
+    styles = binutils::get_styles(),
+    arg_required_else_help = true,
+)]
+struct Args {
+    /// Path to the input .yo file or raw binary (loaded at address 0)
+    input: String,
+
+    /// Size of the memory, e.g. `0x20000` (64 KiB by default)
+    #[arg(long, value_name = "SIZE", value_parser = utils::parse_mem_size)]
+    mem_size: Option<usize>,
+
+    /// Print logs during disassembling
+    #[command(flatten)]
+    verbose: verbose::Verbosity,
+}
+
+/// Print a line in the format of `.yo` file.
+fn print_line(addr: u64, bytes: &[u8], text: &str) {
+    let hex: String = bytes.iter().map(|b| format!("{b:02x}")).collect();
+    println!("{addr:#06x}: {hex: <21}| {text}");
+}
+
+/// Print bytes that are not instructions as data directives.
+fn print_data(addr: u64, bytes: &[u8]) {
+    let mut offset = 0;
+    while offset < bytes.len() {
+        let addr = addr + offset as u64;
+        if bytes.len() - offset >= 8 {
+            let chunk = &bytes[offset..offset + 8];
+            print_line(addr, chunk, &format!("    .quad {:#x}", get_u64(chunk)));
+            offset += 8;
+        } else {
+            let byte = bytes[offset];
+            print_line(addr, &[byte], &format!("    .byte {byte:#x}"));
+            offset += 1;
+        }
+    }
+}
+
+/// Whether the source line of a `.yo` file is a directive such as `.quad`,
+/// possibly after a label.
+fn is_directive(src: &str) -> bool {
+    let code = src.split('#').next().unwrap_or_default().trim_start();
+    let code = match code.split_once(':') {
+        Some((label, rest)) if !label.contains(char::is_whitespace) => rest.trim_start(),
+        _ => code,
+    };
+    code.starts_with('.')
+}
+
+/// Decode all bytes of a region into instructions. Returns `None` if some of
+/// the bytes are not valid instructions.
+fn decode_all(addr: u64, bytes: &[u8]) -> Option<Vec<(u64, asm::Inst<u64>, usize)>> {
+    let mut insts = Vec::new();
+    let mut offset = 0;
+    while offset < bytes.len() {
+        let cur = addr + offset as u64;
+        let (inst, len) = asm::decode(&bytes[offset..], cur).ok()?;
+        insts.push((cur, inst, len));
+        offset += len;
+    }
+    Some(insts)
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    let log_level = binutils::verbose_level_to_trace(args.verbose.log_level());
+    binutils::logging_setup(log_level, None::<&std::fs::File>);
+
+    let content = std::fs::read(&args.input)
+        .with_context(|| format!("could not read file `{}`", args.input))?;
+
+    // regions of contents, and whether each of them contains instructions or
+    // data (unknown for raw binary)
+    let mut regions: Vec<(u64, Vec<u8>, Option<bool>)> = Vec::new();
+    let mut labels = BTreeMap::new();
+    if args.input.ends_with(".yo") {
+        let content = String::from_utf8(content)
+            .with_context(|| format!("`{}` is not a text file", args.input))?;
+        let mut option = AssembleOption::default().set_file_name(&args.input);
+        if let Some(size) = args.mem_size {
+            option = option.set_mem_size(size);
+        }
+        let obj = match ObjectExt::parse_yo_with_option(&content, option) {
+            Ok(obj) => obj,
+            Err(errors) => {
+                eprint!("{errors:#}");
+                std::process::exit(1);
+            }
+        };
+        for (name, addr) in &obj.obj.symbols {
+            labels.entry(*addr).or_insert(name.clone());
+        }
+        // each line of the .yo file is either an instruction or data
+        for line in obj.source.lines() {
+            if let (Some(addr), Some(asm::Data::Bytes(bytes))) = (line.addr, &line.data) {
+                regions.push((addr, bytes.clone(), Some(!is_directive(&line.src))));
+            }
+        }
+    } else {
+        regions.push((0, content, None));
+    }
+
+    let mut next_addr = 0;
+    let mut label_iter = labels.iter().peekable();
+    for (addr, bytes, is_inst) in regions {
+        // labels placed between regions, e.g. the stack
+        while let Some((label_addr, name)) = label_iter.next_if(|(a, _)| **a < addr) {
+            if *label_addr < next_addr {
+                continue;
+            }
+            if *label_addr != next_addr {
+                print_line(*label_addr, &[], &format!("    .pos {label_addr:#x}"));
+            }
+            print_line(*label_addr, &[], &format!("{name}:"));
+            next_addr = *label_addr;
+        }
+        if addr != next_addr {
+            print_line(addr, &[], &format!("    .pos {addr:#x}"));
+        }
+        next_addr = addr + bytes.len() as u64;
+
+        let items = if let Some(is_inst) = is_inst {
+            match decode_all(addr, &bytes).filter(|_| is_inst) {
+                Some(insts) => insts.into_iter().map(Ok).collect(),
+                None => vec![Err((addr, bytes.as_slice()))],
+            }
+        } else {
+            // walk through the binary, skipping bytes that can not be decoded
+            let mut items = Vec::new();
+            let mut offset = 0;
+            while offset < bytes.len() {
+                let cur = addr + offset as u64;
+                match asm::decode(&bytes[offset..], cur) {
+                    Ok((inst, len)) => {
+                        items.push(Ok((cur, inst, len)));
+                        offset += len;
+                    }
+                    Err(_) => {
+                        items.push(Err((cur, &bytes[offset..offset + 1])));
+                        offset += 1;
+                    }
+                }
+            }
+            items
+        };
+
+        for item in items {
+            let cur = match &item {
+                Ok((cur, _, _)) | Err((cur, _)) => *cur,
+            };
+            // labels inside an instruction are dropped
+            while let Some((label_addr, name)) = label_iter.next_if(|(a, _)| **a <= cur) {
+                if *label_addr == cur {
+                    print_line(cur, &[], &format!("{name}:"));
+                }
+            }
+            match item {
+                Ok((cur, inst, len)) => {
+                    let start = (cur - addr) as usize;
+                    let text = format!("    {}", inst.display_with(&labels));
+                    print_line(cur, &bytes[start..start + len], &text);
+                }
+                Err((cur, data)) => print_data(cur, data),
+            }
+        }
+    }
+    for (addr, name) in label_iter {
+        if *addr < next_addr {
+            continue;
+        }
+        if *addr != next_addr {
+            print_line(*addr, &[], &format!("    .pos {addr:#x}"));
+        }
+        print_line(*addr, &[], &format!("{name}:"));
+        next_addr = *addr;
+    }
+    Ok(())
+}
@@ -1,7 +1,20 @@
+use std::{cell::RefCell, rc::Rc};
+
 use anyhow::{Context, Result};
 use binutils::{clap, verbose};
 use clap::Parser;
-use y86_sim::utils;
+use y86_sim::{
+    isa::{IsaMachine, JsonlTracer, Stat, StepOutcome},
+    profile::{Profile, ProfileKind},
+    utils, AssembleOption, ObjectExt,
+};
+
+/// Format of the trace
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+enum TraceFormat {
+    /// one JSON object per line
+    Jsonl,
+}
 
 /// Y86 ISA simulator written in Rust.
 #[derive(Parser, Debug)]
@@ -16,6 +29,25 @@ struct Args {
     /// Path to the input .yo file
     input: String,
 
+    /// Print a trace record of each instruction instead of the human-readable
+    /// output
+    #[arg(long, value_name = "FORMAT")]
+    trace: Option<TraceFormat>,
+
+    /// Print the listing annotated with the executed instructions of each
+    /// line and a summary of each label, instead of the human-readable output
+    #[arg(long, conflicts_with = "trace")]
+    profile: bool,
+
+    /// Size of the memory, e.g. `0x20000` (64 KiB by default)
+    #[arg(long, value_name = "SIZE", value_parser = utils::parse_mem_size)]
+    mem_size: Option<usize>,
+
+    /// Implement the exception extension, i.e. map the vector table, EPC and
+    /// cause registers, and execute `trap` and `iret`
+    #[arg(long)]
+    exceptions: bool,
+
     /// Print logs during simulation
     #[command(flatten)]
     verbose: verbose::Verbosity,
@@ -29,25 +61,65 @@ fn main() -> Result<()> {
     let content = std::fs::read_to_string(&args.input)
         .with_context(|| format!("could not read file `{}`", args.input))?;
 
-    let mut bin = [0; y86_sim::BIN_SIZE];
-
-    for line in content.lines() {
-        if let Some(index) = line.find('|') {
-            if let Some(sep) = line[..index].find(':') {
-                let addr = utils::parse_literal(line[..sep].trim()).ok_or(anyhow::anyhow!(
-                    "invalid address literal: {}",
-                    line[..sep].trim()
-                ))? as usize;
-                let value = line[sep + 1..index].trim();
-                if !value.is_empty() {
-                    let bytes = utils::decode_hex(value)?;
-                    bin[addr..addr + bytes.len()].copy_from_slice(&bytes);
+    let mut option = AssembleOption::default().set_file_name(&args.input);
+    if let Some(size) = args.mem_size {
+        option = option.set_mem_size(size);
+    }
+    let obj = match ObjectExt::parse_yo_with_option(&content, option) {
+        Ok(obj) => obj,
+        Err(errors) => {
+            eprint!("{errors:#}");
+            std::process::exit(1);
+        }
+    };
+    let mut machine = IsaMachine::new(obj.obj.init_mem());
+    if args.exceptions {
+        machine.enable_exceptions();
+    }
+
+    let (stat, io) = match args.trace {
+        Some(TraceFormat::Jsonl) => {
+            let out = std::io::BufWriter::new(std::io::stdout());
+            let tracer = Rc::new(RefCell::new(JsonlTracer::new(out)));
+            machine.add_observer(Box::new(tracer.clone()));
+            while let StepOutcome::Retired = machine.step() {
+                if tracer.borrow().failed() {
+                    break;
                 }
             }
+            match tracer.borrow_mut().finish() {
+                Ok(()) => {}
+                // stop quietly if the reader of the trace exits early
+                Err(err) if err.kind() == std::io::ErrorKind::BrokenPipe => return Ok(()),
+                Err(err) => return Err(err).context("could not write the trace"),
+            }
+            (machine.stat(), machine.io().clone())
         }
+        None if args.profile => {
+            let profile = Rc::new(RefCell::new(Profile::new(ProfileKind::Insts)));
+            machine.add_observer(Box::new(profile.clone()));
+            while let StepOutcome::Retired = machine.step() {}
+            let profile = profile.borrow();
+            print!(
+                "{}\n{}",
+                obj.annotate(&profile),
+                obj.profile_summary(&profile)
+            );
+            (machine.stat(), machine.io().clone())
+        }
+        None => {
+            let result = y86_sim::isa::simulate_machine(machine, true);
+            (result.stat, result.io)
+        }
+    };
+    // exit with an error if the program stops with an exception, or with the
+    // exit code set by the program
+    if stat != Stat::Hlt {
+        std::process::exit(1);
+    }
+    if let Some(code) = io.exit_code {
+        std::process::exit(code as u8 as i32);
     }
-
-    y86_sim::isa::simulate(bin, true)?;
 
     Ok(())
 }
@@ -0,0 +1,76 @@
+use anyhow::{Context, Result};
+use binutils::{clap, verbose};
+use clap::Parser;
+use y86_sim::{
+    link::{link, LinkOption, RelocObject},
+    utils,
+};
+
+/// Y86 linker written in Rust.
+#[derive(Parser, Debug)]
+#[command(
+    author,
+    version,
+    long_about = None,
+    styles = binutils::get_styles(),
+    arg_required_else_help = true,
+)]
+struct Args {
+    /// Paths to the relocatable objects produced by `yas -c`
+    #[arg(required = true)]
+    inputs: Vec<String>,
+
+    /// Output filename
+    #[arg(short = 'o', long, default_value = "a.yo")]
+    output: String,
+
+    /// Place a section at an address, e.g. `.data=0x400`
+    #[arg(long, value_name = "NAME=ADDR")]
+    section_start: Vec<String>,
+
+    /// Size of the memory the program is placed in, e.g. `0x20000` (64 KiB by default)
+    #[arg(long, value_name = "SIZE", value_parser = utils::parse_mem_size)]
+    mem_size: Option<usize>,
+
+    /// Print logs during linking
+    #[command(flatten)]
+    verbose: verbose::Verbosity,
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    let log_level = binutils::verbose_level_to_trace(args.verbose.log_level());
+    binutils::logging_setup(log_level, None::<&std::fs::File>);
+
+    let mut option = LinkOption::default();
+    if let Some(size) = args.mem_size {
+        option = option.set_mem_size(size);
+    }
+    for item in &args.section_start {
+        let (name, addr) = item
+            .split_once('=')
+            .with_context(|| format!("invalid section start `{item}`, expect NAME=ADDR"))?;
+        let addr = y86_sim::utils::parse_literal(addr)
+            .with_context(|| format!("invalid address `{addr}`"))?;
+        option = option.set_section_start(name, addr);
+    }
+
+    let mut objects = Vec::new();
+    for input in &args.inputs {
+        let content = std::fs::read_to_string(input)
+            .with_context(|| format!("could not read file `{input}`"))?;
+        match RelocObject::parse(&content, input) {
+            Ok(obj) => objects.push((input.clone(), obj)),
+            Err(errors) => {
+                eprint!("{errors:#}");
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let a = link(&objects, &option)?;
+    std::fs::write(&args.output, format!("{}", a))
+        .with_context(|| format!("could not write file `{}`", &args.output))?;
+    println!("writing to file `{}`", &args.output);
+    Ok(())
+}
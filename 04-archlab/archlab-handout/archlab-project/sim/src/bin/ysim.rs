@@ -4,8 +4,9 @@ use clap::{error::ErrorKind, CommandFactory, Parser};
 use y86_sim::{
     architectures::{arch_names, create_sim},
     assemble,
-    framework::{MemData, MEM_SIZE},
-    utils, AssembleOption,
+    framework::{CpuSim, MemData, MEM_SIZE},
+    profile::{Profile, ProfileKind},
+    utils, AssembleOption, ObjectExt,
 };
 
 /// Print architecture information after help message
@@ -35,6 +36,13 @@ fn after_help() -> String {
     )
 }
 
+/// Format of the trace
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+enum TraceFormat {
+    /// one JSON object per line
+    Jsonl,
+}
+
 /// Y86 pipeline simulator written in Rust
 #[derive(Parser, Debug)]
 #[command(
@@ -46,7 +54,7 @@ fn after_help() -> String {
     arg_required_else_help = true,
 )]
 struct Args {
-    /// Path to the input .ya file
+    /// Path to the input .ys or .yo file
     input: Option<String>,
 
     /// Get information about the current architecture
@@ -61,11 +69,99 @@ struct Args {
     #[arg(long, default_value = "100000")]
     max_cpu_cycle: Option<u64>,
 
+    /// Print a trace record of each cycle instead of the human-readable
+    /// output
+    #[arg(long, value_name = "FORMAT")]
+    trace: Option<TraceFormat>,
+
+    /// Print the listing annotated with the cycles of each line and a summary
+    /// of each label, instead of the human-readable output
+    #[arg(long, conflicts_with = "trace")]
+    profile: bool,
+
+    /// Dump the pipeline registers, intermediate signals and unit ports of
+    /// each cycle to a VCD file, which can be viewed by GTKWave or Surfer
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["trace", "profile"])]
+    vcd: Option<String>,
+
+    /// Report overlapping contents as warnings instead of errors
+    #[arg(long)]
+    allow_overlap: bool,
+
+    /// Size of the memory, e.g. `0x20000` (64 KiB by default)
+    #[arg(long, value_name = "SIZE", value_parser = utils::parse_mem_size)]
+    mem_size: Option<usize>,
+
     /// Print logs during simulation
     #[command(flatten)]
     verbose: verbose::Verbosity,
 }
 
+/// Run the simulator and print a JSON record of each cycle.
+#[cfg(feature = "serde")]
+fn trace_jsonl(mut pipe: Box<dyn CpuSim>, max_cpu_cycle: u64) -> Result<()> {
+    use std::io::{ErrorKind, Write};
+
+    // stop if the reader of the trace exits early, e.g. `| head`
+    let ignore_broken_pipe = |err: std::io::Error| match err.kind() {
+        ErrorKind::BrokenPipe => Ok(()),
+        _ => Err(err),
+    };
+    let mut out = std::io::BufWriter::new(std::io::stdout());
+    // the same as `CpuSim::step`, but record each cycle
+    loop {
+        pipe.propagate_signals();
+        if let Err(err) = writeln!(out, "{}", pipe.trace_record()) {
+            return Ok(ignore_broken_pipe(err)?);
+        }
+        if pipe.is_terminate() {
+            break;
+        }
+        if pipe.cycle_count() > max_cpu_cycle {
+            anyhow::bail!(
+                "exceed maximum CPU cycle limit (use --max-cpu-cycle to change the limit)"
+            );
+        }
+        pipe.initiate_next_cycle();
+    }
+    out.flush().or_else(ignore_broken_pipe)?;
+    Ok(())
+}
+
+#[cfg(not(feature = "serde"))]
+fn trace_jsonl(_: Box<dyn CpuSim>, _: u64) -> Result<()> {
+    anyhow::bail!("ysim is built without the `serde` feature, which is required by --trace")
+}
+
+/// Run the simulator and dump the signals of each cycle to a VCD file.
+#[cfg(feature = "serde")]
+fn dump_vcd(pipe: &mut dyn CpuSim, arch: &str, path: &str, max_cpu_cycle: u64) -> Result<()> {
+    let file =
+        std::fs::File::create(path).with_context(|| format!("could not create file `{path}`"))?;
+    let mut vcd = y86_sim::vcd::VcdWriter::new(std::io::BufWriter::new(file), arch);
+    // the same as `CpuSim::step`, but record each cycle
+    loop {
+        pipe.propagate_signals();
+        vcd.write_cycle(pipe.cycle_count(), &pipe.vcd_signals()?)?;
+        if pipe.is_terminate() {
+            break;
+        }
+        if pipe.cycle_count() > max_cpu_cycle {
+            anyhow::bail!(
+                "exceed maximum CPU cycle limit (use --max-cpu-cycle to change the limit)"
+            );
+        }
+        pipe.initiate_next_cycle();
+    }
+    vcd.finish(pipe.cycle_count() + 1)?;
+    Ok(())
+}
+
+#[cfg(not(feature = "serde"))]
+fn dump_vcd(_: &mut dyn CpuSim, _: &str, _: &str, _: u64) -> Result<()> {
+    anyhow::bail!("ysim is built without the `serde` feature, which is required by --vcd")
+}
+
 fn main() -> Result<()> {
     let args = Args::parse();
     let verbose_asm = args
@@ -78,8 +174,31 @@ fn main() -> Result<()> {
     let maybe_a = if let Some(input) = &args.input {
         let content = std::fs::read_to_string(input)
             .with_context(|| format!("could not read file `{}`", input))?;
-        let obj = assemble(&content, AssembleOption::default().set_verbose(verbose_asm))?;
-        Some(obj)
+        let mut option = AssembleOption::default()
+            .set_verbose(verbose_asm)
+            .set_file_name(input)
+            .set_allow_overlap(args.allow_overlap);
+        // the memory of the simulation is initialized from the object
+        if let Some(size) = args.mem_size {
+            option = option.set_mem_size(size);
+        }
+        let result = if input.ends_with(".yo") {
+            ObjectExt::parse_yo_with_option(&content, option)
+        } else {
+            assemble(&content, option)
+        };
+        match result {
+            Ok(obj) => {
+                for warning in &obj.warnings {
+                    eprintln!("{warning:#}");
+                }
+                Some(obj)
+            }
+            Err(errors) => {
+                eprint!("{errors:#}");
+                std::process::exit(1);
+            }
+        }
     } else {
         None
     };
@@ -95,7 +214,7 @@ fn main() -> Result<()> {
     }
 
     if args.info {
-        let empty_sim = create_sim(arch.clone(), MemData::init([0; MEM_SIZE]), false);
+        let empty_sim = create_sim(arch.clone(), MemData::zeroed(MEM_SIZE), false);
 
         print!("{}", empty_sim);
 
@@ -103,11 +222,21 @@ fn main() -> Result<()> {
     } else {
         let a = maybe_a.ok_or(anyhow::anyhow!("no input file"))?;
         let mem = MemData::init(a.obj.init_mem());
-        let mut pipe = create_sim(arch, mem.clone(), true);
-
         let max_cpu_cycle = args.max_cpu_cycle.unwrap();
+
+        if let Some(TraceFormat::Jsonl) = args.trace {
+            return trace_jsonl(create_sim(arch, mem, false), max_cpu_cycle);
+        }
+
+        let tty_out = !args.profile && args.vcd.is_none();
+        let mut pipe = create_sim(arch.clone(), mem.clone(), tty_out);
+        let mut profile = Profile::new(ProfileKind::Cycles);
+        if let Some(path) = &args.vcd {
+            dump_vcd(pipe.as_mut(), &arch, path, max_cpu_cycle)?;
+        }
         while !pipe.is_terminate() {
             pipe.step();
+            profile.record_cycle(pipe.as_ref());
             if pipe.cycle_count() > max_cpu_cycle {
                 anyhow::bail!(
                     "exceed maximum CPU cycle limit (use --max-cpu-cycle to change the limit)"
@@ -115,7 +244,16 @@ fn main() -> Result<()> {
             }
         }
 
+        if args.profile {
+            print!("{}\n{}", a.annotate(&profile), a.profile_summary(&profile));
+            return Ok(());
+        }
         utils::mem_diff(&a.obj.init_mem(), &mem.read());
+        let io = pipe.io_devices();
+        io.print();
+        if let Some(code) = io.exit_code {
+            std::process::exit(code as u8 as i32);
+        }
         // mem_print(&pipe.mem());
     }
     Ok(())
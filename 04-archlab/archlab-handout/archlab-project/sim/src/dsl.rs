@@ -189,6 +189,7 @@ macro_rules! define_stages {
 
         /// All pipeline registers (all stages).
         #[derive(Default, Debug, Clone)]
+        #[cfg_attr(feature = "serde", derive(serde::Serialize))]
         pub struct PipeRegs {
             $(pub $pr_short_name: unit_stage::$pr_name),*
         }
@@ -199,6 +200,16 @@ macro_rules! define_stages {
             pub fn mux(&mut self, new: &PipeRegs) {
                 $( self.$pr_short_name.mux(&new.$pr_short_name); )*
             }
+
+            /// Stall and bubble signals of all pipeline registers.
+            #[allow(unused)]
+            pub fn controls(&self) -> Vec<$crate::framework::StageControl> {
+                vec![$( $crate::framework::StageControl {
+                    name: stringify!($pr_short_name),
+                    stall: self.$pr_short_name.stall,
+                    bubble: self.$pr_short_name.bubble,
+                } ),*]
+            }
         }
     };
 }
@@ -2,9 +2,18 @@
 //! general CPU simulator framework.
 mod propagate;
 
+use std::{
+    ops::{Deref, DerefMut},
+    sync::{Arc, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard},
+};
+
 pub use propagate::{PropCircuit, PropOrder, PropOrderBuilder, PropUpdates, Propagator, Tracer};
 
-use crate::isa::RegFile;
+use crate::{
+    isa::{ConditionCode, RegFile},
+    mmio::IoDevices,
+    utils::{get_u64, put_u64},
+};
 
 /// HardwareUnits depends on the [`std::fmt::Display`] trait, which enables
 /// rich-text output in terminal.
@@ -14,36 +23,168 @@ pub trait HardwareUnits: std::fmt::Display {
 
     /// Return the content of register file, indexed by the register code.
     fn register_file(&self) -> RegFile;
+
+    /// Return the state of the memory-mapped devices.
+    fn io_devices(&self) -> IoDevices;
+
+    /// Deep copy the states of the units, including the memory.
+    fn snapshot(&self) -> UnitsSnapshot;
+
+    /// Restore the states saved by [`HardwareUnits::snapshot`]. The memory is
+    /// restored in place, so that it is still shared with the caller.
+    fn restore(&mut self, snapshot: &UnitsSnapshot);
+}
+
+/// States of the hardware units, see [`HardwareUnits::snapshot`].
+#[derive(Debug, Clone)]
+pub struct UnitsSnapshot {
+    pub regs: RegFile,
+    pub cc: ConditionCode,
+    pub mem: MemSnapshot,
 }
 
-/// Size of the memory that is used to store instructions and data (stack).
-/// No matter what architecture we are using, memory store must exist. Otherwise
-/// we have no place to store instructions.
+/// Default size of the memory that is used to store instructions and data
+/// (stack). No matter what architecture we are using, memory store must exist.
+/// Otherwise we have no place to store instructions.
+///
+/// The size can be chosen for each simulation (e.g. `ysim --mem-size`), as
+/// long as it is at most [`MAX_MEM_SIZE`].
 pub const MEM_SIZE: usize = 1 << 16;
 
-#[derive(Debug, PartialEq, Eq)]
-pub struct MemData(std::rc::Rc<std::cell::RefCell<[u8; MEM_SIZE]>>);
+/// Maximum size of the memory chosen at runtime.
+pub const MAX_MEM_SIZE: usize = 1 << 28;
+
+/// The memory shared by the hardware units, with the memory-mapped devices
+/// (see [`crate::mmio`]). Clones share the same memory, which can be moved to
+/// another thread along with the simulator.
+#[derive(Debug)]
+pub struct MemData {
+    data: Arc<RwLock<Box<[u8]>>>,
+    io: Arc<RwLock<IoDevices>>,
+}
 
 impl Clone for MemData {
     fn clone(&self) -> Self {
-        Self(std::rc::Rc::clone(&self.0))
+        Self {
+            data: Arc::clone(&self.data),
+            io: Arc::clone(&self.io),
+        }
     }
 }
 
 impl MemData {
-    pub fn init(data: [u8; MEM_SIZE]) -> Self {
-        Self(std::rc::Rc::new(std::cell::RefCell::new(data)))
+    /// Create the memory with the initial contents, whose length is the size
+    /// of the memory.
+    pub fn init(data: Box<[u8]>) -> Self {
+        Self {
+            data: Arc::new(RwLock::new(data)),
+            io: Default::default(),
+        }
+    }
+
+    /// Create a zeroed memory of the given size.
+    pub fn zeroed(size: usize) -> Self {
+        Self::init(vec![0; size].into_boxed_slice())
+    }
+
+    /// Size of the memory in bytes.
+    pub fn size(&self) -> usize {
+        self.read().len()
+    }
+
+    // A panic while the memory is locked (e.g. a failed test) does not make
+    // the memory invalid, so the poison is ignored.
+    pub fn read(&self) -> MemGuard<RwLockReadGuard<'_, Box<[u8]>>> {
+        MemGuard(self.data.read().unwrap_or_else(PoisonError::into_inner))
+    }
+
+    pub fn write(&self) -> MemGuard<RwLockWriteGuard<'_, Box<[u8]>>> {
+        MemGuard(self.data.write().unwrap_or_else(PoisonError::into_inner))
+    }
+
+    /// Whether a quad word can be stored to or loaded from `addr`, i.e. a
+    /// device is mapped at the address or the quad word is in the memory.
+    pub fn is_valid(&self, addr: u64) -> bool {
+        self.io().is_mapped(addr)
+            || addr
+                .checked_add(8)
+                .is_some_and(|end| end <= self.size() as u64)
+    }
+
+    fn io_mut(&self) -> RwLockWriteGuard<'_, IoDevices> {
+        self.io.write().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Store a quad word to a device, or to the memory if no device is mapped
+    /// at `addr`. The address should be valid.
+    pub fn store_u64(&self, addr: u64, value: u64) {
+        if !self.io_mut().store(addr, value) {
+            put_u64(&mut self.write()[addr as usize..], value);
+        }
+    }
+
+    /// Load a quad word from a device, or from the memory if no device is
+    /// mapped at `addr`. The address should be valid.
+    pub fn load_u64(&self, addr: u64) -> u64 {
+        let value = self.io().load(addr);
+        value.unwrap_or_else(|| get_u64(&self.read()[addr as usize..]))
+    }
+
+    /// Map the exception registers. See [`IoDevices::enable_exceptions`].
+    pub fn enable_exceptions(&self) {
+        self.io_mut().enable_exceptions();
+    }
+
+    /// Enter the handler of an exception. See [`IoDevices::enter_handler`].
+    pub fn enter_handler(&self, cause: u64, epc: u64) -> Option<u64> {
+        self.io_mut().enter_handler(&self.read(), cause, epc)
+    }
+
+    pub fn io(&self) -> RwLockReadGuard<'_, IoDevices> {
+        self.io.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Copy the contents of the memory and the state of the devices.
+    pub fn snapshot(&self) -> MemSnapshot {
+        MemSnapshot {
+            data: Box::from(&*self.read()),
+            io: self.io().clone(),
+        }
+    }
+
+    /// Restore the contents saved by [`MemData::snapshot`], which is visible
+    /// to all clones of the memory.
+    pub fn restore(&self, snapshot: &MemSnapshot) {
+        Box::clone_from(&mut self.write().0, &snapshot.data);
+        self.io_mut().clone_from(&snapshot.io);
     }
+}
+
+/// The locked contents of [`MemData`], see [`MemData::read`] and
+/// [`MemData::write`].
+pub struct MemGuard<G>(G);
 
-    pub fn read(&self) -> std::cell::Ref<'_, [u8; MEM_SIZE]> {
-        self.0.borrow()
+impl<G: Deref<Target = Box<[u8]>>> Deref for MemGuard<G> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.0
     }
+}
 
-    pub fn write(&self) -> std::cell::RefMut<'_, [u8; MEM_SIZE]> {
-        self.0.borrow_mut()
+impl<G: DerefMut<Target = Box<[u8]>>> DerefMut for MemGuard<G> {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.0
     }
 }
 
+/// A copy of [`MemData`], see [`MemData::snapshot`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemSnapshot {
+    data: Box<[u8]>,
+    io: IoDevices,
+}
+
 pub enum CpuStatus {
     CycleStart,
     CycleEnd,
@@ -56,7 +197,10 @@ pub enum CpuStatus {
 ///
 /// Implement the [`std::fmt::Display`] trait to display architecture-specific
 /// information.
-pub trait CpuSim: std::fmt::Display {
+///
+/// A simulator owns its state (or shares it only through [`MemData`]), so
+/// that it can be moved to another thread, e.g. to run tests in parallel.
+pub trait CpuSim: std::fmt::Display + Send {
     /// Initiate the next cycle or the first cycle. This function should be
     /// called after calling [`CpuSim::propagate_signals`]. Otherwise the
     /// behavior is undefined.
@@ -74,6 +218,11 @@ pub trait CpuSim: std::fmt::Display {
     /// Whether the simulation is terminated
     fn is_terminate(&self) -> bool;
 
+    /// Status of the program, which is declared by `#![status = ...]` in HCL.
+    /// After the simulation terminates, it is the final status (`Hlt`, `Adr`
+    /// or `Ins`).
+    fn status(&self) -> Option<crate::isa::Stat>;
+
     /// Get the current cycle count. Each call to [`CpuSim::propagate_signals`]
     /// will increase the cycle count by 1.
     fn cycle_count(&self) -> u64;
@@ -82,14 +231,38 @@ pub trait CpuSim: std::fmt::Display {
     /// [`propagate::PropOrder::max_dist`].
     fn cycle_cost(&self) -> u64;
 
+    /// ISA extensions implemented by the architecture, which are declared by
+    /// `#![extensions(...)]` in HCL.
+    fn extensions(&self) -> &'static [crate::isa::Extension];
+
     /// Get the registers and their values.
     ///
     /// The id of the register should be in increasing order.
     fn registers(&self) -> RegFile;
 
+    /// Console output and exit code written by the program through the
+    /// memory-mapped devices (see [`crate::mmio`]).
+    fn io_devices(&self) -> IoDevices;
+
     /// This function is called by debugger to display variables
     fn get_stage_info(&self) -> Vec<StageInfo>;
 
+    /// Stall and bubble signals of the pipeline registers in the current
+    /// cycle, in the order of their declarations. It should be called after
+    /// [`CpuSim::propagate_signals`].
+    fn stage_controls(&self) -> Vec<StageControl>;
+
+    /// Record of the current cycle for `ysim --trace`, including the pipeline
+    /// registers, the intermediate signals and the inputs and outputs of the
+    /// units. It should be called after [`CpuSim::propagate_signals`].
+    #[cfg(feature = "serde")]
+    fn trace_record(&self) -> serde_json::Value;
+
+    /// Signals of the current cycle for `ysim --vcd` (see [`crate::vcd`]).
+    /// It should be called after [`CpuSim::propagate_signals`].
+    #[cfg(feature = "serde")]
+    fn vcd_signals(&self) -> Result<Vec<crate::vcd::Signal>, crate::vcd::Error>;
+
     fn step(&mut self);
 
     /// Get the value of a register if it exists
@@ -99,14 +272,35 @@ pub trait CpuSim: std::fmt::Display {
 
     /// Get the information of the computational graph
     fn proporder(&self) -> &PropOrder;
+
+    /// Save the full state of the simulator, i.e. the pipeline registers,
+    /// the signals, the states of the units and the memory, which can be
+    /// restored by [`CpuSim::restore`].
+    fn snapshot(&self) -> Box<dyn SimSnapshot>;
+
+    /// Restore the state saved by [`CpuSim::snapshot`]. The simulation then
+    /// continues as if it had never left the cycle of the snapshot.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the snapshot is taken from another architecture.
+    fn restore(&mut self, snapshot: &dyn SimSnapshot);
+}
+
+/// The state of a simulator saved by [`CpuSim::snapshot`].
+pub trait SimSnapshot: std::any::Any + Send {
+    /// The cycle count when the snapshot is taken.
+    fn cycle_count(&self) -> u64;
+
+    fn as_any(&self) -> &dyn std::any::Any;
 }
 
 // here we use trait to collect the types
 pub trait CpuCircuit {
-    type UnitIn: Default;
-    type UnitOut: Default;
-    type Inter: Default;
-    type StageState: Default;
+    type UnitIn: Default + Clone + Send;
+    type UnitOut: Default + Clone + Send;
+    type Inter: Default + Clone + Send;
+    type StageState: Default + Clone + Send;
 }
 
 pub trait CpuArch: CpuCircuit + Sized {
@@ -160,6 +354,64 @@ impl<T: CpuArch> PipeSim<T> {
             cycle_count: 0,
         }
     }
+
+    /// See [`CpuSim::snapshot`].
+    pub fn save(&self) -> PipeSnapshot<T> {
+        PipeSnapshot {
+            unit_in: self.cur_unit_in.clone(),
+            unit_out: self.cur_unit_out.clone(),
+            inter: self.cur_inter.clone(),
+            cur_state: self.cur_state.clone(),
+            nex_state: self.nex_state.clone(),
+            units: self.units.snapshot(),
+            terminate: self.terminate,
+            cycle_count: self.cycle_count,
+        }
+    }
+
+    /// See [`CpuSim::restore`].
+    pub fn load(&mut self, snapshot: &PipeSnapshot<T>) {
+        self.cur_unit_in.clone_from(&snapshot.unit_in);
+        self.cur_unit_out.clone_from(&snapshot.unit_out);
+        self.cur_inter.clone_from(&snapshot.inter);
+        self.cur_state.clone_from(&snapshot.cur_state);
+        self.nex_state.clone_from(&snapshot.nex_state);
+        self.units.restore(&snapshot.units);
+        self.terminate = snapshot.terminate;
+        self.cycle_count = snapshot.cycle_count;
+    }
+}
+
+/// The state of a [`PipeSim`]. The propagation order is not saved, as it is
+/// the same for all simulators of an architecture.
+pub struct PipeSnapshot<T: CpuArch> {
+    unit_in: T::UnitIn,
+    unit_out: T::UnitOut,
+    inter: T::Inter,
+    cur_state: T::StageState,
+    nex_state: T::StageState,
+    units: UnitsSnapshot,
+    terminate: bool,
+    cycle_count: u64,
+}
+
+impl<T: CpuArch + 'static> SimSnapshot for PipeSnapshot<T> {
+    fn cycle_count(&self) -> u64 {
+        self.cycle_count
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+}
+
+/// Control signals of a pipeline register in a cycle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StageControl {
+    /// short name of the stage, e.g. `d`
+    pub name: &'static str,
+    pub stall: bool,
+    pub bubble: bool,
 }
 
 #[derive(Debug)]
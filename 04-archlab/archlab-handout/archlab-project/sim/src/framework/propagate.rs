@@ -229,13 +229,13 @@ impl Tracer {
 // Update input and intermediate signals from output signals.
 pub type Updater<T> = Box<
     dyn FnMut(
-        &mut <T as CpuCircuit>::UnitIn,
-        &mut <T as CpuCircuit>::Inter,
-        &mut <T as CpuCircuit>::StageState,
-        &mut Tracer,
-        &<T as CpuCircuit>::UnitOut,
-        &<T as CpuCircuit>::StageState,
-    ),
+            &mut <T as CpuCircuit>::UnitIn,
+            &mut <T as CpuCircuit>::Inter,
+            &mut <T as CpuCircuit>::StageState,
+            &mut Tracer,
+            &<T as CpuCircuit>::UnitOut,
+            &<T as CpuCircuit>::StageState,
+        ) + Send,
 >;
 
 pub struct PropUpdates<T: CpuCircuit> {
@@ -294,7 +294,8 @@ impl<T: CpuCircuit> PropCircuit<T> {
                 &mut Tracer,
                 &T::UnitOut,
                 &T::StageState,
-            ) + 'static,
+            ) + Send
+            + 'static,
     ) {
         self.updates.updates.insert(name, Box::new(func));
     }
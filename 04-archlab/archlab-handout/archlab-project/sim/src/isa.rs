@@ -1,7 +1,9 @@
 //! Instruction Set definition for Y86-64 Architecture
 
+use std::{cell::RefCell, rc::Rc};
+
 use crate::{
-    object::BIN_SIZE,
+    mmio::IoDevices,
     utils::{format_reg_file, get_u64, mem_diff, put_u64},
 };
 
@@ -41,6 +43,9 @@ define_code! {
     POPQ = 0xb;
     // extended instruction
     IOPQ = 0xc;
+    JM = 0xd;
+    // exception extension: `trap` and `iret`, distinguished by ifun
+    EXC = 0xe;
 }
 
 define_code! {
@@ -74,8 +79,64 @@ define_code! {
     SUB = 1;
     AND = 2;
     XOR = 3;
+    // arithmetic extension
+    MUL = 4;
+    DIV = 5;
+    MOD = 6;
+    SAR = 7;
+    SHL = 8;
+    SHR = 9;
+}
+
+define_code! {
+    @mod exc_fn;
+    @type u8;
+    TRAP = 0;
+    IRET = 1;
+}
+
+// Causes of exceptions, which index the vector table (see
+// [`Extension::Exception`]).
+define_code! {
+    @mod exc_cause;
+    @type u64;
+    CAUSE_TRAP = 0;
+    CAUSE_ADR = 1;
+    CAUSE_INS = 2;
+}
+
+/// Optional extensions of the Y86 ISA. An architecture declares the
+/// extensions it implements by `#![extensions(...)]` in HCL.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Extension {
+    /// `mulq`, `divq`, `modq`, `sarq`, `shlq`, `shrq` and their immediate
+    /// forms (e.g. `imulq`). Division is signed, and shifts use the low 6
+    /// bits of `rA`.
+    Arith,
+    /// Exception handling (see [`crate::mmio::ExceptionRegs`]). After the
+    /// vector table is set, `trap` (or `syscall`) and the faults `Adr` and
+    /// `Ins` jump to the handler at `M[base + 8 * cause]` instead of stopping
+    /// the machine. The faulting instruction has no effect, and its address
+    /// (or the address following `trap`) is saved to EPC, to which `iret`
+    /// returns.
+    Exception,
+}
+
+/// Length in bytes of the instructions of `icode`, or `None` for an invalid
+/// icode.
+pub fn inst_len(icode: u8) -> Option<usize> {
+    use inst_code::*;
+    match icode {
+        HALT | NOP | RET | EXC => Some(1),
+        CMOVX | OPQ | PUSHQ | POPQ => Some(2),
+        JX | CALL => Some(9),
+        IRMOVQ | RMMOVQ | MRMOVQ | IOPQ | JM => Some(10),
+        _ => None,
+    }
 }
 
+/// Compute `b op a`. Returns `None` for an invalid function code or a
+/// division by zero.
 pub fn arithmetic_compute(a: u64, b: u64, op: u8) -> Option<u64> {
     use op_code::*;
     match op {
@@ -83,6 +144,13 @@ pub fn arithmetic_compute(a: u64, b: u64, op: u8) -> Option<u64> {
         SUB => Some(b.wrapping_sub(a)),
         XOR => Some(b ^ a),
         AND => Some(b & a),
+        MUL => Some(b.wrapping_mul(a)),
+        DIV | MOD if a == 0 => None,
+        DIV => Some((b as i64).wrapping_div(a as i64) as u64),
+        MOD => Some((b as i64).wrapping_rem(a as i64) as u64),
+        SAR => Some(((b as i64) >> (a & 63)) as u64),
+        SHL => Some(b << (a & 63)),
+        SHR => Some(b >> (a & 63)),
         _ => None,
     }
 }
@@ -143,6 +211,11 @@ impl ConditionCode {
                 ADD => (!(a ^ b) & (a ^ e)) >> W_1 != 0,
                 // (b - a): a, b have different sign and b, e have different sign
                 SUB => ((a ^ b) & (b ^ e)) >> W_1 != 0,
+                // the signed product does not fit in 64 bits
+                MUL => (b as i64).checked_mul(a as i64).is_none(),
+                // only i64::MIN / -1 overflows
+                DIV | MOD => b as i64 == i64::MIN && a as i64 == -1,
+                // logical operations and shifts never overflow
                 _ => false,
             },
         };
@@ -164,10 +237,11 @@ impl std::fmt::Display for ConditionCode {
 }
 
 /// Simulator State (at each stage), depending on the hardware design.
-#[derive(Debug, Clone, PartialEq, Eq, Copy)]
+#[derive(Debug, Clone, PartialEq, Eq, Copy, Default)]
 #[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub enum Stat {
     /// Indicates that everything is fine.
+    #[default]
     Aok = 0,
     /// Indicates that the stage is bubbled. A bubbled stage execute the NOP
     /// instruction. Initially, all stages are in the bubble state.
@@ -185,12 +259,6 @@ pub enum Stat {
     Ins = 4,
 }
 
-impl Default for Stat {
-    fn default() -> Self {
-        Self::Aok
-    }
-}
-
 impl std::fmt::Display for Stat {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let (name, s) = match self {
@@ -206,325 +274,805 @@ impl std::fmt::Display for Stat {
 
 /// Simulation result of the Y86 machine code on the standard ISA.
 pub struct StandardResult {
-    pub bin: [u8; BIN_SIZE],
+    /// the final memory
+    pub bin: Box<[u8]>,
     pub cc: ConditionCode,
     pub regs: RegFile,
+    /// address of the `halt` instruction, or of the faulting instruction
     pub pc: usize,
     pub n_insts: u64,
+    /// final status, which is `Hlt`, `Adr` or `Ins`
+    pub stat: Stat,
+    /// console output and exit code written by the program
+    pub io: IoDevices,
 }
 
-/// Execute Y86 machine code w.r.t. the ISA specification. This function
-/// is used to verify the correctness of the pipeline architectures.
-///
-/// It supports the extended `iopq` instruction.
-pub fn simulate(mut bin: [u8; BIN_SIZE], tty_out: bool) -> anyhow::Result<StandardResult> {
-    let original = bin;
-    let mut pc = 0;
-
-    fn ensure_reg(reg: u8) -> anyhow::Result<usize> {
-        if reg >= 16 {
-            anyhow::bail!("invalid register code: {:#x}", reg);
-        }
-        Ok(reg as usize)
+/// An instruction executed by [`IsaMachine`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetiredInst {
+    pub pc: u64,
+    pub icode: u8,
+    pub ifun: u8,
+    /// `rA` and `rB`, if the instruction has a register byte
+    pub regs: Option<(u8, u8)>,
+    /// the constant word, if the instruction has one
+    pub val_c: Option<u64>,
+}
+
+/// An exception stopping [`IsaMachine`], with the status `Adr` or `Ins`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IsaFault {
+    pub stat: Stat,
+    /// address of the faulting instruction
+    pub pc: u64,
+    pub reason: String,
+}
+
+impl std::fmt::Display for IsaFault {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} at {:#x}", self.reason, self.pc)
     }
+}
 
-    // Condition code register
-    let mut reg_cc = ConditionCode::default();
-    let mut reg_file = [0u64; 16];
+impl std::error::Error for IsaFault {}
+
+/// Result of [`IsaMachine::step`].
+#[derive(Debug)]
+pub enum StepOutcome {
+    /// An instruction is executed, or a fault is handled by entering its
+    /// handler ([`Extension::Exception`]), and the machine can continue.
+    Retired,
+    /// The `halt` instruction is executed (or has been executed before).
+    Halted,
+    /// The instruction can not be executed, e.g. it has an invalid
+    /// instruction code or accesses an invalid address. The state of the
+    /// machine is not changed, and the machine stops.
+    Fault(IsaFault),
+}
 
-    let mut n_insts = 0;
+/// Observer of the instructions executed by [`IsaMachine`], e.g. for
+/// tracing.
+pub trait IsaObserver {
+    /// Called after an instruction is executed, where `machine` is in the
+    /// state after the instruction.
+    fn retire(&mut self, inst: &RetiredInst, machine: &IsaMachine);
+}
 
-    loop {
-        n_insts += 1;
-        let icode = bin[pc] >> 4;
-        let ifun = bin[pc] & 0xf;
+/// Print each instruction to the terminal, like the original `yis`.
+struct TtyPrinter;
 
+impl IsaObserver for TtyPrinter {
+    fn retire(&mut self, inst: &RetiredInst, _: &IsaMachine) {
         use crate::utils::GRAY;
-
-        macro_rules! print_inst {
-            () => {
-                if tty_out {
-                    print!(
-                        "{GRAY}{:#06x}{GRAY:#}  icode: {icode:#x} ({}), ifun: {}",
-                        pc,
-                        inst_code::name_of(icode),
-                        ifun
-                    );
-                }
-            };
+        print!(
+            "{GRAY}{:#06x}{GRAY:#}  icode: {:#x} ({}), ifun: {}",
+            inst.pc,
+            inst.icode,
+            inst_code::name_of(inst.icode),
+            inst.ifun
+        );
+        if let Some((ra, rb)) = inst.regs {
+            print!(
+                ", rA: {}, rB: {}",
+                reg_code::name_of(ra),
+                reg_code::name_of(rb)
+            );
         }
-
-        macro_rules! print_reg {
-            () => {
-                if tty_out {
-                    print!(
-                        ", rA: {}, rB: {}",
-                        reg_code::name_of(bin[pc + 1] >> 4),
-                        reg_code::name_of(bin[pc + 1] & 0xf)
-                    );
-                }
-            };
+        if let Some(v) = inst.val_c {
+            print!(", V: {:#x}", v);
         }
+        println!();
+    }
+}
 
-        macro_rules! print_v {
-            ($v:expr) => {
-                if tty_out {
-                    print!(", V: {:#x}", $v);
-                }
+/// Record of an executed instruction for `yis --trace`, with the register
+/// and memory writes of the instruction and the condition codes after it.
+pub fn trace_record(inst: &RetiredInst, machine: &IsaMachine) -> serde_json::Value {
+    use inst_code::*;
+    use reg_code::RSP;
+
+    let regs = machine.regs();
+    let (ra, rb) = inst.regs.unwrap_or((reg_code::RNONE, reg_code::RNONE));
+    let reg_writes: &[u8] = match inst.icode {
+        IRMOVQ | OPQ | IOPQ => &[rb],
+        CMOVX if machine.cc().test(inst.ifun) => &[rb],
+        MRMOVQ => &[ra],
+        POPQ => &[RSP, ra],
+        PUSHQ | CALL | RET => &[RSP],
+        _ => &[],
+    };
+    let reg_writes: serde_json::Map<_, _> = reg_writes
+        .iter()
+        .map(|&r| {
+            let name = reg_code::name_of(r).to_lowercase();
+            (name, serde_json::json!(regs[r as usize]))
+        })
+        .collect();
+    let mem_write = match inst.icode {
+        RMMOVQ => Some(regs[rb as usize].wrapping_add(inst.val_c.unwrap_or_default())),
+        PUSHQ | CALL => Some(regs[RSP as usize]),
+        _ => None,
+    };
+    let mem_writes: Vec<_> = mem_write
+        .and_then(|addr| {
+            let bytes = machine.mem().get(addr as usize..).and_then(|b| b.get(..8));
+            let value = match bytes {
+                Some(bytes) => get_u64(bytes),
+                // a store to a device, where the console keeps only the byte
+                None if addr == crate::mmio::CONSOLE_ADDR => *machine.io().output.last()? as u64,
+                None => machine.io().load(addr)?,
             };
-        }
+            Some(serde_json::json!({ "addr": addr, "value": value }))
+        })
+        .into_iter()
+        .collect();
+    let bytes = &machine.mem()[inst.pc as usize..];
+    let text = match crate::asm::decode(bytes, inst.pc) {
+        Ok((decoded, _)) => decoded.to_string(),
+        Err(_) => String::new(),
+    };
+    let cc = machine.cc();
+    serde_json::json!({
+        "n": machine.n_insts(),
+        "pc": inst.pc,
+        "inst": text,
+        "regs": reg_writes,
+        "mem": mem_writes,
+        "cc": { "zf": cc.zf, "sf": cc.sf, "of": cc.of },
+    })
+}
 
-        macro_rules! print_end {
-            () => {
-                if tty_out {
-                    println!();
-                }
-            };
+/// Write a [`trace_record`] as a JSON line for each instruction. After a
+/// write error, e.g. the reader of the trace exits early, nothing is written
+/// and the caller should stop the simulation.
+pub struct JsonlTracer<W: std::io::Write> {
+    out: W,
+    error: Option<std::io::Error>,
+}
+
+impl<W: std::io::Write> JsonlTracer<W> {
+    pub fn new(out: W) -> Self {
+        Self { out, error: None }
+    }
+
+    /// Whether writing the trace has failed.
+    pub fn failed(&self) -> bool {
+        self.error.is_some()
+    }
+
+    /// Flush the trace, and return the first write error if any.
+    pub fn finish(&mut self) -> std::io::Result<()> {
+        match self.error.take() {
+            Some(err) => Err(err),
+            None => self.out.flush(),
         }
+    }
+}
 
-        let nex_pc = match icode {
-            inst_code::HALT => {
-                if ifun != 0 {
-                    anyhow::bail!("invalid ifun for HALT: {:#x}", ifun);
-                }
-                print_inst!();
-                print_end!();
-                break;
-            }
-            inst_code::NOP => {
-                let nex_pc = pc.checked_add(1).ok_or(anyhow::anyhow!("pc overflow"))?;
-                if ifun != 0 {
-                    anyhow::bail!("invalid ifun for NOP: {:#x}", ifun);
-                }
-                print_inst!();
-                print_end!();
-                nex_pc
+impl<W: std::io::Write> IsaObserver for Rc<RefCell<JsonlTracer<W>>> {
+    fn retire(&mut self, inst: &RetiredInst, machine: &IsaMachine) {
+        let mut tracer = self.borrow_mut();
+        if tracer.error.is_none() {
+            if let Err(err) = writeln!(tracer.out, "{}", trace_record(inst, machine)) {
+                tracer.error = Some(err);
             }
-            inst_code::CMOVX => {
-                let nex_pc = pc.checked_add(2).ok_or(anyhow::anyhow!("pc overflow"))?;
-                let ra = ensure_reg(bin[pc + 1] >> 4)?;
-                let rb = ensure_reg(bin[pc + 1] & 0xf)?;
+        }
+    }
+}
 
-                print_inst!();
-                print_reg!();
-                print_end!();
+/// Reference machine of the Y86 ISA, which executes an instruction in each
+/// [`IsaMachine::step`]. It is used to verify the correctness of the
+/// pipeline architectures.
+///
+/// It supports the extended `iopq` and `jm` instructions, the arithmetic
+/// extension ([`Extension::Arith`]) and, after
+/// [`IsaMachine::enable_exceptions`], the exception extension
+/// ([`Extension::Exception`]). As in CS:APP, the machine stops with the
+/// status `Adr` on an invalid instruction or data address, and `Ins` on an
+/// invalid instruction (including division by zero), unless the fault is
+/// handled.
+pub struct IsaMachine {
+    mem: Box<[u8]>,
+    regs: RegFile,
+    cc: ConditionCode,
+    pc: u64,
+    n_insts: u64,
+    stat: Stat,
+    fault: Option<IsaFault>,
+    io: IoDevices,
+    observers: Vec<Box<dyn IsaObserver>>,
+}
 
-                if reg_cc.test(ifun) {
-                    reg_file[rb] = reg_file[ra];
-                }
-                nex_pc
-            }
-            inst_code::IRMOVQ => {
-                let nex_pc = pc.checked_add(10).ok_or(anyhow::anyhow!("pc overflow"))?;
-                if ifun != 0 {
-                    anyhow::bail!("invalid ifun for IRMOVQ: {:#x}", ifun);
-                }
+impl IsaMachine {
+    /// Create a machine with the given memory, starting at address 0. The
+    /// size of the memory is the length of `bin`.
+    pub fn new(bin: Box<[u8]>) -> Self {
+        Self {
+            mem: bin,
+            regs: [0; 16],
+            cc: CC_INIT,
+            pc: 0,
+            n_insts: 0,
+            stat: Stat::Aok,
+            fault: None,
+            io: IoDevices::default(),
+            observers: Vec::new(),
+        }
+    }
 
-                let ra = bin[pc + 1] >> 4;
-                if ra != reg_code::RNONE {
-                    anyhow::bail!("invalid register code: {:#x}", ra);
-                }
-                let rb = ensure_reg(bin[pc + 1] & 0xf)?;
-                let v = get_u64(&bin[(pc + 2)..(pc + 10)]);
+    /// Implement the exception extension, i.e. map the exception registers
+    /// (see [`crate::mmio::ExceptionRegs`]). Otherwise `trap` and `iret` are
+    /// invalid instructions.
+    pub fn enable_exceptions(&mut self) {
+        self.io.enable_exceptions();
+    }
 
-                print_inst!();
-                print_reg!();
-                print_v!(v);
-                print_end!();
+    /// Notify the observer of each executed instruction.
+    pub fn add_observer(&mut self, observer: Box<dyn IsaObserver>) {
+        self.observers.push(observer);
+    }
 
-                reg_file[rb] = v;
+    /// Address of the next instruction, or of the `halt` or the faulting
+    /// instruction after the machine stops.
+    pub fn pc(&self) -> u64 {
+        self.pc
+    }
 
-                nex_pc
-            }
-            inst_code::RMMOVQ => {
-                let nex_pc = pc.checked_add(10).ok_or(anyhow::anyhow!("pc overflow"))?;
-                if ifun != 0 {
-                    anyhow::bail!("invalid ifun for RMMOVQ: {:#x}", ifun);
-                }
+    pub fn regs(&self) -> &RegFile {
+        &self.regs
+    }
 
-                print_inst!();
-                print_reg!();
-                print_end!();
+    pub fn cc(&self) -> ConditionCode {
+        self.cc
+    }
 
-                let ra = ensure_reg(bin[pc + 1] >> 4)?;
-                let rb = ensure_reg(bin[pc + 1] & 0xf)?;
-                let v = get_u64(&bin[(pc + 2)..(pc + 10)]);
+    pub fn mem(&self) -> &[u8] {
+        &self.mem
+    }
 
-                let addr = (reg_file[rb] as i64 + v as i64) as usize;
-                if addr >= BIN_SIZE {
-                    anyhow::bail!("invalid memory address: {:#x}", addr);
-                }
-                put_u64(&mut bin[addr..(addr + 8)], reg_file[ra]);
+    /// Number of executed instructions, including `halt` but not the
+    /// faulting instruction.
+    pub fn n_insts(&self) -> u64 {
+        self.n_insts
+    }
 
-                nex_pc
-            }
-            inst_code::MRMOVQ => {
-                let nex_pc = pc.checked_add(10).ok_or(anyhow::anyhow!("pc overflow"))?;
-                if ifun != 0 {
-                    anyhow::bail!("invalid ifun for MRMOVQ: {:#x}", ifun);
-                }
-                print_inst!();
-                print_reg!();
-                print_end!();
+    /// `Aok` while running, otherwise the reason the machine stops.
+    pub fn stat(&self) -> Stat {
+        self.stat
+    }
 
-                let ra = ensure_reg(bin[pc + 1] >> 4)?;
-                let rb = ensure_reg(bin[pc + 1] & 0xf)?;
-                let v = get_u64(&bin[(pc + 2)..(pc + 10)]);
+    /// The exception stopping the machine, if any.
+    pub fn fault(&self) -> Option<&IsaFault> {
+        self.fault.as_ref()
+    }
 
-                let addr = (reg_file[rb] as i64 + v as i64) as usize;
-                if addr >= BIN_SIZE {
-                    anyhow::bail!("invalid memory address: {:#x}", addr);
-                }
-                reg_file[ra] = get_u64(&bin[addr..(addr + 8)]);
+    /// Console output, exit code and exception registers of the
+    /// memory-mapped devices.
+    pub fn io(&self) -> &IoDevices {
+        &self.io
+    }
 
-                nex_pc
-            }
-            inst_code::OPQ => {
-                let nex_pc = pc.checked_add(2).ok_or(anyhow::anyhow!("pc overflow"))?;
-                let ra = ensure_reg(bin[pc + 1] >> 4)?;
-                let rb = ensure_reg(bin[pc + 1] & 0xf)?;
-                print_inst!();
-                print_reg!();
-                print_end!();
-
-                let va = reg_file[ra];
-                let vb = reg_file[rb];
-
-                let Some(ve) = arithmetic_compute(va, vb, ifun) else {
-                    anyhow::bail!("invalid ifun for OPQ: {:#x}", ifun);
+    pub fn is_halted(&self) -> bool {
+        self.stat == Stat::Hlt
+    }
+
+    /// The final state of the machine.
+    pub fn into_result(self) -> StandardResult {
+        StandardResult {
+            bin: self.mem,
+            cc: self.cc,
+            regs: self.regs,
+            pc: self.pc as usize,
+            n_insts: self.n_insts,
+            stat: self.stat,
+            io: self.io,
+        }
+    }
+
+    /// Execute the next instruction.
+    pub fn step(&mut self) -> StepOutcome {
+        match (self.stat, &self.fault) {
+            (Stat::Hlt, _) => return StepOutcome::Halted,
+            (_, Some(fault)) => return StepOutcome::Fault(fault.clone()),
+            _ => {}
+        }
+        let inst = match self.execute() {
+            Ok(inst) => inst,
+            Err(fault) if self.enter_fault_handler(&fault) => return StepOutcome::Retired,
+            Err((stat, reason)) => {
+                let fault = IsaFault {
+                    stat,
+                    pc: self.pc,
+                    reason,
                 };
-                reg_cc.set(va, vb, ve, ifun);
-                reg_file[rb] = ve;
+                self.stat = stat;
+                self.fault = Some(fault.clone());
+                return StepOutcome::Fault(fault);
+            }
+        };
+        self.n_insts += 1;
+        let mut observers = std::mem::take(&mut self.observers);
+        for observer in &mut observers {
+            observer.retire(&inst, self);
+        }
+        self.observers = observers;
+        if self.is_halted() {
+            StepOutcome::Halted
+        } else {
+            StepOutcome::Retired
+        }
+    }
 
-                nex_pc
+    /// Enter the handler of a fault at pc if the vector table is set. A `trap`
+    /// failing to enter its handler is not handled again as `Ins`.
+    fn enter_fault_handler(&mut self, (stat, _): &(Stat, String)) -> bool {
+        let trap = inst_code::EXC << 4 | exc_fn::TRAP;
+        if self.mem.get(self.pc as usize) == Some(&trap) {
+            return false;
+        }
+        let cause = match stat {
+            Stat::Adr => exc_cause::CAUSE_ADR,
+            _ => exc_cause::CAUSE_INS,
+        };
+        match self.io.enter_handler(&self.mem, cause, self.pc) {
+            Some(handler) => {
+                self.pc = handler;
+                true
             }
-            inst_code::JX => {
-                let nex_pc = pc.checked_add(9).ok_or(anyhow::anyhow!("pc overflow"))?;
-                let v = get_u64(&bin[(pc + 1)..(pc + 9)]);
+            None => false,
+        }
+    }
 
-                print_inst!();
-                print_v!(v);
-                print_end!();
+    /// Range of the 8 bytes at `addr` in the memory.
+    fn mem_range(&self, addr: u64) -> Result<std::ops::Range<usize>, (Stat, String)> {
+        match addr.checked_add(8) {
+            Some(end) if end <= self.mem.len() as u64 => Ok(addr as usize..end as usize),
+            _ => Err((Stat::Adr, format!("invalid memory address {:#x}", addr))),
+        }
+    }
 
-                if reg_cc.test(ifun) {
-                    v as usize
-                } else {
-                    nex_pc
-                }
-            }
-            inst_code::CALL => {
-                if ifun != 0 {
-                    anyhow::bail!("invalid ifun for CALL: {:#x}", ifun);
-                }
+    fn read_u64(&self, addr: u64) -> Result<u64, (Stat, String)> {
+        if let Some(value) = self.io.load(addr) {
+            return Ok(value);
+        }
+        let range = self.mem_range(addr)?;
+        Ok(get_u64(&self.mem[range]))
+    }
 
-                let v = get_u64(&bin[(pc + 1)..(pc + 9)]);
+    fn write_u64(&mut self, addr: u64, value: u64) -> Result<(), (Stat, String)> {
+        if self.io.store(addr, value) {
+            return Ok(());
+        }
+        let range = self.mem_range(addr)?;
+        put_u64(&mut self.mem[range], value);
+        Ok(())
+    }
 
-                print_inst!();
-                print_v!(v);
-                print_end!();
+    /// Execute the instruction at pc. The state is changed only if the
+    /// instruction succeeds, otherwise the status and the reason are
+    /// returned.
+    fn execute(&mut self) -> Result<RetiredInst, (Stat, String)> {
+        use inst_code::*;
+
+        let pc = self.pc;
+        let invalid_pc = || (Stat::Adr, format!("invalid instruction address {:#x}", pc));
+        let first = *self.mem.get(pc as usize).ok_or_else(invalid_pc)?;
+        let icode = first >> 4;
+        let ifun = first & 0xf;
+        let len =
+            inst_len(icode).ok_or_else(|| (Stat::Ins, format!("invalid icode {:#x}", icode)))?;
+        let bytes = self.mem.get(pc as usize..pc as usize + len);
+        let bytes = bytes.ok_or_else(invalid_pc)?;
+        let regs = matches!(len, 2 | 10).then(|| (bytes[1] >> 4, bytes[1] & 0xf));
+        let inst = RetiredInst {
+            pc,
+            icode,
+            ifun,
+            regs,
+            val_c: match len {
+                9 => Some(get_u64(&bytes[1..9])),
+                10 => Some(get_u64(&bytes[2..10])),
+                _ => None,
+            },
+        };
+        let (ra, rb) = regs.map_or((0, 0), |(a, b)| (a as usize, b as usize));
+        let v = inst.val_c.unwrap_or_default();
+        let val_p = pc + len as u64;
+
+        // only these instructions have function codes
+        let invalid_ifun = || {
+            (
+                Stat::Ins,
+                format!("invalid ifun for {}: {:#x}", name_of(icode), ifun),
+            )
+        };
+        let has_ifun =
+            matches!(icode, CMOVX | OPQ | JX | IOPQ) || icode == EXC && ifun <= exc_fn::IRET;
+        if ifun != 0 && !has_ifun || matches!(icode, CMOVX | JX) && ifun > cond_fn::G {
+            return Err(invalid_ifun());
+        }
+        if matches!(icode, IRMOVQ | IOPQ | JM) && ra != reg_code::RNONE as usize {
+            return Err((Stat::Ins, format!("invalid register code {:#x}", ra)));
+        }
 
-                let rsp = reg_file.get_mut(reg_code::RSP as usize).unwrap();
-                *rsp = rsp.checked_sub(8).ok_or(anyhow::anyhow!("rsp overflow"))?;
-                put_u64(
-                    &mut bin[(*rsp as usize)..(*rsp as usize + 8)],
-                    pc as u64 + 9,
-                );
+        let rsp = reg_code::RSP as usize;
+        let compute = |a: u64, b: u64| {
+            arithmetic_compute(a, b, ifun).ok_or_else(|| {
+                if matches!(ifun, op_code::DIV | op_code::MOD) {
+                    (Stat::Ins, "divide by zero".to_string())
+                } else {
+                    invalid_ifun()
+                }
+            })
+        };
 
-                v as usize
+        let new_pc = match icode {
+            HALT => {
+                self.stat = Stat::Hlt;
+                pc
+            }
+            NOP => val_p,
+            CMOVX => {
+                if self.cc.test(ifun) {
+                    self.regs[rb] = self.regs[ra];
+                }
+                val_p
+            }
+            IRMOVQ => {
+                self.regs[rb] = v;
+                val_p
+            }
+            RMMOVQ => {
+                self.write_u64(self.regs[rb].wrapping_add(v), self.regs[ra])?;
+                val_p
+            }
+            MRMOVQ => {
+                self.regs[ra] = self.read_u64(self.regs[rb].wrapping_add(v))?;
+                val_p
+            }
+            OPQ | IOPQ => {
+                let (a, b) = match icode {
+                    OPQ => (self.regs[ra], self.regs[rb]),
+                    _ => (v, self.regs[rb]),
+                };
+                let e = compute(a, b)?;
+                self.cc.set(a, b, e, ifun);
+                self.regs[rb] = e;
+                val_p
+            }
+            JX if self.cc.test(ifun) => v,
+            JX => val_p,
+            CALL => {
+                let new_rsp = self.regs[rsp].wrapping_sub(8);
+                self.write_u64(new_rsp, val_p)?;
+                self.regs[rsp] = new_rsp;
+                v
+            }
+            RET => {
+                let dest = self.read_u64(self.regs[rsp])?;
+                self.regs[rsp] = self.regs[rsp].wrapping_add(8);
+                dest
             }
-            inst_code::RET => {
-                if ifun != 0 {
-                    anyhow::bail!("invalid ifun for RET: {:#x}", ifun);
+            PUSHQ => {
+                let new_rsp = self.regs[rsp].wrapping_sub(8);
+                self.write_u64(new_rsp, self.regs[ra])?;
+                self.regs[rsp] = new_rsp;
+                val_p
+            }
+            POPQ => {
+                let value = self.read_u64(self.regs[rsp])?;
+                self.regs[rsp] = self.regs[rsp].wrapping_add(8);
+                self.regs[ra] = value;
+                val_p
+            }
+            // jm v(rb): jump to the address stored at M[v + R[rb]]
+            JM => self.read_u64(self.regs[rb].wrapping_add(v))?,
+            EXC if ifun == exc_fn::TRAP => {
+                let handler = self
+                    .io
+                    .enter_handler(&self.mem, exc_cause::CAUSE_TRAP, val_p);
+                handler.ok_or_else(|| (Stat::Ins, "trap without a handler".to_string()))?
+            }
+            EXC => match &self.io.exc {
+                Some(exc) => exc.epc,
+                None => {
+                    return Err((
+                        Stat::Ins,
+                        "iret without the exception extension".to_string(),
+                    ))
                 }
-                print_inst!();
-                print_end!();
+            },
+            _ => unreachable!(),
+        };
+        self.pc = new_pc;
+        Ok(inst)
+    }
+}
 
-                let rsp = reg_file.get_mut(reg_code::RSP as usize).unwrap();
-                let v = get_u64(&bin[(*rsp as usize)..(*rsp as usize + 8)]);
+/// Execute Y86 machine code w.r.t. the ISA specification until it stops.
+/// If `tty_out` is true, each instruction and the final state are printed.
+pub fn simulate(bin: Box<[u8]>, tty_out: bool) -> StandardResult {
+    simulate_machine(IsaMachine::new(bin), tty_out)
+}
 
-                *rsp = rsp.checked_add(8).ok_or(anyhow::anyhow!("rsp overflow"))?;
+/// Run the machine until it stops, like [`simulate`], e.g. a machine with
+/// the exception extension.
+pub fn simulate_machine(mut machine: IsaMachine, tty_out: bool) -> StandardResult {
+    let bin = Box::<[u8]>::from(machine.mem());
+    if tty_out {
+        machine.add_observer(Box::new(TtyPrinter));
+    }
+    while let StepOutcome::Retired = machine.step() {}
 
-                v as usize
-            }
-            inst_code::PUSHQ => {
-                let nex_pc = pc.checked_add(2).ok_or(anyhow::anyhow!("pc overflow"))?;
-                if ifun != 0 {
-                    anyhow::bail!("invalid ifun for PUSHQ: {:#x}", ifun);
-                }
-                print_inst!();
-                print_reg!();
-                print_end!();
+    if tty_out {
+        println!();
+        match machine.fault() {
+            Some(fault) => println!("status: {} ({fault})", machine.stat()),
+            None => println!("status: {}", machine.stat()),
+        }
+        println!("total instructions: {}", machine.n_insts());
+        println!("{}", format_reg_file(*machine.regs()));
+        mem_diff(&bin, machine.mem());
+        machine.io().print();
+    }
 
-                let ra = ensure_reg(bin[pc + 1] >> 4)?;
-                let va = reg_file[ra];
+    machine.into_result()
+}
 
-                let rsp = reg_file.get_mut(reg_code::RSP as usize).unwrap();
-                *rsp = rsp.checked_sub(8).ok_or(anyhow::anyhow!("rsp overflow"))?;
-                let new_rsp = *rsp as usize;
-                put_u64(&mut bin[new_rsp..(new_rsp + 8)], va);
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
 
-                nex_pc
-            }
-            inst_code::POPQ => {
-                let nex_pc = pc.checked_add(2).ok_or(anyhow::anyhow!("pc overflow"))?;
-                if ifun != 0 {
-                    anyhow::bail!("invalid ifun for POPQ: {:#x}", ifun);
-                }
-                print_inst!();
-                print_reg!();
-                print_end!();
+    use super::{
+        arithmetic_compute, op_code::*, simulate, ConditionCode, IsaMachine, IsaObserver,
+        RetiredInst, Stat, StepOutcome,
+    };
+
+    struct Recorder(Rc<RefCell<Vec<RetiredInst>>>);
 
-                let ra = ensure_reg(bin[pc + 1] >> 4)?;
+    impl IsaObserver for Recorder {
+        fn retire(&mut self, inst: &RetiredInst, machine: &IsaMachine) {
+            assert_eq!(machine.n_insts() as usize, self.0.borrow().len() + 1);
+            self.0.borrow_mut().push(*inst);
+        }
+    }
 
-                let rsp = reg_file.get_mut(reg_code::RSP as usize).unwrap();
-                let old_rsp = *rsp as usize;
-                *rsp = rsp.checked_add(8).ok_or(anyhow::anyhow!("rsp overflow"))?;
-                reg_file[ra] = get_u64(&bin[old_rsp..(old_rsp + 8)]);
+    #[test]
+    fn test_isa_machine() {
+        let src = "    irmovq $7, %rax\n    jmp end\n    halt\nend:\n    rmmovq %rax, 8(%rax)\n    halt\n";
+        let a = crate::assemble(src, crate::AssembleOption::default()).unwrap();
+        let mut machine = IsaMachine::new(a.obj.init_mem());
+        let retired = Rc::new(RefCell::new(Vec::new()));
+        machine.add_observer(Box::new(Recorder(retired.clone())));
+
+        assert!(matches!(machine.step(), StepOutcome::Retired));
+        assert_eq!((machine.pc(), machine.regs()[0]), (0xa, 7));
+        assert!(matches!(machine.step(), StepOutcome::Retired));
+        assert_eq!(machine.pc(), 0x14);
+        assert!(matches!(machine.step(), StepOutcome::Retired));
+        assert_eq!(machine.mem()[0xf], 7);
+        assert!(matches!(machine.step(), StepOutcome::Halted));
+        assert!(matches!(machine.step(), StepOutcome::Halted));
+        assert_eq!((machine.pc(), machine.n_insts()), (0x1e, 4));
+
+        let retired = retired.borrow();
+        let pcs: Vec<u64> = retired.iter().map(|inst| inst.pc).collect();
+        assert_eq!(pcs, [0, 0xa, 0x14, 0x1e]);
+        assert_eq!(retired[1].val_c, Some(0x14));
+        assert_eq!(retired[2].regs, Some((0, 0)));
+
+        // a fault leaves the machine unchanged
+        let src = "    irmovq $-8, %rsp\n    pushq %rsp\n    halt\n";
+        let a = crate::assemble(src, crate::AssembleOption::default()).unwrap();
+        let mut machine = IsaMachine::new(a.obj.init_mem());
+        machine.step();
+        let err = match machine.step() {
+            StepOutcome::Fault(e) => e,
+            _ => panic!("pushq should fault"),
+        };
+        assert_eq!(err.stat, Stat::Adr);
+        assert_eq!(
+            err.to_string(),
+            "invalid memory address 0xfffffffffffffff0 at 0xa"
+        );
+        assert_eq!((machine.pc(), machine.n_insts()), (0xa, 1));
+        assert_eq!(machine.regs()[4], -8i64 as u64);
+        assert!(matches!(machine.step(), StepOutcome::Fault(e) if e == err));
+    }
 
-                nex_pc
+    #[test]
+    fn test_trace_record() {
+        let src = "    irmovq $0x100, %rsp\n    irmovq $5, %rax\n    pushq %rax\n    halt\n";
+        let a = crate::assemble(src, crate::AssembleOption::default()).unwrap();
+        let mut machine = IsaMachine::new(a.obj.init_mem());
+        let records = Rc::new(RefCell::new(Vec::new()));
+        struct Tracer(Rc<RefCell<Vec<serde_json::Value>>>);
+        impl IsaObserver for Tracer {
+            fn retire(&mut self, inst: &RetiredInst, machine: &IsaMachine) {
+                self.0.borrow_mut().push(super::trace_record(inst, machine));
             }
-            // extended instruction
-            // iopq v, rb
-            inst_code::IOPQ => {
-                let nex_pc = pc.checked_add(10).ok_or(anyhow::anyhow!("pc overflow"))?;
-                let ra = bin[pc + 1] >> 4;
-                if ra != reg_code::RNONE {
-                    anyhow::bail!("invalid register code: {:#x}", ra);
-                }
-                let rb = ensure_reg(bin[pc + 1] & 0xf)?;
-                let vb = reg_file[rb];
-                let v = get_u64(&bin[(pc + 2)..(pc + 10)]);
+        }
+        machine.add_observer(Box::new(Tracer(records.clone())));
+        while let StepOutcome::Retired = machine.step() {}
+
+        let records = records.borrow();
+        assert_eq!(records.len(), 4);
+        assert_eq!(
+            records[2],
+            serde_json::json!({
+                "n": 3,
+                "pc": 0x14,
+                "inst": "pushq %rax",
+                "regs": { "rsp": 0xf8 },
+                "mem": [{ "addr": 0xf8, "value": 5 }],
+                "cc": { "zf": false, "sf": false, "of": false },
+            })
+        );
+        assert_eq!(records[3]["regs"], serde_json::json!({}));
+
+        // stores to the devices are recorded with the stored values
+        let src = "    irmovq $0x141, %rax\n    rmmovq %rax, 0x10000000\n    \
+                   rmmovq %rax, 0x10000008\n    halt\n";
+        let a = crate::assemble(src, crate::AssembleOption::default()).unwrap();
+        let mut machine = IsaMachine::new(a.obj.init_mem());
+        let records = Rc::new(RefCell::new(Vec::new()));
+        machine.add_observer(Box::new(Tracer(records.clone())));
+        while let StepOutcome::Retired = machine.step() {}
+
+        let records = records.borrow();
+        assert_eq!(
+            [&records[1]["mem"], &records[2]["mem"]],
+            [
+                &serde_json::json!([{ "addr": 0x1000_0000, "value": 0x41 }]),
+                &serde_json::json!([{ "addr": 0x1000_0008, "value": 0x141 }]),
+            ]
+        );
+
+        // the first write error is kept for the caller to stop the simulation
+        struct Closed;
+        impl std::io::Write for Closed {
+            fn write(&mut self, _: &[u8]) -> std::io::Result<usize> {
+                Err(std::io::ErrorKind::BrokenPipe.into())
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+        let mut machine = IsaMachine::new(a.obj.init_mem());
+        let tracer = Rc::new(RefCell::new(super::JsonlTracer::new(Closed)));
+        machine.add_observer(Box::new(tracer.clone()));
+        assert!(matches!(machine.step(), StepOutcome::Retired));
+        assert!(tracer.borrow().failed());
+        let err = tracer.borrow_mut().finish().unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::BrokenPipe);
+    }
 
-                print_inst!();
-                print_reg!();
-                print_v!(v);
-                print_end!();
+    #[test]
+    fn test_stat() {
+        let run = |src: &str| {
+            let a = crate::assemble(src, crate::AssembleOption::default()).unwrap();
+            let res = simulate(a.obj.init_mem(), false);
+            (res.stat, res.pc, res.n_insts)
+        };
+        assert_eq!(run("    nop\n    halt\n"), (Stat::Hlt, 1, 2));
+        // prog10.ys
+        let src = "    irmovq $1, %rax\n    xorq %rsp, %rsp\n    pushq %rax\n    halt\n";
+        assert_eq!(run(src), (Stat::Adr, 0xc, 2));
+        assert_eq!(run("    nop\n    .byte 0xf0\n"), (Stat::Ins, 1, 1));
+        assert_eq!(run("    jmp 0x10000\n"), (Stat::Adr, 0x10000, 1));
+        assert_eq!(run("    mrmovq 0xfff9, %rax\n"), (Stat::Adr, 0, 0));
+        let src = "    irmovq $7, %rax\n    xorq %rbx, %rbx\n    divq %rbx, %rax\n    halt\n";
+        assert_eq!(run(src), (Stat::Ins, 0xc, 2));
+    }
 
-                let Some(ve) = arithmetic_compute(v, vb, ifun) else {
-                    anyhow::bail!("invalid ifun for IOPQ: {:#x}", ifun);
-                };
-                reg_cc.set(v, vb, ve, ifun);
-                reg_file[rb] = ve;
+    #[test]
+    fn test_mem_size() {
+        use crate::{architectures::create_sim, framework::MemData, AsmErrorKind};
 
-                nex_pc
-            }
-            _ => anyhow::bail!("unknown icode: {:#x}", icode),
+        let option = |size| crate::AssembleOption::default().set_mem_size(size);
+        let Err(err) = crate::assemble("    .pos 0x200\n    halt\n", option(0x100)) else {
+            panic!("expect errors");
         };
-        pc = nex_pc;
+        assert_eq!(err.errors[0].kind, AsmErrorKind::AddressOutOfRange(0x200));
+        let a = crate::assemble("    .pos 0x18000\n    .quad 1\n", option(0x20000)).unwrap();
+        assert_eq!((a.obj.mem_size(), a.obj.init_mem()[0x18000]), (0x20000, 1));
+
+        // the stack at the end of the memory
+        let src = "    irmovq $0x100, %rsp\n    pushq %rsp\n    halt\n";
+        for (size, stat) in [(0x100, Stat::Hlt), (0x80, Stat::Adr)] {
+            let a = crate::assemble(src, option(size)).unwrap();
+            assert_eq!(simulate(a.obj.init_mem(), false).stat, stat);
+            let mut sim = create_sim("pipe_std".into(), MemData::init(a.obj.init_mem()), false);
+            while !sim.is_terminate() {
+                sim.step();
+            }
+            assert_eq!(sim.status(), Some(stat));
+        }
     }
 
-    if tty_out {
-        println!();
-        println!("total instructions: {}", n_insts);
-        println!("{}", format_reg_file(reg_file));
-        mem_diff(&original, &bin);
+    #[test]
+    fn test_exception() {
+        use super::exc_cause::*;
+        use crate::mmio::HandlerEntry;
+
+        let src = "    irmovq vectors, %rax
+    rmmovq %rax, 0x10000010
+    trap
+    mrmovq 0x10000, %rbx
+    halt
+trap_h:
+    irmovq $1, %rcx
+    iret
+adr_h:
+    mrmovq 0x10000018, %rdx
+    halt
+    .align 8
+vectors:
+    .quad trap_h
+    .quad adr_h
+";
+        let a = crate::assemble(src, crate::AssembleOption::default()).unwrap();
+        let mut machine = IsaMachine::new(a.obj.init_mem());
+        machine.enable_exceptions();
+        let result = super::simulate_machine(machine, false);
+        assert_eq!((result.stat, result.pc), (Stat::Hlt, 0x35));
+        assert_eq!(&result.regs[1..4], [1, 0x15, 0]);
+        let entry = |cause, handler| HandlerEntry {
+            cause,
+            epc: 0x15,
+            handler,
+        };
+        let entries = [entry(CAUSE_TRAP, 0x20), entry(CAUSE_ADR, 0x2b)];
+        assert_eq!(result.io.handler_entries(), entries);
+        assert_eq!(result.io.exc.unwrap().cause, CAUSE_ADR);
+
+        // without the extension, the registers are not mapped
+        let result = simulate(a.obj.init_mem(), false);
+        assert_eq!(
+            (result.stat, result.pc, result.io.exc),
+            (Stat::Adr, 0xa, None)
+        );
+
+        // no vector table
+        let a = crate::assemble("    trap\n    halt\n", crate::AssembleOption::default()).unwrap();
+        let mut machine = IsaMachine::new(a.obj.init_mem());
+        machine.enable_exceptions();
+        assert!(matches!(machine.step(), StepOutcome::Fault(e) if e.stat == Stat::Ins));
+        assert_eq!(
+            machine.fault().unwrap().to_string(),
+            "trap without a handler at 0x0"
+        );
     }
 
-    Ok(StandardResult {
-        bin,
-        cc: reg_cc,
-        regs: reg_file,
-        pc,
-        n_insts,
-    })
+    #[test]
+    fn test_arith() {
+        let compute = |a: i64, b: i64, op| arithmetic_compute(a as u64, b as u64, op);
+        assert_eq!(compute(3, -7, MUL), Some(-21i64 as u64));
+        assert_eq!(compute(2, -7, DIV), Some(-3i64 as u64));
+        assert_eq!(compute(2, -7, MOD), Some(-1i64 as u64));
+        assert_eq!(compute(0, 7, DIV), None);
+        assert_eq!(compute(-1, i64::MIN, DIV), Some(i64::MIN as u64));
+        assert_eq!(compute(0x41, -8, SAR), Some(-4i64 as u64));
+        assert_eq!(compute(0x41, -8, SHR), Some(u64::MAX >> 1 & !3));
+        assert_eq!(compute(4, 1, SHL), Some(16));
+
+        let mut cc = ConditionCode::default();
+        let (a, b) = (3u64, 0x4000_0000_0000_0000u64);
+        cc.set(a, b, b.wrapping_mul(a), MUL);
+        assert!(cc.of && cc.sf);
+        let (a, b) = (-1i64 as u64, i64::MIN as u64);
+        cc.set(a, b, b, DIV);
+        assert!(cc.of);
+        cc.set(a, b, 0, MOD);
+        assert!(cc.of && cc.zf);
+
+        let src = "    irmovq $7, %rax\n    xorq %rbx, %rbx\n    divq %rbx, %rax\n    halt\n";
+        let a = crate::assemble(src, crate::AssembleOption::default()).unwrap();
+        let mut machine = IsaMachine::new(a.obj.init_mem());
+        while let StepOutcome::Retired = machine.step() {}
+        assert_eq!(
+            machine.fault().unwrap().to_string(),
+            "divide by zero at 0xc"
+        );
+    }
 }
@@ -1,15 +1,23 @@
 pub mod architectures;
-mod asm;
+pub mod asm;
 mod dsl;
 pub mod framework;
 pub mod isa;
 mod lab;
+pub mod link;
+pub mod map;
+pub mod mmio;
 mod object;
+pub mod profile;
 pub mod test;
 pub mod utils;
+#[cfg(feature = "serde")]
+pub mod vcd;
 
-pub use asm::{assemble, AssembleOption};
-pub use object::{ObjectExt, SourceInfo, BIN_SIZE};
+pub use asm::{
+    assemble, assemble_relocatable, AsmError, AsmErrorKind, AsmErrors, AssembleOption, Severity,
+};
+pub use object::{ObjectExt, SourceInfo};
 
 #[cfg(test)]
 mod tests {
@@ -0,0 +1,589 @@
+//! Relocatable objects and the linker.
+//!
+//! `yas -c` assembles a source file into a [`RelocObject`], whose contents are
+//! placed in sections at offsets relative to the section. Uses of labels are
+//! recorded as relocations. [`link`] lays out the sections of all objects,
+//! resolves the symbols across objects and patches the relocated fields,
+//! producing the same [`ObjectExt`] as [`crate::assemble`].
+//!
+//! The object file is a text file. Each section starts with a header followed
+//! by its listing in the format of `.yo` file, and the symbol table and the
+//! relocations follow the sections:
+//!
+//! ```text
+//! .section .text 0x13 1
+//! 0x0000: 30f41300000000000000 |     irmovq stack, %rsp
+//! 0x000a: 800000000000000000   |     call main
+//! 0x0013:                      | stack:
+//! .symbol local stack .text 0x13
+//! .symbol global main
+//! .reloc .text 0x2 8 0x0 stack
+//! .reloc .text 0xb 8 0xa main
+//! ```
+//!
+//! A symbol is either defined in a section at an offset, or undefined and
+//! imported from other objects. A relocation gives the section, the offset
+//! and the size of the field, the offset of the line (used as the value of
+//! `.`), and the expression to evaluate.
+
+use std::{collections::BTreeMap, fmt::Display};
+
+use anyhow::bail;
+
+use crate::{
+    asm::{self, AsmError, AsmErrorKind, AsmErrors, LineError},
+    framework::{MAX_MEM_SIZE, MEM_SIZE},
+    object::{fits_in, parse_yo_line, push_yo_line, LineInfo, Object, SymbolMap},
+    ObjectExt, SourceInfo,
+};
+
+/// Visibility of a symbol.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Binding {
+    /// only visible in the object that defines it
+    Local,
+    /// exported by `.global`, or imported from other objects
+    Global,
+}
+
+/// Contents placed at offsets relative to the start of the section.
+#[derive(Debug, Clone)]
+pub struct Section {
+    pub name: String,
+    /// the start address of the section is a multiple of it
+    pub align: u64,
+    pub bytes: Vec<u8>,
+    /// listing of the section, where addresses are offsets in the section
+    pub lines: Vec<LineInfo>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Symbol {
+    pub name: String,
+    pub binding: Binding,
+    /// index of the section and the offset in it, or `None` if the symbol is
+    /// defined in another object
+    pub def: Option<(usize, u64)>,
+}
+
+/// A field whose value depends on the addresses of symbols.
+#[derive(Debug, Clone)]
+pub struct Reloc {
+    /// index of the section
+    pub section: usize,
+    /// offset of the field in the section
+    pub offset: u64,
+    /// size of the field in bytes
+    pub size: u8,
+    /// offset of the line in the section, which is the value of `.`
+    pub here: u64,
+    pub expr: asm::Imm,
+}
+
+/// Relocatable object produced by [`crate::assemble_relocatable`].
+///
+/// To render the object file, use `format!("{}", obj)`.
+#[derive(Debug, Clone, Default)]
+pub struct RelocObject {
+    pub sections: Vec<Section>,
+    pub symbols: Vec<Symbol>,
+    pub relocs: Vec<Reloc>,
+    /// warnings reported by the assembler, which are not written to the file
+    pub warnings: Vec<AsmError>,
+}
+
+impl Display for RelocObject {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for section in &self.sections {
+            let size = section.bytes.len();
+            writeln!(f, ".section {} {size:#x} {}", section.name, section.align)?;
+            for line in &section.lines {
+                line.fmt_listing(f, &section.bytes)?;
+            }
+        }
+        for symbol in &self.symbols {
+            let binding = match symbol.binding {
+                Binding::Local => "local",
+                Binding::Global => "global",
+            };
+            write!(f, ".symbol {binding} {}", symbol.name)?;
+            if let Some((section, offset)) = symbol.def {
+                write!(f, " {} {offset:#x}", self.sections[section].name)?;
+            }
+            writeln!(f)?;
+        }
+        for reloc in &self.relocs {
+            writeln!(
+                f,
+                ".reloc {} {:#x} {} {:#x} {}",
+                self.sections[reloc.section].name, reloc.offset, reloc.size, reloc.here, reloc.expr
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl RelocObject {
+    /// Parse an object file written by the [`Display`] of [`RelocObject`].
+    /// `file_name` is used in the diagnostics.
+    pub fn parse(src: &str, file_name: &str) -> Result<Self, AsmErrors> {
+        let mut obj = Self::default();
+        // contents of the current section
+        let mut scratch = Object::default();
+        let mut errors = Vec::new();
+        for (index, text) in src.lines().enumerate() {
+            if let Err(err) = obj.parse_line(text, index + 1, &mut scratch) {
+                errors.push(AsmError::new(file_name, index + 1, text, err));
+            }
+        }
+        obj.finish_section(&scratch);
+        if !errors.is_empty() {
+            return Err(AsmErrors::new(file_name, errors));
+        }
+        Ok(obj)
+    }
+
+    /// Copy the contents of the current section from `scratch`.
+    fn finish_section(&mut self, scratch: &Object) {
+        if let Some(section) = self.sections.last_mut() {
+            let size = section.bytes.len();
+            section.bytes.copy_from_slice(&scratch.binary()[..size]);
+        }
+    }
+
+    fn section_index(&self, name: &str, offset: usize) -> Result<usize, LineError> {
+        self.sections
+            .iter()
+            .position(|s| s.name == name)
+            .ok_or_else(|| {
+                LineError::new(
+                    AsmErrorKind::Syntax(format!("unknown section `{name}`")),
+                    offset..offset + name.len(),
+                )
+            })
+    }
+
+    fn parse_line(
+        &mut self,
+        text: &str,
+        line: usize,
+        scratch: &mut Object,
+    ) -> Result<(), LineError> {
+        // fields separated by spaces with their byte offsets
+        let mut fields = text.split(' ').scan(0, |offset, field| {
+            let start = *offset;
+            *offset += field.len() + 1;
+            Some((start, field))
+        });
+        let mut next = |what: &str| {
+            fields.next().filter(|(_, s)| !s.is_empty()).ok_or_else(|| {
+                LineError::new(
+                    AsmErrorKind::Syntax(format!("expect {what}")),
+                    text.len()..text.len(),
+                )
+            })
+        };
+        let number = |(offset, s): (usize, &str)| {
+            crate::utils::parse_literal(s).ok_or_else(|| {
+                LineError::new(
+                    AsmErrorKind::InvalidNumber(s.to_string()),
+                    offset..offset + s.len(),
+                )
+            })
+        };
+
+        if text.starts_with(".section ") {
+            next("directive")?;
+            self.finish_section(scratch);
+            let name = next("section name")?.1.to_string();
+            let size = number(next("section size")?)?;
+            if size > MAX_MEM_SIZE as u64 {
+                return Err(LineError::new(
+                    AsmErrorKind::AddressOutOfRange(size),
+                    0..text.len(),
+                ));
+            }
+            *scratch = Object::new(size as usize);
+            let align = number(next("alignment")?)?;
+            self.sections.push(Section {
+                name,
+                align: align.max(1),
+                bytes: vec![0; size as usize],
+                lines: Vec::new(),
+            });
+        } else if text.starts_with(".symbol ") {
+            next("directive")?;
+            let (offset, binding) = next("binding")?;
+            let binding = match binding {
+                "local" => Binding::Local,
+                "global" => Binding::Global,
+                _ => {
+                    return Err(LineError::new(
+                        AsmErrorKind::Syntax(format!("invalid binding `{binding}`")),
+                        offset..offset + binding.len(),
+                    ))
+                }
+            };
+            let name = next("symbol name")?.1.to_string();
+            let def = match next("section") {
+                Ok((offset, section)) => {
+                    let section = self.section_index(section, offset)?;
+                    Some((section, number(next("offset")?)?))
+                }
+                Err(_) => None,
+            };
+            self.symbols.push(Symbol { name, binding, def });
+        } else if text.starts_with(".reloc ") {
+            next("directive")?;
+            let (offset, section) = next("section")?;
+            let section = self.section_index(section, offset)?;
+            let (offset_offset, offset_str) = next("offset")?;
+            let offset = number((offset_offset, offset_str))?;
+            let (size_offset, size_str) = next("size")?;
+            let size = match number((size_offset, size_str))? {
+                size @ (1 | 2 | 4 | 8) => size as u8,
+                _ => {
+                    return Err(LineError::new(
+                        AsmErrorKind::Syntax(format!("invalid field size `{size_str}`")),
+                        size_offset..size_offset + size_str.len(),
+                    ))
+                }
+            };
+            // the field must be inside the section
+            let len = self.sections[section].bytes.len() as u64;
+            if offset.checked_add(size as u64).is_none_or(|end| end > len) {
+                return Err(LineError::new(
+                    AsmErrorKind::AddressOutOfRange(offset),
+                    offset_offset..offset_offset + offset_str.len(),
+                ));
+            }
+            let (here_offset, here) = next("line offset")?;
+            let here = number((here_offset, here))?;
+            // the expression takes the rest of the line
+            let start = here_offset + text[here_offset..].find(' ').unwrap_or(0) + 1;
+            let expr =
+                asm::Imm::parse_str(text.get(start..).unwrap_or_default()).map_err(|mut err| {
+                    err.range = err.range.start + start..err.range.end + start;
+                    err
+                })?;
+            self.relocs.push(Reloc {
+                section,
+                offset,
+                size,
+                here,
+                expr,
+            });
+        } else {
+            let Some(section) = self.sections.last_mut() else {
+                return Err(LineError::new(
+                    AsmErrorKind::Syntax("expect a section header".to_string()),
+                    0..text.len(),
+                ));
+            };
+            let mut info = LineInfo {
+                file: 0,
+                line,
+                addr: None,
+                inst: None,
+                label: None,
+                data: None,
+                constant: None,
+                src: text.to_string(),
+            };
+            parse_yo_line(text, &mut info, scratch)?;
+            if let Some(addr) = info.addr {
+                if addr + info.size() as u64 > section.bytes.len() as u64 {
+                    return Err(LineError::new(
+                        AsmErrorKind::AddressOutOfRange(addr),
+                        0..text.len(),
+                    ));
+                }
+            }
+            push_yo_line(&mut section.lines, info);
+        }
+        Ok(())
+    }
+}
+
+/// Options of [`link`].
+#[derive(Default)]
+pub struct LinkOption {
+    section_start: BTreeMap<String, u64>,
+    mem_size: Option<usize>,
+}
+
+impl LinkOption {
+    /// Place the section at the given address. Sections without a start
+    /// address follow the previous section.
+    pub fn set_section_start(mut self, name: impl Into<String>, addr: u64) -> Self {
+        self.section_start.insert(name.into(), addr);
+        self
+    }
+
+    /// Set the size of the memory the program is placed in, which is
+    /// [`MEM_SIZE`] by default.
+    pub fn set_mem_size(mut self, mem_size: usize) -> Self {
+        self.mem_size = Some(mem_size);
+        self
+    }
+}
+
+/// Link relocatable objects with their file names into an object.
+///
+/// Sections with the same name are placed together, in the order of their
+/// first appearance. The first section starts at address 0 unless specified
+/// by [`LinkOption::set_section_start`]. Global symbols are shared by all
+/// objects, while local symbols are only visible in their own objects.
+pub fn link(objects: &[(String, RelocObject)], option: &LinkOption) -> anyhow::Result<ObjectExt> {
+    let mem_size = option.mem_size.unwrap_or(MEM_SIZE);
+    let mut names: Vec<&str> = Vec::new();
+    for (_, obj) in objects {
+        for section in &obj.sections {
+            if !names.contains(&section.name.as_str()) {
+                names.push(&section.name);
+            }
+        }
+    }
+
+    // start address of each section of each object
+    let mut bases = vec![Vec::new(); objects.len()];
+    // (start, end, object, section) of all sections
+    let mut placed = Vec::new();
+    let mut cur = 0;
+    for name in &names {
+        if let Some(start) = option.section_start.get(*name) {
+            cur = *start;
+        }
+        for (index, (file, obj)) in objects.iter().enumerate() {
+            for (sec_index, section) in obj.sections.iter().enumerate() {
+                if section.name != *name {
+                    continue;
+                }
+                let base = cur.next_multiple_of(section.align);
+                let end = base + section.bytes.len() as u64;
+                if end > mem_size as u64 {
+                    bail!("section `{name}` of `{file}` is out of the memory range");
+                }
+                bases[index].resize(obj.sections.len(), 0);
+                bases[index][sec_index] = base;
+                placed.push((base, end, index, sec_index));
+                cur = end;
+            }
+        }
+    }
+    placed.sort();
+    for pair in placed.windows(2) {
+        let (_, end, a, a_sec) = pair[0];
+        let (start, _, b, b_sec) = pair[1];
+        if start < end {
+            bail!(
+                "section `{}` of `{}` overlaps with section `{}` of `{}`",
+                objects[b].1.sections[b_sec].name,
+                objects[b].0,
+                objects[a].1.sections[a_sec].name,
+                objects[a].0,
+            );
+        }
+    }
+
+    let addr_of = |index: usize, (section, offset): (usize, u64)| bases[index][section] + offset;
+    let mut globals = SymbolMap::new();
+    let mut defined_in = BTreeMap::new();
+    for (index, (file, obj)) in objects.iter().enumerate() {
+        for symbol in &obj.symbols {
+            if let (Binding::Global, Some(def)) = (symbol.binding, symbol.def) {
+                if let Some(prev) = defined_in.insert(&symbol.name, file) {
+                    bail!(
+                        "symbol `{}` is defined in both `{prev}` and `{file}`",
+                        symbol.name
+                    );
+                }
+                globals.insert(symbol.name.clone(), addr_of(index, def));
+            }
+        }
+    }
+
+    let mut out = Object::new(mem_size);
+    let mut symbols = globals.clone();
+    let mut lines = Vec::new();
+    let mut errors = Vec::new();
+    for (index, (file, obj)) in objects.iter().enumerate() {
+        for (sec_index, section) in obj.sections.iter().enumerate() {
+            let base = bases[index][sec_index] as usize;
+            out.binary_mut()[base..base + section.bytes.len()].copy_from_slice(&section.bytes);
+        }
+
+        let mut scope = globals.clone();
+        for symbol in &obj.symbols {
+            if let (Binding::Local, Some(def)) = (symbol.binding, symbol.def) {
+                scope.insert(symbol.name.clone(), addr_of(index, def));
+                if !asm::is_local_label(&symbol.name) {
+                    symbols
+                        .entry(symbol.name.clone())
+                        .or_insert(addr_of(index, def));
+                }
+            }
+        }
+        for reloc in &obj.relocs {
+            let base = bases[index][reloc.section];
+            let result = reloc.expr.eval(&scope, base + reloc.here);
+            let addr = base + reloc.offset;
+            if addr + reloc.size as u64 > out.mem_size() as u64 {
+                errors.push(format!("{file}: {}", AsmErrorKind::AddressOutOfRange(addr)));
+                continue;
+            }
+            match result {
+                Ok(value) if fits_in(value, reloc.size) => {
+                    out.write_num_data(addr as usize, reloc.size, value)
+                }
+                Ok(value) => errors.push(format!(
+                    "{file}: {}",
+                    AsmErrorKind::ValueOutOfRange {
+                        value,
+                        size: reloc.size
+                    }
+                )),
+                Err(err) => errors.push(format!("{file}: {}", err.kind)),
+            }
+        }
+    }
+    if !errors.is_empty() {
+        errors.dedup();
+        bail!("could not link the objects:\n{}", errors.join("\n"));
+    }
+
+    // listing in the order of addresses
+    for (base, _, index, sec_index) in placed {
+        for line in &objects[index].1.sections[sec_index].lines {
+            let mut line = line.clone();
+            line.file = index;
+            if let Some(addr) = line.addr.as_mut() {
+                *addr += base;
+            }
+            if let (Some(addr), Some(asm::Data::Bytes(bytes))) = (line.addr, &mut line.data) {
+                let range = addr as usize..addr as usize + bytes.len();
+                bytes.copy_from_slice(&out.binary()[range]);
+            }
+            if let Some(constant) = &line.constant {
+                out.constants
+                    .insert(constant.name.clone(), constant.value.unwrap_or_default());
+            }
+            lines.push(line);
+        }
+    }
+    out.symbols = symbols;
+    Ok(ObjectExt {
+        obj: out,
+        source: SourceInfo::new(lines, objects.iter().map(|(f, _)| f.clone()).collect()),
+        warnings: Vec::new(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{link, LinkOption, RelocObject};
+    use crate::{assemble, assemble_relocatable, AssembleOption};
+
+    const DRIVER: &str = "    .global main
+    irmovq stack, %rsp
+    call main
+    halt
+main:
+    irmovq src, %rdi
+    irmovq $len, %rdx
+    call ncopy
+    ret
+    .section .data
+    .align 8
+src:
+    .quad 1
+    .quad -2
+end:
+    .equ len, (end - src) / 8
+    .pos 0x100
+stack:
+";
+
+    const NCOPY: &str = "    .global ncopy
+ncopy:
+    xorq %rax, %rax
+loop:
+    mrmovq (%rdi), %r10
+    addq %r10, %rax
+    iaddq $8, %rdi
+    iaddq $-1, %rdx
+    jg loop
+    ret
+";
+
+    fn object(src: &str, name: &str) -> (String, RelocObject) {
+        let option = AssembleOption::default().set_file_name(name);
+        let obj = assemble_relocatable(src, option).unwrap();
+        // objects are linked after a round trip through the file format
+        let text = obj.to_string();
+        let parsed = RelocObject::parse(&text, name).unwrap();
+        assert_eq!(parsed.to_string(), text);
+        (name.to_string(), parsed)
+    }
+
+    #[test]
+    fn test_link() {
+        let objects = [object(DRIVER, "driver.ys"), object(NCOPY, "ncopy.ys")];
+        let a = link(&objects, &LinkOption::default()).unwrap();
+
+        // the same layout assembled as a single file
+        let (text, data) = DRIVER.split_once("    .section .data\n").unwrap();
+        let stack = format!(".pos {:#x}", a.obj.symbols["src"] + 0x100);
+        let data = data.replace(".pos 0x100", &stack);
+        let src = format!("{text}{}{data}", NCOPY.replace(".global ncopy", ""));
+        let b = assemble(&src, AssembleOption::default()).unwrap();
+        assert_eq!(a.obj.init_mem(), b.obj.init_mem());
+        assert_eq!(a.obj.symbols, b.obj.symbols);
+        assert_eq!(a.obj.constants["len"], 2);
+        // lines are located in the object files, after the section headers
+        assert_eq!(a.source.get_line_number_by_addr(0x1e), Some((0, 8)));
+        let ncopy = a.obj.symbols["ncopy"];
+        assert_eq!(a.source.get_line_number_by_addr(ncopy), Some((1, 2)));
+
+        let option = LinkOption::default().set_section_start(".data", 0x400);
+        let a = link(&objects, &option).unwrap();
+        assert_eq!(a.obj.symbols["src"], 0x400);
+        assert_eq!(a.obj.symbols["stack"], 0x500);
+    }
+
+    #[test]
+    fn test_link_errors() {
+        let driver = object(DRIVER, "driver.ys");
+        let err = link(std::slice::from_ref(&driver), &LinkOption::default())
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("undefined symbol `ncopy`"));
+
+        let ncopy = object(NCOPY, "ncopy.ys");
+        let err = link(&[driver, ncopy.clone(), ncopy], &LinkOption::default())
+            .err()
+            .unwrap();
+        assert_eq!(
+            err.to_string(),
+            "symbol `ncopy` is defined in both `ncopy.ys` and `ncopy.ys`"
+        );
+
+        // sections are only available in relocatable objects
+        let err = assemble(DRIVER, AssembleOption::default()).err().unwrap();
+        assert!(err
+            .errors
+            .iter()
+            .any(|e| e.kind.to_string() == "`.section` is only supported in relocatable objects"));
+        let src = "    .global len\n    .equ len, 3\n    .equ here, .\n";
+        let err = assemble_relocatable(src, AssembleOption::default()).unwrap_err();
+        assert_eq!(err.errors.len(), 2);
+
+        // relocated fields must be inside the section
+        let text = ".section .text 0x10 1\n.section .data 0x8 8\n\
+                    .reloc .text 0xfffc 8 0x0 main\n.reloc .text 0xc 8 0x0 main\n\
+                    .reloc .data 0x0 3 0x0 main\n.reloc .data 0x0 8 0x0 main\n";
+        let err = RelocObject::parse(text, "a.yo").unwrap_err();
+        let kinds: Vec<_> = err.errors.iter().map(|e| (e.line, e.column)).collect();
+        assert_eq!(kinds, [(3, 14), (4, 14), (5, 18)]);
+    }
+}
@@ -0,0 +1,270 @@
+//! Symbol map and cross-reference listing of an assembled object, written by
+//! `yas --map` and `yas --xref`.
+//!
+//! Both listings are rendered as text by [`Display`], or as JSON by
+//! `to_json`.
+
+use std::{collections::BTreeMap, fmt::Display};
+
+use serde_json::{json, Value};
+
+use crate::{asm, ObjectExt};
+
+/// Kind of the contents at a symbol, inferred from the line following it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolKind {
+    /// the symbol is followed by an instruction
+    Code,
+    /// the symbol is followed by data, or by nothing (e.g. the stack)
+    Data,
+}
+
+impl SymbolKind {
+    fn name(self) -> &'static str {
+        match self {
+            SymbolKind::Code => "code",
+            SymbolKind::Data => "data",
+        }
+    }
+}
+
+/// A line in a source file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Location {
+    pub file: String,
+    pub line: usize,
+}
+
+impl Display for Location {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.file, self.line)
+    }
+}
+
+impl Location {
+    fn to_json(&self) -> Value {
+        json!({ "file": self.file, "line": self.line })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MapEntry {
+    pub name: String,
+    pub addr: u64,
+    /// distance to the next symbol at a higher address, or to the end of the
+    /// contents for the last one
+    pub size: u64,
+    pub kind: SymbolKind,
+    pub def: Location,
+}
+
+/// All labels of an object in the order of their addresses.
+#[derive(Debug, Clone)]
+pub struct SymbolMapListing {
+    pub entries: Vec<MapEntry>,
+}
+
+#[derive(Debug, Clone)]
+pub struct XrefEntry {
+    pub name: String,
+    pub def: Location,
+    /// lines using the label in their instructions, data or constants
+    pub refs: Vec<Location>,
+}
+
+/// References of all labels of an object in the order of their names.
+#[derive(Debug, Clone)]
+pub struct XrefListing {
+    pub entries: Vec<XrefEntry>,
+}
+
+impl ObjectExt {
+    fn location(&self, index: usize) -> Location {
+        let line = &self.source.lines()[index];
+        Location {
+            file: self.source.files()[line.file].clone(),
+            line: line.line,
+        }
+    }
+
+    /// Index of the line defining each label.
+    fn label_lines(&self) -> BTreeMap<&str, usize> {
+        let lines = self.source.lines().iter().enumerate();
+        lines
+            .filter_map(|(index, line)| Some((line.label.as_deref()?, index)))
+            .filter(|(name, _)| !asm::is_local_label(name))
+            .collect()
+    }
+
+    /// Build the symbol map of the labels.
+    pub fn symbol_map(&self) -> SymbolMapListing {
+        let lines = self.source.lines();
+        let end = lines
+            .iter()
+            .filter_map(|line| Some(line.addr? + line.size() as u64))
+            .max()
+            .unwrap_or_default();
+        let mut entries: Vec<MapEntry> = self
+            .label_lines()
+            .into_iter()
+            .map(|(name, index)| {
+                let addr = self.obj.symbols.get(name).copied().unwrap_or_default();
+                // the first contents at the address of the label
+                let contents = lines[index..]
+                    .iter()
+                    .take_while(|line| line.addr.is_none_or(|a| a == addr))
+                    .find(|line| line.size() > 0);
+                let kind = match contents {
+                    Some(line) if line.inst.is_some() => SymbolKind::Code,
+                    _ => SymbolKind::Data,
+                };
+                MapEntry {
+                    name: name.to_string(),
+                    addr,
+                    size: 0,
+                    kind,
+                    def: self.location(index),
+                }
+            })
+            .collect();
+        entries.sort_by_key(|e| e.addr);
+        let addrs: Vec<u64> = entries.iter().map(|e| e.addr).collect();
+        for entry in &mut entries {
+            let next = addrs.iter().find(|a| **a > entry.addr).copied();
+            entry.size = next.unwrap_or(end.max(entry.addr)) - entry.addr;
+        }
+        SymbolMapListing { entries }
+    }
+
+    /// Build the cross-reference listing of the labels.
+    pub fn xref(&self) -> XrefListing {
+        let mut refs: BTreeMap<&str, Vec<Location>> = BTreeMap::new();
+        for (index, line) in self.source.lines().iter().enumerate() {
+            let values = line.value_fields().into_iter().map(|(_, _, imm)| imm);
+            let exprs = values.chain(line.constant.as_ref().map(|c| &c.expr));
+            let mut names: Vec<&str> = exprs.flat_map(|imm| imm.symbols()).collect();
+            names.sort();
+            names.dedup();
+            for name in names {
+                refs.entry(name).or_default().push(self.location(index));
+            }
+        }
+        let entries = self
+            .label_lines()
+            .into_iter()
+            .map(|(name, index)| XrefEntry {
+                name: name.to_string(),
+                def: self.location(index),
+                refs: refs.remove(name).unwrap_or_default(),
+            })
+            .collect();
+        XrefListing { entries }
+    }
+}
+
+impl SymbolMapListing {
+    pub fn to_json(&self) -> Value {
+        let entries = self.entries.iter().map(|e| {
+            json!({
+                "name": e.name,
+                "addr": e.addr,
+                "size": e.size,
+                "section": e.kind.name(),
+                "def": e.def.to_json(),
+            })
+        });
+        Value::Array(entries.collect())
+    }
+}
+
+impl Display for SymbolMapListing {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let width = self.entries.iter().map(|e| e.name.len()).max();
+        let width = width.unwrap_or_default().max("symbol".len());
+        writeln!(f, "address size    section {:width$} defined at", "symbol")?;
+        for e in &self.entries {
+            writeln!(
+                f,
+                "{:#06x}  {:#06x}  {:7} {:width$} {}",
+                e.addr,
+                e.size,
+                e.kind.name(),
+                e.name,
+                e.def
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl XrefListing {
+    pub fn to_json(&self) -> Value {
+        let entries = self.entries.iter().map(|e| {
+            json!({
+                "name": e.name,
+                "def": e.def.to_json(),
+                "refs": e.refs.iter().map(Location::to_json).collect::<Vec<_>>(),
+            })
+        });
+        Value::Array(entries.collect())
+    }
+}
+
+impl Display for XrefListing {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let width = self.entries.iter().map(|e| e.name.len()).max();
+        let width = width.unwrap_or_default().max("symbol".len());
+        let def_width = self.entries.iter().map(|e| e.def.to_string().len()).max();
+        let def_width = def_width.unwrap_or_default().max("defined at".len());
+        writeln!(
+            f,
+            "{:width$} {:def_width$} referenced at",
+            "symbol", "defined at"
+        )?;
+        for e in &self.entries {
+            let def = e.def.to_string();
+            write!(f, "{:width$} {def:def_width$}", e.name)?;
+            for (i, r) in e.refs.iter().enumerate() {
+                match i {
+                    0 => write!(f, " {r}")?,
+                    _ => write!(f, "\n{:width$} {:def_width$} {r}", "", "")?,
+                }
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SymbolKind;
+    use crate::{asm::tests::RSUM_YS, assemble, AssembleOption};
+
+    #[test]
+    fn test_symbol_map() {
+        let a = assemble(RSUM_YS, AssembleOption::default()).unwrap();
+        let map = a.symbol_map();
+        let sum_list = map.entries.iter().find(|e| e.name == "sum_list").unwrap();
+        assert_eq!(sum_list.addr, a.obj.symbols["sum_list"]);
+        assert_eq!(sum_list.kind, SymbolKind::Code);
+        let ele1 = map.entries.iter().find(|e| e.name == "ele1").unwrap();
+        assert_eq!((ele1.size, ele1.kind), (0x10, SymbolKind::Data));
+        let stack = map.entries.last().unwrap();
+        assert_eq!((stack.name.as_str(), stack.size), ("stack", 0));
+        assert!(map.to_string().contains("sum_list_ret"));
+        assert_eq!(map.to_json()[0]["name"], map.entries[0].name);
+
+        let xref = a.xref();
+        let sum_list = xref.entries.iter().find(|e| e.name == "sum_list").unwrap();
+        assert_eq!(sum_list.def.line, 9);
+        let lines: Vec<usize> = sum_list.refs.iter().map(|r| r.line).collect();
+        assert_eq!(lines, [6, 22]);
+        let json = xref.to_json();
+        assert!(json
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|e| e["name"] == "stack"));
+    }
+}
@@ -0,0 +1,244 @@
+//! Memory-mapped I/O devices, which are shared by the ISA simulator and the
+//! data memory of the architectures.
+//!
+//! The devices are mapped above the largest memory
+//! ([`crate::framework::MAX_MEM_SIZE`]), so that they never hide any byte of
+//! the memory. A quad word stored to the address of a device is consumed by
+//! the device, and a load from the address reads the state of the device.
+//! For example, a program prints `hi` and exits with code 3 by
+//!
+//! ```asm
+//!     irmovq $0x68, %rax
+//!     rmmovq %rax, 0x10000000     # 'h'
+//!     irmovq $0x69, %rax
+//!     rmmovq %rax, 0x10000000     # 'i'
+//!     irmovq $3, %rax
+//!     rmmovq %rax, 0x10000008
+//!     halt
+//! ```
+
+/// Storing to this address appends the low byte to the console output.
+/// Loading from it reads 0.
+pub const CONSOLE_ADDR: u64 = 0x1000_0000;
+
+/// Storing to this address sets the exit code of the program. Loading from it
+/// reads the exit code, or 0 if it is not set.
+pub const EXIT_ADDR: u64 = 0x1000_0008;
+
+const _: () = assert!(crate::framework::MAX_MEM_SIZE as u64 <= CONSOLE_ADDR);
+
+/// Address of the vector table base register.
+pub const VECTOR_ADDR: u64 = 0x1000_0010;
+
+/// Address of the EPC register, i.e. the return address of `iret`.
+pub const EPC_ADDR: u64 = 0x1000_0018;
+
+/// Address of the cause register.
+pub const CAUSE_ADDR: u64 = 0x1000_0020;
+
+/// Registers of the exception extension ([`crate::isa::Extension::Exception`]),
+/// which are read and written by loads and stores at [`VECTOR_ADDR`],
+/// [`EPC_ADDR`] and [`CAUSE_ADDR`]. They are mapped only if the machine
+/// implements the extension (see [`IoDevices::enable_exceptions`]).
+///
+/// A handler that skips the faulting instruction instead of retrying it adds
+/// the length of the instruction to EPC before `iret`:
+///
+/// ```asm
+///     mrmovq 0x10000018, %rax
+///     iaddq $10, %rax
+///     rmmovq %rax, 0x10000018
+///     iret
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExceptionRegs {
+    /// Base address of the vector table, whose entry `cause` is the address
+    /// of the handler. Exceptions are handled only after it is set.
+    pub vector_base: Option<u64>,
+    pub epc: u64,
+    /// cause of the last exception (see [`crate::isa::exc_cause`])
+    pub cause: u64,
+    /// every exception handled so far
+    pub entries: Vec<HandlerEntry>,
+}
+
+/// Entry of an exception handler.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HandlerEntry {
+    pub cause: u64,
+    pub epc: u64,
+    /// address of the handler
+    pub handler: u64,
+}
+
+/// State of the devices after the stores of a program.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IoDevices {
+    /// bytes written to the console
+    pub output: Vec<u8>,
+    /// the last value written to the exit-code device
+    pub exit_code: Option<u64>,
+    /// the exception registers, or `None` if they are not mapped
+    pub exc: Option<ExceptionRegs>,
+}
+
+impl IoDevices {
+    /// Map the exception registers, which are reset if they are mapped
+    /// already.
+    pub fn enable_exceptions(&mut self) {
+        self.exc = Some(ExceptionRegs::default());
+    }
+
+    /// Handle a store of `value` to `addr`. Return false if no device is
+    /// mapped at the address, i.e. the memory should be written instead.
+    pub fn store(&mut self, addr: u64, value: u64) -> bool {
+        match (addr, &mut self.exc) {
+            (CONSOLE_ADDR, _) => self.output.push(value as u8),
+            (EXIT_ADDR, _) => self.exit_code = Some(value),
+            (VECTOR_ADDR, Some(exc)) => exc.vector_base = Some(value),
+            (EPC_ADDR, Some(exc)) => exc.epc = value,
+            (CAUSE_ADDR, Some(exc)) => exc.cause = value,
+            _ => return false,
+        }
+        true
+    }
+
+    /// Handle a load from `addr`. Return `None` if the memory should be read
+    /// instead.
+    pub fn load(&self, addr: u64) -> Option<u64> {
+        match (addr, &self.exc) {
+            (CONSOLE_ADDR, _) => Some(0),
+            (EXIT_ADDR, _) => Some(self.exit_code.unwrap_or_default()),
+            (VECTOR_ADDR, Some(exc)) => Some(exc.vector_base.unwrap_or_default()),
+            (EPC_ADDR, Some(exc)) => Some(exc.epc),
+            (CAUSE_ADDR, Some(exc)) => Some(exc.cause),
+            _ => None,
+        }
+    }
+
+    /// Whether a device is mapped at `addr`.
+    pub fn is_mapped(&self, addr: u64) -> bool {
+        self.load(addr).is_some()
+    }
+
+    /// Enter the handler of an exception, where `mem` contains the vector
+    /// table. Save `epc` and `cause`, and return the address of the handler,
+    /// or `None` (without changing the registers) if the registers are not
+    /// mapped, the vector table is not set or the entry is out of the memory.
+    pub fn enter_handler(&mut self, mem: &[u8], cause: u64, epc: u64) -> Option<u64> {
+        let exc = self.exc.as_mut()?;
+        let entry = exc.vector_base?.checked_add(cause * 8)?;
+        let bytes = mem.get(entry as usize..entry.checked_add(8)? as usize)?;
+        let handler = crate::utils::get_u64(bytes);
+        exc.epc = epc;
+        exc.cause = cause;
+        exc.entries.push(HandlerEntry {
+            cause,
+            epc,
+            handler,
+        });
+        Some(handler)
+    }
+
+    /// Entries of the exception handlers so far.
+    pub fn handler_entries(&self) -> &[HandlerEntry] {
+        self.exc.as_ref().map_or(&[], |exc| &exc.entries)
+    }
+
+    /// The console output, where invalid UTF-8 is replaced.
+    pub fn output_lossy(&self) -> String {
+        String::from_utf8_lossy(&self.output).into_owned()
+    }
+
+    /// Print the console output and the exit code (if any) of a finished
+    /// program.
+    pub fn print(&self) {
+        if !self.output.is_empty() {
+            let output = self.output_lossy();
+            println!(
+                "console output:\n{}",
+                output.strip_suffix('\n').unwrap_or(&output)
+            );
+        }
+        if let Some(code) = self.exit_code {
+            println!("exit code: {code}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::IoDevices;
+    use crate::{architectures::create_sim, framework::MemData, isa::simulate};
+
+    const SRC: &str = "    irmovq $0x10000000, %rdx
+    irmovq $0x68, %rax
+    rmmovq %rax, (%rdx)
+    irmovq $0x69, %rax
+    rmmovq %rax, (%rdx)
+    irmovq $3, %rax
+    rmmovq %rax, 8(%rdx)
+    mrmovq 8(%rdx), %rbx
+    halt
+";
+
+    #[test]
+    fn test_io_devices() {
+        let a = crate::assemble(SRC, crate::AssembleOption::default()).unwrap();
+        let expected = IoDevices {
+            output: b"hi".to_vec(),
+            exit_code: Some(3),
+            ..Default::default()
+        };
+        let result = simulate(a.obj.init_mem(), false);
+        assert_eq!(result.io, expected);
+        // the exit code is read back, and the memory is not written
+        assert_eq!(result.regs[3], 3);
+        assert_eq!(result.bin, a.obj.init_mem());
+
+        for arch in ["seq_std", "pipe_std"] {
+            let mem = MemData::init(a.obj.init_mem());
+            let mut sim = create_sim(arch.into(), mem.clone(), false);
+            while !sim.is_terminate() {
+                sim.step();
+            }
+            assert_eq!(sim.io_devices(), expected);
+            assert_eq!(sim.registers(), result.regs);
+            assert_eq!(*mem.read(), *a.obj.init_mem());
+        }
+    }
+
+    /// The top of the default memory is plain memory, e.g. the stack of a
+    /// program starting at 0x10000, even with the exception registers.
+    #[test]
+    fn test_memory_below_devices() -> anyhow::Result<()> {
+        let src = "    irmovq $0x10000, %rsp
+    irmovq $0x11, %rax
+    pushq %rax
+    pushq %rax
+    irmovq $0x33, %rax
+    pushq %rax
+    irmovq $0xffe0, %rdx
+    irmovq $0x68, %rax
+    rmmovq %rax, (%rdx)
+    mrmovq (%rdx), %rbx
+    irmovq $0xffc0, %rsi
+    rmmovq %rax, (%rsi)
+    popq %rcx
+    halt
+";
+        let a = crate::assemble(src, crate::AssembleOption::default()).unwrap();
+        let result = simulate(a.obj.init_mem(), false);
+        assert_eq!(result.stat, crate::isa::Stat::Hlt);
+        assert_eq!(result.io, IoDevices::default());
+        assert_eq!((result.regs[1], result.regs[3]), (0x33, 0x68));
+        assert_eq!(crate::utils::get_u64(&result.bin[0xffe0..]), 0x68);
+        assert_eq!(crate::utils::get_u64(&result.bin[0xffe8..]), 0x33);
+        assert_eq!(crate::utils::get_u64(&result.bin[0xffc0..]), 0x68);
+
+        for arch in ["seq_std", "pipe_std", "pipe_exc"] {
+            crate::test::SimTester::new(arch).unwrap().test_isa(src)?;
+        }
+        Ok(())
+    }
+}
@@ -1,43 +1,46 @@
 //! This file provides binary representation of y86 instructions
 
-use std::{collections::BTreeMap, fmt::Display};
+use std::{collections::BTreeMap, fmt::Display, str::FromStr};
 
 use crate::{
-    asm::{self, Reg},
+    asm::{self, AsmError, AsmErrorKind, AsmErrors, AssembleOption, LineError, Reg},
     framework::MEM_SIZE,
 };
 
-/// Maximum size of the assembled binary.
-pub const BIN_SIZE: usize = MEM_SIZE;
+pub(crate) type SymbolMap = BTreeMap<String, u64>;
 
-type SymbolMap = BTreeMap<String, u64>;
-
-impl asm::Imm {
-    fn desymbol(&self, sym: &SymbolMap) -> u64 {
-        match self {
-            asm::Imm::Num(n) => *n as u64,
-            asm::Imm::Label(label) => sym[label],
-        }
+impl asm::Addr<asm::Imm> {
+    fn desymbol(&self, sym: &SymbolMap, here: u64) -> Result<asm::Addr<u64>, LineError> {
+        let dis = match &self.0 {
+            Some(dis) => Some(dis.eval(sym, here)?),
+            None => None,
+        };
+        Ok(asm::Addr(dis, self.1))
     }
 }
+
 impl asm::Inst<asm::Imm> {
-    pub fn desymbol(&self, sym: &SymbolMap) -> asm::Inst<u64> {
+    /// Resolve all expressions. `here` is the address of the instruction.
+    pub(crate) fn desymbol(&self, sym: &SymbolMap, here: u64) -> Result<asm::Inst<u64>, LineError> {
         use asm::Inst::*;
-        match self {
+        Ok(match self {
             HALT => HALT,
             NOP => NOP,
             CMOVX(cond, ra, rb) => CMOVX(*cond, *ra, *rb),
-            IRMOVQ(rb, v) => IRMOVQ(*rb, v.desymbol(sym)),
-            RMMOVQ(ra, addr) => RMMOVQ(*ra, *addr),
-            MRMOVQ(addr, ra) => MRMOVQ(*addr, *ra),
+            IRMOVQ(rb, v) => IRMOVQ(*rb, v.eval(sym, here)?),
+            RMMOVQ(ra, addr) => RMMOVQ(*ra, addr.desymbol(sym, here)?),
+            MRMOVQ(addr, ra) => MRMOVQ(addr.desymbol(sym, here)?, *ra),
             OPQ(op, ra, rb) => OPQ(*op, *ra, *rb),
-            JX(cond, v) => JX(*cond, v.desymbol(sym)),
-            CALL(v) => CALL(v.desymbol(sym)),
+            JX(cond, v) => JX(*cond, v.eval(sym, here)?),
+            CALL(v) => CALL(v.eval(sym, here)?),
             RET => RET,
             PUSHQ(ra) => PUSHQ(*ra),
             POPQ(ra) => POPQ(*ra),
-            IOPQ(op, imm, reg) => IOPQ(*op, imm.desymbol(sym), *reg),
-        }
+            IOPQ(op, imm, reg) => IOPQ(*op, imm.eval(sym, here)?, *reg),
+            JM(imm, reg) => JM(imm.eval(sym, here)?, *reg),
+            TRAP => TRAP,
+            IRET => IRET,
+        })
     }
 }
 
@@ -49,11 +52,32 @@ macro_rules! h2 {
 }
 
 impl LineInfo {
-    pub fn write_object(&self, obj: &mut Object) {
+    /// Number of bytes occupied by the instruction or data of this line.
+    pub fn size(&self) -> usize {
+        if let Some(inst) = &self.inst {
+            inst.len()
+        } else if let Some(data) = &self.data {
+            data.size()
+        } else {
+            0
+        }
+    }
+
+    /// Encode the instruction or data of this line into the object. `sym`
+    /// contains the values of labels and constants visible to this line.
+    pub(crate) fn write_object(&self, obj: &mut Object, sym: &SymbolMap) -> Result<(), LineError> {
         if let Some(addr) = self.addr {
+            let end = addr.saturating_add(self.size() as u64);
+            let mem_size = obj.mem_size() as u64;
+            if end > mem_size {
+                return Err(LineError::new(
+                    AsmErrorKind::AddressOutOfRange(addr.max(mem_size)),
+                    asm::content_range(&self.src),
+                ));
+            }
             let addr = addr as usize;
             if let Some(inst) = &self.inst {
-                match inst.desymbol(&obj.symbols) {
+                match inst.desymbol(sym, addr as u64)? {
                     asm::Inst::HALT => obj.binary[addr] = h2!(inst.icode(), 0),
                     asm::Inst::NOP => obj.binary[addr] = h2!(inst.icode(), 0),
                     asm::Inst::CMOVX(c, ra, rb) => {
@@ -103,39 +127,177 @@ impl LineInfo {
                         obj.binary[addr + 1] = h2!(Reg::RNONE, rb);
                         obj.write_num_data(addr + 2, 8, v);
                     }
+                    asm::Inst::JM(v, rb) => {
+                        obj.binary[addr] = h2!(inst.icode(), 0);
+                        obj.binary[addr + 1] = h2!(Reg::RNONE, rb);
+                        obj.write_num_data(addr + 2, 8, v);
+                    }
+                    asm::Inst::TRAP => {
+                        obj.binary[addr] = h2!(inst.icode(), crate::isa::exc_fn::TRAP)
+                    }
+                    asm::Inst::IRET => {
+                        obj.binary[addr] = h2!(inst.icode(), crate::isa::exc_fn::IRET)
+                    }
                 }
             }
-            if let Some((sz, data)) = &self.data {
-                let data = data.desymbol(&obj.symbols);
-                obj.write_num_data(addr, *sz, data);
+            if let Some(asm::Data::Bytes(bytes)) = &self.data {
+                obj.binary[addr..addr + bytes.len()].copy_from_slice(bytes);
+            }
+            for (offset, sz, data) in self.data.iter().flat_map(asm::Data::fields) {
+                let value = data.eval(sym, addr as u64)?;
+                if !fits_in(value, sz) {
+                    return Err(LineError::new(
+                        AsmErrorKind::ValueOutOfRange { value, size: sz },
+                        asm::content_range(&self.src),
+                    ));
+                }
+                obj.write_num_data(addr + offset as usize, sz, value);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Whether the value can be stored in `size` bytes. Both signed and unsigned
+/// values are accepted, like a relocation of the same width.
+pub(crate) fn fits_in(value: u64, size: u8) -> bool {
+    let bits = size as u32 * 8;
+    bits >= 64 || value >> bits == 0 || (value as i64) >> (bits - 1) == -1
+}
+
+/// Maximum number of bytes in a line of `.yo` file.
+const LISTING_BYTES: usize = 10;
+
+/// Write the address and the bytes of a line in `.yo` file.
+fn write_bytes(f: &mut std::fmt::Formatter<'_>, addr: usize, bytes: &[u8]) -> std::fmt::Result {
+    write!(f, "{:#06x}: ", addr)?;
+    for byte in bytes {
+        write!(f, "{:02x}", byte)?;
+    }
+    write!(f, "{: <1$}", "", 21usize.saturating_sub(bytes.len() * 2))
+}
+
+impl LineInfo {
+    /// The expressions whose values are encoded in this line, with the
+    /// offsets and the sizes of their fields in bytes.
+    pub(crate) fn value_fields(&self) -> Vec<(u64, u8, &asm::Imm)> {
+        use asm::Inst::*;
+        match (&self.inst, &self.data) {
+            (Some(IRMOVQ(_, v) | IOPQ(_, v, _) | JM(v, _)), _) => vec![(2, 8, v)],
+            (Some(RMMOVQ(_, asm::Addr(Some(v), _)) | MRMOVQ(asm::Addr(Some(v), _), _)), _) => {
+                vec![(2, 8, v)]
+            }
+            (Some(JX(_, v) | CALL(v)), _) => vec![(1, 8, v)],
+            (None, Some(data)) => data.fields(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// All expressions of this line, including the one of the constant.
+    pub(crate) fn imms_mut(&mut self) -> Vec<&mut asm::Imm> {
+        use asm::Inst::*;
+        let mut imms = Vec::new();
+        match &mut self.inst {
+            Some(IRMOVQ(_, v) | IOPQ(_, v, _) | JM(v, _) | JX(_, v) | CALL(v)) => imms.push(v),
+            Some(RMMOVQ(_, asm::Addr(Some(v), _)) | MRMOVQ(asm::Addr(Some(v), _), _)) => {
+                imms.push(v)
+            }
+            _ => {}
+        }
+        match &mut self.data {
+            Some(asm::Data::Num(_, values)) => imms.extend(values),
+            Some(asm::Data::Fill { value, .. }) => imms.push(value),
+            _ => {}
+        }
+        imms.extend(self.constant.as_mut().map(|c| &mut c.expr));
+        imms
+    }
+
+    /// Write this line in the format of `.yo` file, where `binary` contains
+    /// the contents at the address of the line.
+    pub(crate) fn fmt_listing(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+        binary: &[u8],
+    ) -> std::fmt::Result {
+        if let Some(value) = self.constant.as_ref().and_then(|c| c.value) {
+            // constants have no address, show their values instead
+            write!(f, "{: <8}{: <21}", "", format!("= {value:#x}"))?
+        } else if let Some(addr) = self.addr {
+            let addr = addr as usize;
+            let bytes = &binary[addr..addr + self.size()];
+            // long data is wrapped to continuation lines without source, like
+            // CS:APP yas
+            let mut chunks = bytes.chunks(LISTING_BYTES).enumerate();
+            let (_, first) = chunks.next().unwrap_or((0, &[]));
+            write_bytes(f, addr, first)?;
+            writeln!(f, "| {}", self.src)?;
+            for (i, chunk) in chunks {
+                write_bytes(f, addr + i * LISTING_BYTES, chunk)?;
+                writeln!(f, "| ")?;
             }
+            return Ok(());
+        } else {
+            write!(f, "{: <29}", "")?
         }
+        writeln!(f, "| {}", self.src)
     }
 }
 
 /// A source info is one line of the .yo file.
 #[derive(Debug, Clone)]
 pub struct LineInfo {
+    /// index of the source file in [`SourceInfo::files`]
+    pub file: usize,
+    /// line number in the source file (start from 1). Lines generated by
+    /// macros take the line number of the invocation.
+    pub line: usize,
     pub addr: Option<u64>,
     pub inst: Option<asm::Inst<asm::Imm>>,
     pub label: Option<String>,
-    // width and data
-    pub data: Option<(u8, asm::Imm)>,
+    pub data: Option<asm::Data>,
+    /// constant defined by `.equ` or `.set`
+    pub constant: Option<asm::Constant>,
     pub src: String,
 }
 
 /// object file
 ///
-/// while y86 language support 64-bit address, we only consider address <
-/// 0x10000.
+/// while y86 language support 64-bit address, we only consider addresses in
+/// the memory, whose size is [`MEM_SIZE`] by default.
 pub struct Object {
-    binary: [u8; BIN_SIZE],
+    binary: Box<[u8]>,
     /// basically labels
     pub symbols: SymbolMap,
+    /// constants defined by `.equ` or `.set`, which hold their last defined
+    /// values
+    pub constants: SymbolMap,
 }
 
 impl Object {
-    fn write_num_data(&mut self, addr: usize, sz: u8, data: u64) {
+    /// Create an empty object for a memory of the given size.
+    pub fn new(mem_size: usize) -> Self {
+        Self {
+            binary: vec![0; mem_size].into_boxed_slice(),
+            symbols: Default::default(),
+            constants: Default::default(),
+        }
+    }
+
+    /// Size of the memory this object is placed in.
+    pub fn mem_size(&self) -> usize {
+        self.binary.len()
+    }
+
+    pub(crate) fn binary(&self) -> &[u8] {
+        &self.binary
+    }
+
+    pub(crate) fn binary_mut(&mut self) -> &mut [u8] {
+        &mut self.binary
+    }
+
+    pub(crate) fn write_num_data(&mut self, addr: usize, sz: u8, data: u64) {
         for i in 0..sz as usize {
             let byte = (data >> (i * 8) & 0xff) as u8;
             self.binary[addr + i] = byte // little endian
@@ -143,34 +305,54 @@ impl Object {
     }
 
     /// Initialize memory from assembled binary.
-    pub fn init_mem(&self) -> [u8; MEM_SIZE] {
-        let mut mem = [0; MEM_SIZE];
-        mem[..self.binary.len()].copy_from_slice(&self.binary);
-        mem
+    pub fn init_mem(&self) -> Box<[u8]> {
+        self.binary.clone()
     }
 }
 
+/// Source lines of the assembled program, which may come from multiple files.
 #[derive(Debug, Default)]
-pub struct SourceInfo(Vec<LineInfo>);
-
-impl From<Vec<LineInfo>> for SourceInfo {
-    fn from(v: Vec<LineInfo>) -> Self {
-        Self(v)
-    }
+pub struct SourceInfo {
+    lines: Vec<LineInfo>,
+    /// names of the source files, the first one is the main file
+    files: Vec<String>,
 }
 
 impl SourceInfo {
-    /// Get the line info by line number (start from 1)
-    pub fn get_line(&self, line: i64) -> Option<&LineInfo> {
+    pub(crate) fn new(lines: Vec<LineInfo>, files: Vec<String>) -> Self {
+        Self { lines, files }
+    }
+
+    /// All lines in the order of the listing.
+    pub fn lines(&self) -> &[LineInfo] {
+        &self.lines
+    }
+
+    /// Names of the source files. The file id of [`LineInfo::file`] is the
+    /// index in this list, and the main file has id 0.
+    pub fn files(&self) -> &[String] {
+        &self.files
+    }
+
+    /// Get the line info by file id and line number (start from 1). If the
+    /// line is expanded to multiple lines, the first one with an address is
+    /// returned.
+    pub fn get_line(&self, file: usize, line: i64) -> Option<&LineInfo> {
         assert!(line > 0);
-        self.0.get(line as usize - 1)
+        let mut lines = self
+            .lines
+            .iter()
+            .filter(|x| x.file == file && x.line as i64 == line);
+        let first = lines.clone().next();
+        lines.find(|x| x.addr.is_some()).or(first)
     }
 
-    pub fn get_line_number_by_addr(&self, addr: u64) -> Option<i64> {
-        self.0
+    /// Get the file id and line number of the first line at the address.
+    pub fn get_line_number_by_addr(&self, addr: u64) -> Option<(usize, i64)> {
+        self.lines
             .iter()
-            .position(|x| x.addr == Some(addr))
-            .map(|x| x as i64 + 1)
+            .find(|x| x.addr == Some(addr))
+            .map(|x| (x.file, x.line as i64))
     }
 }
 
@@ -182,42 +364,227 @@ pub struct ObjectExt {
     pub obj: Object,
     /// annotate each line with its address
     pub source: SourceInfo,
+    /// warnings reported by the assembler
+    pub warnings: Vec<asm::AsmError>,
+}
+
+impl ObjectExt {
+    /// Parse a `.yo` file, which is the output of `yas` (either this one or
+    /// the one of CS:APP).
+    ///
+    /// The binary, the source lines, the labels and the constants are
+    /// recovered, while instructions are kept as raw bytes. Lines are numbered
+    /// as in the `.yo` file. The output of `Display` can be parsed back to an
+    /// object with the same display.
+    pub fn parse_yo(src: &str) -> Result<Self, AsmErrors> {
+        Self::parse_yo_with_name(src, "<source>")
+    }
+
+    /// Same as [`ObjectExt::parse_yo`], with the file name used in the source
+    /// info and the diagnostics.
+    pub fn parse_yo_with_name(src: &str, file_name: &str) -> Result<Self, AsmErrors> {
+        Self::parse_yo_with_option(src, AssembleOption::default().set_file_name(file_name))
+    }
+
+    /// Same as [`ObjectExt::parse_yo`], with the file name and the memory size
+    /// of the option. Other options are ignored.
+    pub fn parse_yo_with_option(src: &str, option: AssembleOption) -> Result<Self, AsmErrors> {
+        let file_name = option.file_name().unwrap_or("<source>");
+        let mut obj = Object::new(option.mem_size());
+        let mut lines = Vec::new();
+        let mut errors = Vec::new();
+        for (index, text) in src.lines().enumerate() {
+            let mut info = LineInfo {
+                file: 0,
+                line: index + 1,
+                addr: None,
+                inst: None,
+                label: None,
+                data: None,
+                constant: None,
+                src: text.to_string(),
+            };
+            if let Err(err) = parse_yo_line(text, &mut info, &mut obj) {
+                errors.push(AsmError::new(file_name, index + 1, text, err));
+            }
+            push_yo_line(&mut lines, info);
+        }
+        if !errors.is_empty() {
+            return Err(AsmErrors::new(file_name, errors));
+        }
+        Ok(Self {
+            obj,
+            source: SourceInfo::new(lines, vec![file_name.to_string()]),
+            warnings: Vec::new(),
+        })
+    }
+}
+
+impl FromStr for ObjectExt {
+    type Err = AsmErrors;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse_yo(s)
+    }
+}
+
+/// Append a parsed line of `.yo` file to the listing. A line with bytes but
+/// without source continues the data of the previous line.
+pub(crate) fn push_yo_line(lines: &mut Vec<LineInfo>, info: LineInfo) {
+    if let (Some(asm::Data::Bytes(bytes)), Some(prev)) = (&info.data, lines.last_mut()) {
+        let end = prev.addr.map(|addr| addr + prev.size() as u64);
+        if let (true, true, Some(asm::Data::Bytes(prev_bytes))) = (
+            info.src.trim().is_empty(),
+            end.is_some() && end == info.addr,
+            &mut prev.data,
+        ) {
+            prev_bytes.extend_from_slice(bytes);
+            return;
+        }
+    }
+    lines.push(info);
+}
+
+/// Parse a line of `.yo` file in the form of `addr: bytes | source`, and
+/// write the bytes to the object.
+pub(crate) fn parse_yo_line(
+    text: &str,
+    info: &mut LineInfo,
+    obj: &mut Object,
+) -> Result<(), LineError> {
+    let Some(sep) = text.find('|') else {
+        return Ok(());
+    };
+    let src = &text[sep + 1..];
+    info.src = src.strip_prefix(' ').unwrap_or(src).to_string();
+    let head = &text[..sep];
+    let head_start = head.len() - head.trim_start().len();
+    let invalid = |s: &str, offset: usize| {
+        LineError::new(
+            AsmErrorKind::InvalidNumber(s.to_string()),
+            offset..offset + s.len(),
+        )
+    };
+
+    if let Some(value) = head.trim().strip_prefix('=') {
+        // constant defined by `.equ` or `.set`
+        let value_str = value.trim();
+        let offset = text.find(value_str).unwrap_or_default();
+        let value = crate::utils::parse_literal(value_str).ok_or(invalid(value_str, offset))?;
+        let code = info.src.trim_start();
+        let name = code
+            .get(4..)
+            .and_then(|rest| rest.split(',').next())
+            .map(str::trim)
+            .unwrap_or_default();
+        info.constant = Some(asm::Constant {
+            name: name.to_string(),
+            expr: asm::Imm::Num(value as i64),
+            redefinable: code.starts_with(".set"),
+            value: Some(value),
+        });
+        obj.constants.insert(name.to_string(), value);
+        return Ok(());
+    }
+
+    if let Some(colon) = head.find(':') {
+        let addr_str = head[..colon].trim();
+        let addr = crate::utils::parse_literal(addr_str).ok_or(invalid(addr_str, head_start))?;
+        let hex = head[colon + 1..].trim();
+        let hex_start =
+            colon + 1 + (head[colon + 1..].len() - head[colon + 1..].trim_start().len());
+        if !hex.len().is_multiple_of(2) || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(invalid(hex, hex_start));
+        }
+        let bytes = crate::utils::decode_hex(hex).map_err(|_| invalid(hex, hex_start))?;
+        if addr.saturating_add(bytes.len() as u64) > obj.mem_size() as u64 {
+            return Err(LineError::new(
+                AsmErrorKind::AddressOutOfRange(addr),
+                head_start..head_start + addr_str.len(),
+            ));
+        }
+        info.addr = Some(addr);
+        if !bytes.is_empty() {
+            let addr = addr as usize;
+            obj.binary[addr..addr + bytes.len()].copy_from_slice(&bytes);
+            info.data = Some(asm::Data::Bytes(bytes));
+        }
+    }
+
+    if let Some((_, label)) = asm::leading_label(&info.src) {
+        info.label = Some(label.to_string());
+        if let Some(addr) = info.addr {
+            obj.symbols.entry(label.to_string()).or_insert(addr);
+        }
+    }
+    Ok(())
 }
 
 impl Default for Object {
     fn default() -> Self {
-        Self {
-            binary: [0; BIN_SIZE],
-            symbols: Default::default(),
-        }
+        Self::new(MEM_SIZE)
     }
 }
 
 impl Display for ObjectExt {
     /// display yo format
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        for src in &self.source.0 {
-            if let Some(addr) = src.addr {
-                let addr = addr as usize;
-                write!(f, "{:#06x}: ", addr)?;
-                if let Some(inst) = &src.inst {
-                    for i in 0..inst.len() {
-                        write!(f, "{:02x}", self.obj.binary[i + addr])?;
-                    }
-                    write!(f, "{: <1$}", "", 21 - inst.len() * 2)?
-                } else if let Some((sz, _)) = &src.data {
-                    for i in 0..*sz as usize {
-                        write!(f, "{:02x}", self.obj.binary[i + addr])?;
-                    }
-                    write!(f, "{: <1$}", "", 21 - *sz as usize * 2)?
-                } else {
-                    write!(f, "{: <21}", "")?
-                }
-            } else {
-                write!(f, "{: <29}", "")?
-            }
-            writeln!(f, "| {}", src.src)?
+        for src in &self.source.lines {
+            src.fmt_listing(f, &self.obj.binary)?
         }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::ObjectExt;
+    use crate::{asm::tests::RSUM_YS, assemble, AsmErrorKind, AssembleOption};
+
+    #[test]
+    fn test_parse_yo() {
+        let src = format!("    .equ SIZE, 0x10\n{RSUM_YS}    .quad SIZE * 2, 1, 2\n");
+        let a = assemble(&src, AssembleOption::default()).unwrap();
+        let yo = a.to_string();
+        let b: ObjectExt = yo.parse().unwrap();
+        assert_eq!(b.to_string(), yo);
+        // long data is wrapped to a continuation line
+        assert!(yo.contains("0x020a: 00000000000002000000 | \n0x0214: 00000000             | \n"));
+        assert_eq!(b.source.lines().len(), a.source.lines().len());
+        assert_eq!(b.obj.init_mem(), a.obj.init_mem());
+        assert_eq!(b.obj.symbols, a.obj.symbols);
+        assert_eq!(b.obj.constants, a.obj.constants);
+        assert_eq!(
+            b.source.get_line_number_by_addr(0x13),
+            a.source.get_line_number_by_addr(0x13)
+        );
+
+        // output of CS:APP yas
+        let yo = "                            | # comment
+0x000:                      |     .pos 0
+0x000: 30f40002000000000000 |     irmovq stack, %rsp
+0x00a: 804800000000000000   |     call main
+0x013: 00                   |     halt
+0x018:                      |     .align 8
+0x018: 0d000d000d000000     | ele1: .quad 0x00d000d000d
+0x048:                      | main:
+0x048: 90                   |     ret
+0x200:                      | stack:
+";
+        let b = ObjectExt::parse_yo(yo).unwrap();
+        let mem = b.obj.init_mem();
+        assert_eq!(mem[..3], [0x30, 0xf4, 0x00]);
+        assert_eq!(mem[0x18..0x1a], [0x0d, 0x00]);
+        assert_eq!(b.obj.symbols["ele1"], 0x18);
+        assert_eq!(b.obj.symbols["main"], 0x48);
+        assert_eq!(b.obj.symbols["stack"], 0x200);
+        assert_eq!(b.source.get_line_number_by_addr(0x13), Some((0, 5)));
+
+        let Err(err) = ObjectExt::parse_yo("0x000: 3 | halt\n0xfffff: 00 | halt") else {
+            panic!("expect errors");
+        };
+        assert_eq!(err.errors.len(), 2);
+        assert_eq!(err.errors[0].kind, AsmErrorKind::InvalidNumber("3".into()));
+        assert_eq!(err.errors[1].kind, AsmErrorKind::AddressOutOfRange(0xfffff));
+    }
+}
@@ -0,0 +1,351 @@
+//! Execution profile of a program, written by `yis --profile` and
+//! `ysim --profile`.
+//!
+//! The ISA simulator counts the executed instructions of each address, and
+//! the pipeline simulator counts the cycles of each address. A cycle belongs
+//! to the instruction being fetched (the program counter of the
+//! architecture), and it is a stall cycle if any pipeline register is
+//! stalled, or otherwise a bubble cycle if any pipeline register is bubbled.
+//! The last such stage in the pipeline is considered as the cause.
+
+use std::{cell::RefCell, collections::BTreeMap, fmt::Display, rc::Rc};
+
+use crate::{
+    framework::CpuSim,
+    isa::{IsaMachine, IsaObserver, RetiredInst},
+    ObjectExt, SourceInfo,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProfileKind {
+    /// executed instructions of the ISA
+    Insts,
+    /// cycles of a pipeline
+    Cycles,
+}
+
+/// Instructions or cycles attributed to an address (or a label).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Counts {
+    /// all instructions or cycles
+    pub total: u64,
+    /// stall cycles by the stage causing them
+    pub stalls: BTreeMap<&'static str, u64>,
+    /// bubble cycles by the stage causing them
+    pub bubbles: BTreeMap<&'static str, u64>,
+}
+
+impl Counts {
+    pub fn stall(&self) -> u64 {
+        self.stalls.values().sum()
+    }
+
+    pub fn bubble(&self) -> u64 {
+        self.bubbles.values().sum()
+    }
+
+    /// Cycles which are neither stalled nor bubbled.
+    pub fn useful(&self) -> u64 {
+        self.total - self.stall() - self.bubble()
+    }
+
+    fn add(&mut self, other: &Counts) {
+        self.total += other.total;
+        for (stage, n) in &other.stalls {
+            *self.stalls.entry(stage).or_default() += n;
+        }
+        for (stage, n) in &other.bubbles {
+            *self.bubbles.entry(stage).or_default() += n;
+        }
+    }
+
+    /// e.g. `stall D 3, bubble E 2`
+    fn causes(&self) -> String {
+        let stalls = self.stalls.iter().map(|(s, n)| ("stall", s, n));
+        let bubbles = self.bubbles.iter().map(|(s, n)| ("bubble", s, n));
+        let causes: Vec<String> = stalls
+            .chain(bubbles)
+            .map(|(kind, stage, n)| format!("{kind} {} {n}", stage.to_uppercase()))
+            .collect();
+        causes.join(", ")
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Profile {
+    pub kind: ProfileKind,
+    /// counts of each address
+    pub addrs: BTreeMap<u64, Counts>,
+}
+
+impl Profile {
+    pub fn new(kind: ProfileKind) -> Self {
+        Self {
+            kind,
+            addrs: BTreeMap::new(),
+        }
+    }
+
+    /// Count an executed instruction of the ISA.
+    pub fn record_inst(&mut self, pc: u64) {
+        self.addrs.entry(pc).or_default().total += 1;
+    }
+
+    /// Count the current cycle of a pipeline, after the signals are
+    /// propagated.
+    pub fn record_cycle(&mut self, sim: &dyn CpuSim) {
+        let counts = self.addrs.entry(sim.program_counter()).or_default();
+        counts.total += 1;
+        // the last cycle only finishes the program
+        if sim.is_terminate() {
+            return;
+        }
+        let controls = sim.stage_controls();
+        if let Some(c) = controls.iter().rev().find(|c| c.stall) {
+            *counts.stalls.entry(c.name).or_default() += 1;
+        } else if let Some(c) = controls.iter().rev().find(|c| c.bubble) {
+            *counts.bubbles.entry(c.name).or_default() += 1;
+        }
+    }
+
+    /// Counts of each source line (file id and line number), attributed by
+    /// [`SourceInfo::get_line_number_by_addr`]. Addresses without source
+    /// lines are dropped.
+    pub fn lines(&self, source: &SourceInfo) -> BTreeMap<(usize, i64), Counts> {
+        let mut lines: BTreeMap<_, Counts> = BTreeMap::new();
+        for (&addr, counts) in &self.addrs {
+            if let Some(line) = source.get_line_number_by_addr(addr) {
+                lines.entry(line).or_default().add(counts);
+            }
+        }
+        lines
+    }
+
+    pub fn total(&self) -> Counts {
+        let mut total = Counts::default();
+        self.addrs.values().for_each(|c| total.add(c));
+        total
+    }
+
+    fn margin(&self, counts: Option<&Counts>) -> String {
+        match (self.kind, counts) {
+            (ProfileKind::Insts, Some(c)) => format!("{:>8}", c.total),
+            (ProfileKind::Insts, None) => format!("{:8}", ""),
+            (ProfileKind::Cycles, Some(c)) => {
+                format!("{:>8} {:>6} {:>6}", c.total, c.stall(), c.bubble())
+            }
+            (ProfileKind::Cycles, None) => format!("{:22}", ""),
+        }
+    }
+}
+
+impl IsaObserver for Rc<RefCell<Profile>> {
+    fn retire(&mut self, inst: &RetiredInst, _: &IsaMachine) {
+        self.borrow_mut().record_inst(inst.pc);
+    }
+}
+
+/// `.yo` listing of an object with the counts of each line in the left
+/// margin.
+pub struct AnnotatedListing<'a> {
+    obj: &'a ObjectExt,
+    profile: &'a Profile,
+}
+
+/// Counts of a label, which covers the addresses up to the next label.
+#[derive(Debug, Clone)]
+pub struct LabelSummary {
+    pub name: String,
+    pub addr: u64,
+    pub counts: Counts,
+}
+
+/// Counts of all labels in the order of their addresses. Addresses not
+/// covered by any label are summarized with an empty name.
+pub struct ProfileSummary {
+    pub kind: ProfileKind,
+    pub labels: Vec<LabelSummary>,
+    pub total: Counts,
+}
+
+impl ObjectExt {
+    /// Annotate the listing of this object by the profile.
+    pub fn annotate<'a>(&'a self, profile: &'a Profile) -> AnnotatedListing<'a> {
+        AnnotatedListing { obj: self, profile }
+    }
+
+    /// Summarize the profile by the labels of this object.
+    pub fn profile_summary(&self, profile: &Profile) -> ProfileSummary {
+        let entries: Vec<_> = self
+            .symbol_map()
+            .entries
+            .into_iter()
+            .filter(|e| e.size > 0)
+            .collect();
+        let mut labels: Vec<LabelSummary> = Vec::new();
+        for (&addr, counts) in &profile.addrs {
+            let entry = entries
+                .iter()
+                .find(|e| (e.addr..e.addr + e.size).contains(&addr));
+            let (name, label_addr) = match entry {
+                Some(e) => (e.name.as_str(), e.addr),
+                None => ("", addr),
+            };
+            match labels.iter_mut().find(|l| l.name == name) {
+                Some(label) => label.counts.add(counts),
+                None => labels.push(LabelSummary {
+                    name: name.to_string(),
+                    addr: label_addr,
+                    counts: counts.clone(),
+                }),
+            }
+        }
+        labels.sort_by_key(|l| l.addr);
+        ProfileSummary {
+            kind: profile.kind,
+            labels,
+            total: profile.total(),
+        }
+    }
+}
+
+impl Display for AnnotatedListing<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        struct Line<'a>(&'a crate::object::LineInfo, &'a ObjectExt);
+        impl Display for Line<'_> {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                self.0.fmt_listing(f, self.1.obj.binary())
+            }
+        }
+
+        let header = match self.profile.kind {
+            ProfileKind::Insts => format!("{:>8}", "insts"),
+            ProfileKind::Cycles => format!("{:>8} {:>6} {:>6}", "cycles", "stall", "bubble"),
+        };
+        writeln!(f, "{header} |")?;
+        let mut lines = self.profile.lines(&self.obj.source);
+        // counts of a line without contents (e.g. a label), which are moved
+        // to the following line at the same address
+        let mut carry: Option<Counts> = None;
+        for line in self.obj.source.lines() {
+            let key = (line.file, line.line as i64);
+            if let Some(counts) = line.addr.and_then(|_| lines.remove(&key)) {
+                carry.get_or_insert_default().add(&counts);
+            }
+            let counts = match line.size() {
+                0 => None,
+                _ => carry.take(),
+            };
+            let text = Line(line, self.obj).to_string();
+            for (i, text) in text.lines().enumerate() {
+                let margin = match i {
+                    0 => self.profile.margin(counts.as_ref()),
+                    _ => self.profile.margin(None),
+                };
+                writeln!(f, "{margin} | {text}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Display for ProfileSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let width = self.labels.iter().map(|l| l.name.len()).max();
+        let width = width.unwrap_or_default().max("(no label)".len());
+        let percent = |n: u64| n as f64 * 100.0 / self.total.total.max(1) as f64;
+        let rows = self.labels.iter().map(|l| {
+            let name = if l.name.is_empty() {
+                "(no label)"
+            } else {
+                &l.name
+            };
+            (name, &l.counts)
+        });
+        match self.kind {
+            ProfileKind::Insts => {
+                writeln!(f, "{:width$} {:>8} {:>7}", "label", "insts", "%")?;
+                for (name, c) in rows.chain([("total", &self.total)]) {
+                    let p = percent(c.total);
+                    writeln!(f, "{name:width$} {:>8} {p:>6.1}%", c.total)?;
+                }
+            }
+            ProfileKind::Cycles => {
+                writeln!(
+                    f,
+                    "{:width$} {:>8} {:>7} {:>8} {:>6} {:>6}  causes",
+                    "label", "cycles", "%", "useful", "stall", "bubble"
+                )?;
+                for (name, c) in rows.chain([("total", &self.total)]) {
+                    let p = percent(c.total);
+                    let (useful, stall, bubble) = (c.useful(), c.stall(), c.bubble());
+                    let row = format!(
+                        "{name:width$} {:>8} {p:>6.1}% {useful:>8} {stall:>6} {bubble:>6}  {}",
+                        c.total,
+                        c.causes()
+                    );
+                    writeln!(f, "{}", row.trim_end())?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use super::{Profile, ProfileKind};
+    use crate::{
+        assemble,
+        framework::MemData,
+        isa::{IsaMachine, StepOutcome},
+        AssembleOption,
+    };
+
+    const SRC: &str = "    irmovq $3, %rcx
+    irmovq $1, %rbx
+loop:
+    mrmovq 0(%rdx), %rax
+    addq %rax, %rsi
+    subq %rbx, %rcx
+    jne loop
+    halt
+";
+
+    #[test]
+    fn test_profile() {
+        let a = assemble(SRC, AssembleOption::default()).unwrap();
+
+        let profile = Rc::new(RefCell::new(Profile::new(ProfileKind::Insts)));
+        let mut machine = IsaMachine::new(a.obj.init_mem());
+        machine.add_observer(Box::new(profile.clone()));
+        while let StepOutcome::Retired = machine.step() {}
+        let profile = profile.borrow();
+        assert_eq!(profile.addrs[&0x14].total, 3);
+        let listing = a.annotate(&profile).to_string();
+        assert!(listing.contains("       3 | 0x0014: 50020000000000000000 |     mrmovq"));
+        let summary = a.profile_summary(&profile);
+        assert_eq!(summary.labels[0].name, "");
+        assert_eq!(summary.labels[1].name, "loop");
+        assert_eq!(summary.labels[1].counts.total, 13);
+        assert_eq!(summary.total.total, 15);
+
+        let mem = MemData::init(a.obj.init_mem());
+        let mut sim = crate::architectures::create_sim("pipe_std".into(), mem, false);
+        let mut profile = Profile::new(ProfileKind::Cycles);
+        while !sim.is_terminate() {
+            sim.step();
+            profile.record_cycle(sim.as_ref());
+        }
+        let total = profile.total();
+        assert_eq!(total.total, sim.cycle_count());
+        // load/use hazard of each iteration
+        assert_eq!(total.stalls["d"], 3);
+        // the mispredicted branch at the end of the loop
+        assert_eq!(total.bubbles["e"], 1);
+        let summary = a.profile_summary(&profile).to_string();
+        assert!(summary.contains("stall D 3, bubble E 1"));
+    }
+}
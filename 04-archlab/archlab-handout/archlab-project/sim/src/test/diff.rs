@@ -6,19 +6,56 @@ use anyhow::Context;
 use super::SimTester;
 use crate::{
     framework::{CpuSim, MemData},
-    isa::StandardResult,
+    isa::{IsaMachine, StandardResult, StepOutcome},
 };
 
 pub struct TestIsaResult {
     pub answer: StandardResult,
     pub sim: Box<dyn CpuSim>,
     pub mem: MemData,
+    skipped_regs: Vec<usize>,
 }
 
 impl TestIsaResult {
     fn check(&self) -> anyhow::Result<()> {
+        if let Some(stat) = self.sim.status() {
+            if stat != self.answer.stat {
+                anyhow::bail!(
+                    "status mismatch: gt = {:?}, sim = {:?}",
+                    self.answer.stat,
+                    stat
+                );
+            }
+        }
+
+        let sim_io = self.sim.io_devices();
+        // the exception handlers should be entered at the same points
+        let (gt_entries, sim_entries) =
+            (self.answer.io.handler_entries(), sim_io.handler_entries());
+        for i in 0..gt_entries.len().max(sim_entries.len()) {
+            if gt_entries.get(i) != sim_entries.get(i) {
+                anyhow::bail!(
+                    "handler entry #{} mismatch: gt = {:?}, sim = {:?}",
+                    i + 1,
+                    gt_entries.get(i),
+                    sim_entries.get(i)
+                );
+            }
+        }
+        if sim_io != self.answer.io {
+            anyhow::bail!(
+                "I/O mismatch: gt = {:?}, sim = {:?}",
+                self.answer.io,
+                sim_io
+            );
+        }
+
         let gt_regs = self.answer.regs;
-        let sim_regs = self.sim.registers();
+        let mut sim_regs = self.sim.registers();
+        // registers written back by the faulting instruction are not compared
+        for reg in &self.skipped_regs {
+            sim_regs[*reg] = gt_regs[*reg];
+        }
         if gt_regs != sim_regs {
             anyhow::bail!(
                 "registers mismatch: gt = {:?}, sim = {:?}",
@@ -27,10 +64,10 @@ impl TestIsaResult {
             );
         }
 
-        let gt_mem = self.answer.bin;
+        let gt_mem = &*self.answer.bin;
         let sim_mem_read = self.mem.read();
-        if gt_mem.as_ref() != sim_mem_read.as_ref() {
-            crate::utils::mem_diff(&gt_mem, &sim_mem_read);
+        if gt_mem != &*sim_mem_read {
+            crate::utils::mem_diff(gt_mem, &sim_mem_read);
             anyhow::bail!("memory mismatch");
         }
         drop(sim_mem_read);
@@ -41,13 +78,21 @@ impl TestIsaResult {
 impl SimTester {
     pub fn test_isa(&self, src: &str) -> anyhow::Result<TestIsaResult> {
         let a = super::make_obj(src).context("assemble")?;
-        let answer = crate::isa::simulate(a.obj.init_mem(), false)?;
         let (sim, sim_mem) = SimTester::simulate_arch(self.arch.clone(), src)?;
+        let mut machine = IsaMachine::new(a.obj.init_mem());
+        // the ISA implements the exception extension if the architecture does
+        if sim.io_devices().exc.is_some() {
+            machine.enable_exceptions();
+        }
+        while let StepOutcome::Retired = machine.step() {}
+        let skipped_regs = super::lockstep::fault_writeback(&machine);
+        let answer = machine.into_result();
 
         let r = TestIsaResult {
             answer,
             sim,
             mem: sim_mem,
+            skipped_regs,
         };
 
         r.check()?;
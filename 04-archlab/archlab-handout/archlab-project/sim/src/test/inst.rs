@@ -95,6 +95,114 @@ impl SimTester {
         Ok(())
     }
 
+    /// Test the operations of the arithmetic extension, and their immediate
+    /// forms if `ext_iopq` is true. The condition codes are checked by the
+    /// following conditional moves.
+    pub fn test_arith(&self, ext_iopq: bool) -> anyhow::Result<()> {
+        let insts = ["mulq", "divq", "modq", "sarq", "shlq", "shrq"];
+        // shifts use the low 6 bits, 3 * 0x4000000000000000 overflows, and
+        // dividing by zero is an invalid instruction
+        let vals_a: [i64; 4] = [3, -1, 0x41, 0];
+        let vals_b: [i64; 3] = [-0x100, 0x4000000000000000, 0x123];
+        let source = r#"
+            irmovq ${vala}, %rdx
+            irmovq ${valb}, %rbx
+            {inst}
+            cmovl %rdx, %rcx
+            cmove %rdx, %rsi
+            halt
+        "#;
+
+        for inst in insts {
+            for vala in vals_a {
+                for valb in vals_b {
+                    let mut forms = vec![format!("{inst} %rdx, %rbx")];
+                    if ext_iopq {
+                        forms.push(format!("i{inst} ${vala}, %rbx"));
+                    }
+                    for inst in forms {
+                        let src =
+                            format(source, interp_args!(vala = vala, valb = valb, inst = inst))?;
+                        self.test_isa_write_on_fail(&src)?;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Test the exception extension: `trap`, `iret` and the handled faults,
+    /// whose younger instructions should have no effects. The handler of a
+    /// fault skips `skip` bytes from the faulting instruction, or halts.
+    pub fn test_exception(&self) -> anyhow::Result<()> {
+        let source = r#"
+            irmovq vectors, %rax
+            rmmovq %rax, 0x10000010
+            irmovq $0x400, %rsp
+            {body}
+            halt
+        trap_h:
+            irmovq $1, %r9
+            addq %r9, %r8
+            iret
+        skip_h:
+            mrmovq 0x10000018, %r10
+            irmovq ${skip}, %r9
+            addq %r9, %r10
+            rmmovq %r10, 0x10000018
+            mrmovq 0x10000020, %r11
+            iret
+        stop_h:
+            mrmovq 0x10000018, %r12
+            halt
+            .align 8
+        vectors:
+            .quad trap_h
+            .quad {handler}
+            .quad {handler}
+        "#;
+        let cases = [
+            ("trap\n syscall\n irmovq $3, %rax", 0, "skip_h"),
+            (
+                "mrmovq 0x10000, %rax\n rmmovq %rsp, 0x100\n addq %rsp, %rsp",
+                10,
+                "skip_h",
+            ),
+            (
+                "irmovq $0x10000, %rsp\n pushq %rsp\n irmovq $0x400, %rsp",
+                2,
+                "skip_h",
+            ),
+            ("call 0x10000\n irmovq $1, %rax", 0, "stop_h"),
+            (".byte 0xf0\n rmmovq %rsp, 0x100", 1, "skip_h"),
+            (".byte 0xe2\n trap", 1, "skip_h"),
+            (
+                "xorq %rbx, %rbx\n divq %rbx, %rax\n cmove %rsp, %rcx",
+                2,
+                "skip_h",
+            ),
+            ("irmovq $1, %rbx\n modq %rbx, %rbx\n trap", 2, "skip_h"),
+            (
+                "irmovq back, %rax\n rmmovq %rax, 0x10000018\n iret\n halt\nback: trap",
+                0,
+                "skip_h",
+            ),
+        ];
+        for (body, skip, handler) in cases {
+            let src = format(
+                source,
+                interp_args!(body = body, skip = skip, handler = handler),
+            )?;
+            self.test_isa_write_on_fail(&src)?;
+        }
+
+        // trap without a vector table
+        self.test_isa_write_on_fail("    irmovq $1, %rax\n    trap\n    halt\n")?;
+
+        Ok(())
+    }
+
     pub fn test_cmov(&self) -> anyhow::Result<()> {
         let insts = [
             "rrmovq", "cmovle", "cmovl", "cmove", "cmovne", "cmovge", "cmovg",
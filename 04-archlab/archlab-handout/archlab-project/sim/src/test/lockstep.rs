@@ -0,0 +1,380 @@
+//! Run an architecture in lockstep with the ISA reference machine, and find
+//! the first instruction whose effect differs.
+//!
+//! The registers and the memory are synchronized separately, since an
+//! architecture may update them in different cycles for the same instruction
+//! (e.g. a pipeline writes the memory in the M stage and the registers in the
+//! W stage). Each of them has a cursor (an [`IsaMachine`]) that is advanced
+//! only when the state of the architecture no longer matches it.
+
+use std::fmt::Display;
+
+use crate::{
+    framework::{CpuSim, MemData},
+    isa::{inst_code, reg_code, IsaMachine, RegFile, Stat, StepOutcome},
+    utils::get_u64,
+};
+
+/// Maximum number of instructions to advance a cursor for a single check.
+const MAX_ADVANCE: usize = 1 << 16;
+
+/// Number of cycles to wait after a divergence is found before reporting it,
+/// since the effect of an earlier instruction may become visible later (e.g.
+/// a store in the M stage happens before the register write of the previous
+/// instruction in the W stage).
+const SETTLE_CYCLES: u64 = 8;
+
+/// The first instruction whose effect differs between the architecture and
+/// the ISA.
+#[derive(Debug, Clone)]
+pub struct Divergence {
+    /// cycle of the architecture at which the difference is found
+    pub cycle: u64,
+    /// index of the instruction in the execution, starting from 1
+    pub n_insts: u64,
+    pub pc: u64,
+    /// disassembly of the instruction
+    pub inst: String,
+    pub detail: String,
+}
+
+impl Display for Divergence {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "instruction #{} at {:#x} ({}) diverges at cycle {}: {}",
+            self.n_insts, self.pc, self.inst, self.cycle, self.detail
+        )
+    }
+}
+
+impl std::error::Error for Divergence {}
+
+/// Which part of the state a cursor follows.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Part {
+    Regs,
+    Mem,
+}
+
+pub struct Lockstep {
+    regs: IsaMachine,
+    mem: IsaMachine,
+    arch_mem: MemData,
+    /// divergences found but not reported yet
+    pending: Vec<(Part, Divergence)>,
+}
+
+impl Lockstep {
+    /// Create a lockstep checker of a program, where `arch_mem` is the memory
+    /// of the architecture running the same program. The architecture should
+    /// be created first, since the ISA implements the exception extension
+    /// only if the architecture maps the exception registers.
+    pub fn new(bin: Box<[u8]>, arch_mem: MemData) -> Self {
+        let machine = || {
+            let mut machine = IsaMachine::new(bin.clone());
+            if arch_mem.io().exc.is_some() {
+                machine.enable_exceptions();
+            }
+            machine
+        };
+        Self {
+            regs: machine(),
+            mem: machine(),
+            arch_mem,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Check the state of the architecture after a cycle. A divergence is
+    /// reported a few cycles after it is found, so that an earlier diverging
+    /// instruction can still be found.
+    pub fn check(&mut self, sim: &dyn CpuSim) -> Result<(), Divergence> {
+        self.sync_all(sim, false);
+        match self.pending.first() {
+            Some((_, d)) if sim.cycle_count() >= d.cycle + SETTLE_CYCLES => Err(self.earliest()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Check the state of the architecture after it terminates, i.e. the ISA
+    /// should not have any effects left, and it should stop with the status
+    /// of the architecture (if declared).
+    pub fn finish(&mut self, sim: &dyn CpuSim) -> Result<(), Divergence> {
+        self.sync_all(sim, true);
+        let isa = &self.regs;
+        if let Some(stat) = sim.status().filter(|s| *s != isa.stat()) {
+            let stopped = isa.stat() != Stat::Aok;
+            let n_insts = isa.n_insts() + (isa.fault().is_some() || !stopped) as u64;
+            let divergence = Divergence {
+                cycle: sim.cycle_count(),
+                n_insts,
+                pc: isa.pc(),
+                inst: disassemble(isa.mem(), isa.pc()),
+                detail: format!("expected status {:?}, got {:?}", isa.stat(), stat),
+            };
+            self.pending.push((Part::Regs, divergence));
+        }
+        match self.pending.is_empty() {
+            true => Ok(()),
+            false => Err(self.earliest()),
+        }
+    }
+
+    fn sync_all(&mut self, sim: &dyn CpuSim, to_end: bool) {
+        for part in [Part::Regs, Part::Mem] {
+            if self.pending.iter().any(|(p, _)| *p == part) {
+                continue;
+            }
+            if let Err(d) = self.sync(part, sim, to_end) {
+                self.pending.push((part, d));
+            }
+        }
+    }
+
+    fn earliest(&self) -> Divergence {
+        let first = self.pending.iter().min_by_key(|(_, d)| d.n_insts);
+        first.unwrap().1.clone()
+    }
+
+    /// Advance the cursor of `part` until it matches the architecture, or
+    /// until it stops if `to_end` is set.
+    fn sync(&mut self, part: Part, sim: &dyn CpuSim, to_end: bool) -> Result<(), Divergence> {
+        let arch_regs = sim.registers();
+        let arch_mem = self.arch_mem.read();
+        let cursor = match part {
+            Part::Regs => &mut self.regs,
+            Part::Mem => &mut self.mem,
+        };
+        let matches = |m: &IsaMachine| match part {
+            Part::Regs => {
+                let skipped = fault_writeback(m);
+                (0..16).all(|i| m.regs()[i] == arch_regs[i] || skipped.contains(&i))
+            }
+            Part::Mem => m.mem() == &*arch_mem,
+        };
+        let diverge = |m: &IsaMachine, n_insts: u64, pc: u64| Divergence {
+            cycle: sim.cycle_count(),
+            n_insts,
+            pc,
+            inst: disassemble(m.mem(), pc),
+            detail: match part {
+                Part::Regs => regs_diff(m.regs(), &arch_regs),
+                Part::Mem => mem_diff(m.mem(), &arch_mem),
+            },
+        };
+
+        // the first instruction changing the part since the last match
+        let mut first = None;
+        for _ in 0..MAX_ADVANCE {
+            if !to_end && matches(cursor) {
+                return Ok(());
+            }
+            let pc = cursor.pc();
+            let prev_regs = *cursor.regs();
+            let icode = cursor.mem().get(pc as usize).map(|b| b >> 4);
+            let writes_mem = matches!(
+                icode,
+                Some(inst_code::RMMOVQ | inst_code::PUSHQ | inst_code::CALL)
+            );
+            if !matches!(cursor.step(), StepOutcome::Retired) {
+                break;
+            }
+            let changed = match part {
+                Part::Regs => *cursor.regs() != prev_regs,
+                Part::Mem => writes_mem,
+            };
+            if changed && first.is_none() {
+                first = Some(diverge(cursor, cursor.n_insts(), pc));
+            }
+        }
+        if matches(cursor) {
+            return Ok(());
+        }
+        Err(first.unwrap_or_else(|| {
+            // the ISA has no more effects, so blame the current instruction
+            let n_insts = cursor.n_insts() + !cursor.is_halted() as u64;
+            let mut divergence = diverge(cursor, n_insts, cursor.pc());
+            if let Some(fault) = cursor.fault() {
+                divergence.detail = format!(
+                    "the ISA stops with {:?} ({fault}); {}",
+                    fault.stat, divergence.detail
+                );
+            }
+            divergence
+        }))
+    }
+}
+
+/// Registers written back by the faulting instruction of `machine`, which
+/// are not compared. Like the CS:APP hardware, an architecture may still write
+/// the results of an instruction accessing an invalid data address.
+pub(super) fn fault_writeback(machine: &IsaMachine) -> Vec<usize> {
+    let Some(fault) = machine.fault().filter(|f| f.stat == Stat::Adr) else {
+        return Vec::new();
+    };
+    let pc = fault.pc as usize;
+    let Some(bytes) = machine.mem().get(pc..pc + 2) else {
+        return Vec::new();
+    };
+    let (ra, rsp) = ((bytes[1] >> 4) as usize, reg_code::RSP as usize);
+    match bytes[0] >> 4 {
+        inst_code::MRMOVQ => vec![ra],
+        inst_code::POPQ => vec![ra, rsp],
+        inst_code::PUSHQ | inst_code::CALL | inst_code::RET => vec![rsp],
+        _ => Vec::new(),
+    }
+}
+
+/// Disassemble the instruction at `pc`.
+fn disassemble(mem: &[u8], pc: u64) -> String {
+    let bytes = mem.get(pc as usize..).unwrap_or_default();
+    match crate::asm::decode(bytes, pc) {
+        Ok((inst, _)) => inst.to_string(),
+        Err(_) => "invalid instruction".to_string(),
+    }
+}
+
+fn regs_diff(expected: &RegFile, got: &RegFile) -> String {
+    let diffs: Vec<String> = (0..16)
+        .filter(|&i| expected[i] != got[i])
+        .map(|i| {
+            format!(
+                "expected %{} = {:#x}, got {:#x}",
+                reg_code::name_of(i as u8).to_lowercase(),
+                expected[i],
+                got[i]
+            )
+        })
+        .collect();
+    diffs.join("; ")
+}
+
+fn mem_diff(expected: &[u8], got: &[u8]) -> String {
+    let diffs: Vec<String> = expected
+        .chunks(8)
+        .zip(got.chunks(8))
+        .enumerate()
+        .filter(|(_, (e, g))| e != g)
+        .take(4)
+        .map(|(i, (e, g))| {
+            format!(
+                "expected M[{:#x}] = {:#x}, got {:#x}",
+                i * 8,
+                get_u64(e),
+                get_u64(g)
+            )
+        })
+        .collect();
+    diffs.join("; ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Lockstep;
+    use crate::framework::MemData;
+
+    fn init_mem(src: &str) -> Box<[u8]> {
+        super::super::make_obj(src).unwrap().obj.init_mem()
+    }
+
+    #[test]
+    fn test_divergence() {
+        let expected = init_mem("irmovq $1, %rax\nirmovq $2, %rbx\nrmmovq %rbx, 0x100\nhalt\n");
+        let actual = init_mem("irmovq $1, %rax\nirmovq $3, %rbx\nrmmovq %rbx, 0x100\nhalt\n");
+        let mem = MemData::init(actual);
+        let mut sim = crate::architectures::create_sim("pipe_std".into(), mem.clone(), false);
+        let mut lockstep = Lockstep::new(expected, mem);
+        let err = loop {
+            sim.step();
+            if let Err(e) = lockstep.check(sim.as_ref()) {
+                break e;
+            }
+            if sim.is_terminate() {
+                break lockstep.finish(sim.as_ref()).unwrap_err();
+            }
+        };
+        assert_eq!((err.n_insts, err.pc), (2, 0xa));
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "instruction #2 at 0xa (irmovq $0x2, %rbx) diverges at cycle {}: \
+                 expected %rbx = 0x2, got 0x3",
+                err.cycle
+            )
+        );
+
+        // the same program never diverges
+        let bin = init_mem("irmovq $0x100, %rsp\nirmovq $1, %rax\npushq %rax\npopq %rbx\nhalt\n");
+        let mem = MemData::init(bin.clone());
+        let mut sim = crate::architectures::create_sim("seq_std".into(), mem.clone(), false);
+        let mut lockstep = Lockstep::new(bin, mem);
+        while !sim.is_terminate() {
+            sim.step();
+            lockstep.check(sim.as_ref()).unwrap();
+        }
+        lockstep.finish(sim.as_ref()).unwrap();
+    }
+
+    /// The last instruction of a small memory can be fetched, but not an
+    /// instruction crossing the end of the memory. The devices are mapped
+    /// regardless of the memory size.
+    #[test]
+    fn test_small_memory() {
+        use crate::isa::Stat;
+
+        let cases = [
+            (0x10, "irmovq $1, %rax\nhalt\n", Stat::Hlt, ""),
+            (0x10, "jmp 0xc\n.pos 0xc\n.byte 0x30\n", Stat::Adr, ""),
+            (
+                0x20,
+                "irmovq $0x10000000, %rdx\nirmovq $0x41, %rax\nrmmovq %rax, (%rdx)\nhalt\n",
+                Stat::Hlt,
+                "A",
+            ),
+        ];
+        for (size, src, stat, output) in cases {
+            let option = crate::AssembleOption::default().set_mem_size(size);
+            let bin = crate::assemble(src, option).unwrap().obj.init_mem();
+            assert_eq!(bin.len(), size);
+            for arch in ["seq_std", "seq_plus_std", "pipe_std", "pipe_exc"] {
+                let mem = MemData::init(bin.clone());
+                let mut sim = crate::architectures::create_sim(arch.into(), mem.clone(), false);
+                let mut lockstep = Lockstep::new(bin.clone(), mem);
+                while !sim.is_terminate() {
+                    sim.step();
+                    lockstep.check(sim.as_ref()).unwrap();
+                }
+                lockstep.finish(sim.as_ref()).unwrap();
+                assert_eq!(sim.status(), Some(stat), "{arch}: {src}");
+                assert_eq!(sim.io_devices().output_lossy(), output);
+            }
+        }
+    }
+
+    /// A `cmovXX` or `jXX` whose function code has no condition is an invalid
+    /// instruction in both the ISA and the builtin architectures.
+    #[test]
+    fn test_invalid_condition() {
+        use crate::isa::Stat;
+
+        let srcs = [
+            "irmovq $1, %rax\n.byte 0x27, 0x03\nhalt\n",
+            "irmovq $1, %rax\n.byte 0x7f\n.quad 0\nhalt\n",
+        ];
+        for src in srcs {
+            let bin = init_mem(src);
+            for arch in ["seq_std", "seq_plus_std", "pipe_std", "pipe_exc"] {
+                let mem = MemData::init(bin.clone());
+                let mut sim = crate::architectures::create_sim(arch.into(), mem.clone(), false);
+                let mut lockstep = Lockstep::new(bin.clone(), mem);
+                while !sim.is_terminate() {
+                    sim.step();
+                    lockstep.check(sim.as_ref()).unwrap();
+                }
+                lockstep.finish(sim.as_ref()).unwrap();
+                assert_eq!(sim.status(), Some(Stat::Ins), "{arch}: {src}");
+            }
+        }
+    }
+}
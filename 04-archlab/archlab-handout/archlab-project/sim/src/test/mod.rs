@@ -3,8 +3,14 @@
 
 mod diff;
 mod inst;
+mod lockstep;
 
-use crate::framework::{CpuSim, MemData};
+pub use lockstep::{Divergence, Lockstep};
+
+use crate::{
+    framework::{CpuSim, MemData, MEM_SIZE},
+    isa::Extension,
+};
 
 pub struct SimTester {
     arch: String,
@@ -12,28 +18,35 @@ pub struct SimTester {
 
 impl SimTester {
     pub fn new(arch: &str) -> Option<Self> {
-        if crate::architectures::arch_names()
-            .iter()
-            .any(|&a| a == arch)
-        {
+        if crate::architectures::arch_names().contains(&arch) {
             Some(Self { arch: arch.into() })
         } else {
             None
         }
     }
 
-    /// Simulate the given source code and return the simulator and the memory
-    /// after the simulation.
+    /// Whether the architecture declares the ISA extension.
+    pub fn supports(&self, ext: Extension) -> bool {
+        let mem = MemData::zeroed(MEM_SIZE);
+        let sim = crate::architectures::create_sim(self.arch.clone(), mem, false);
+        sim.extensions().contains(&ext)
+    }
+
+    /// Simulate the given source code in lockstep with the ISA, and return
+    /// the simulator and the memory after the simulation.
     fn simulate_arch(arch: String, src: &str) -> anyhow::Result<(Box<dyn CpuSim>, MemData)> {
         let obj = make_obj(src)?;
         let mem = MemData::init(obj.obj.init_mem());
         let mut pipe = crate::architectures::create_sim(arch, mem.clone(), false);
+        let mut lockstep = Lockstep::new(obj.obj.init_mem(), mem.clone());
         while !pipe.is_terminate() {
             pipe.step();
+            lockstep.check(pipe.as_ref())?;
             if pipe.cycle_count() > 3_000_000 {
                 anyhow::bail!("exceed maximum CPU cycle limit");
             }
         }
+        lockstep.finish(pipe.as_ref())?;
         Ok((pipe, mem))
     }
 }
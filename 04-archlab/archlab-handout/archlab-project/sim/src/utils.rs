@@ -1,12 +1,16 @@
 use binutils::clap::builder::styling::*;
 
-use crate::{framework::MEM_SIZE, isa::RegFile};
+use crate::{framework::MAX_MEM_SIZE, isa::RegFile};
 
 pub const GRAY: Style = Style::new().fg_color(Some(Color::Ansi(AnsiColor::BrightBlack)));
 pub const RED: Style = Style::new().fg_color(Some(Color::Ansi(AnsiColor::Red)));
 pub const REDB: Style = RED.bold();
 pub const GRN: Style = Style::new().fg_color(Some(Color::Ansi(AnsiColor::Green)));
 pub const GRNB: Style = GRN.bold();
+pub const BLU: Style = Style::new().fg_color(Some(Color::Ansi(AnsiColor::BrightBlue)));
+pub const BLUB: Style = BLU.bold();
+pub const YLW: Style = Style::new().fg_color(Some(Color::Ansi(AnsiColor::Yellow)));
+pub const YLWB: Style = YLW.bold();
 pub const B: Style = Style::new().bold();
 
 /// Parse numeric literal from string in yas source file.
@@ -14,16 +18,26 @@ pub const B: Style = Style::new().bold();
 /// For decimal number, it should be a valid i64.
 /// For hexadecimal number, it should be prefixed with "0x" and in range of u64.
 pub fn parse_literal(s: &str) -> Option<u64> {
-    let (sign, s) = s.strip_suffix("-").map(|s| (-1, s)).unwrap_or((1, s));
+    let (sign, s) = s.strip_prefix('-').map(|s| (-1, s)).unwrap_or((1, s));
     if let Ok(r) = s.parse::<i64>() {
         return Some((r * sign) as u64);
     }
     if let Ok(r) = u64::from_str_radix(s.strip_prefix("0x")?, 16) {
-        return Some((r as i64 * sign) as u64);
+        return Some((r as i64).wrapping_mul(sign) as u64);
     }
     None
 }
 
+/// Parse the size of the memory, e.g. for `--mem-size`. It should be a
+/// positive number at most [`MAX_MEM_SIZE`].
+pub fn parse_mem_size(s: &str) -> Result<usize, String> {
+    match parse_literal(s) {
+        Some(size @ 1..) if size <= MAX_MEM_SIZE as u64 => Ok(size as usize),
+        Some(_) => Err(format!("expect a size from 1 to {MAX_MEM_SIZE:#x}")),
+        None => Err(format!("invalid number `{s}`")),
+    }
+}
+
 /// Get 64-bit unsigned integer value in little endian order.
 pub fn get_u64(binary: &[u8]) -> u64 {
     let mut res = 0;
@@ -47,20 +61,17 @@ pub fn decode_hex(s: &str) -> Result<Vec<u8>, std::num::ParseIntError> {
         .collect()
 }
 
-pub fn mem_diff(left: &[u8; MEM_SIZE], right: &[u8; MEM_SIZE]) {
-    for i in 0..MEM_SIZE >> 3 {
+pub fn mem_diff(left: &[u8], right: &[u8]) {
+    for (i, (l, r)) in left.chunks(8).zip(right.chunks(8)).enumerate() {
         let offset = i << 3;
-        if get_u64(&left[offset..]) != get_u64(&right[offset..]) {
-            let l = &left[offset..offset + 8];
-            let r = &right[offset..offset + 8];
-
+        if l != r {
             print!("{:#06x}: ", offset);
-            for i in 0..8 {
+            for i in 0..l.len() {
                 let s = if l[i] != r[i] { REDB } else { GRAY };
                 print!("{s}{:02x}{s:#}", l[i])
             }
             print!(" -> ");
-            for i in 0..8 {
+            for i in 0..r.len() {
                 let s = if l[i] != r[i] { GRNB } else { GRAY };
                 print!("{s}{:02x}{s:#}", r[i])
             }
@@ -69,9 +80,9 @@ pub fn mem_diff(left: &[u8; MEM_SIZE], right: &[u8; MEM_SIZE]) {
     }
 }
 
-pub fn mem_print(bin: &[u8; MEM_SIZE]) {
+pub fn mem_print(bin: &[u8]) {
     let mut max_i = 0;
-    for i in 0..MEM_SIZE >> 3 {
+    for i in 0..bin.len().div_ceil(8) {
         if get_u64(&bin[i << 3..]) != 0 {
             max_i = i;
         }
@@ -0,0 +1,523 @@
+//! Value Change Dump (VCD) of the signals of an architecture, written by
+//! `ysim --vcd`, which can be inspected by waveform viewers such as GTKWave
+//! and Surfer.
+//!
+//! The signals of a cycle are collected by serializing the pipeline
+//! registers, the intermediate signals and the ports of the units (see
+//! [`crate::framework::CpuSim::vcd_signals`]). Their names are hierarchical,
+//! e.g. `E.valA` (a pipeline register by its alias in HCL), `d_srcA` (an
+//! intermediate signal) and `alu.e` (a port of a unit), and their widths are
+//! derived from their types:
+//!
+//! - `bool` has 1 bit, and integers have their own widths (e.g. `u8` has 8
+//!   bits);
+//! - an enum is encoded by the index of its variant, e.g. [`Stat`] in 3 bits;
+//! - a struct is a scope of its fields, e.g. `reg_cc.cc.zf` for a
+//!   [`ConditionCode`];
+//! - an array is packed into a single signal whose first element is the most
+//!   significant, e.g. the 72-bit `imem.align`.
+//!
+//! [`Stat`]: crate::isa::Stat
+//! [`ConditionCode`]: crate::isa::ConditionCode
+
+use std::{fmt::Display, io::Write};
+
+use serde::{ser, Serialize};
+
+use crate::framework::StageControl;
+
+/// A signal in a cycle.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Signal {
+    /// hierarchical name separated by `.`
+    pub name: String,
+    /// number of bits, at most 128
+    pub width: u32,
+    pub value: u128,
+}
+
+/// A value that can not be dumped as a signal, e.g. a string.
+#[derive(Debug)]
+pub struct Error(String);
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl ser::Error for Error {
+    fn custom<T: Display>(msg: T) -> Self {
+        Self(msg.to_string())
+    }
+}
+
+/// Width of an enum, which is encoded by the index of its variant.
+fn enum_width(name: &str) -> u32 {
+    match name {
+        // Aok, Bub, Hlt, Adr and Ins
+        "Stat" => 3,
+        _ => 8,
+    }
+}
+
+/// Collect the signals of a cycle, where the stages are renamed by
+/// `stage_alias` (pairs of the short name and the alias, e.g. `("e", "E")`),
+/// and their `bubble` and `stall` signals are given by `controls`.
+pub fn signals(
+    stages: &impl Serialize,
+    controls: &[StageControl],
+    stage_alias: &[(&str, &str)],
+    inter: &impl Serialize,
+    unit_in: &impl Serialize,
+    unit_out: &impl Serialize,
+) -> Result<Vec<Signal>, Error> {
+    let mut signals = Vec::new();
+    inter.serialize(Flatten::new(&mut signals))?;
+
+    let mut stage_signals = Vec::new();
+    stages.serialize(Flatten::new(&mut stage_signals))?;
+    for signal in &mut stage_signals {
+        let (stage, field) = signal.name.split_once('.').unwrap_or_default();
+        if let Some(control) = controls.iter().find(|c| c.name == stage) {
+            match field {
+                "bubble" => signal.value = control.bubble as u128,
+                "stall" => signal.value = control.stall as u128,
+                _ => {}
+            }
+        }
+        if let Some((_, alias)) = stage_alias.iter().find(|(cur, _)| *cur == stage) {
+            signal.name = format!("{alias}.{field}");
+        }
+    }
+    signals.append(&mut stage_signals);
+
+    // the inputs and outputs of a unit are in the same scope
+    let mut unit_signals = Vec::new();
+    unit_in.serialize(Flatten::new(&mut unit_signals))?;
+    unit_out.serialize(Flatten::new(&mut unit_signals))?;
+    let unit_of = |s: &Signal| s.name.split('.').next().unwrap_or_default().to_string();
+    let mut units = Vec::new();
+    for unit in unit_signals.iter().map(unit_of) {
+        if !units.contains(&unit) {
+            units.push(unit);
+        }
+    }
+    unit_signals.sort_by_cached_key(|s| units.iter().position(|u| *u == unit_of(s)));
+    signals.append(&mut unit_signals);
+
+    Ok(signals)
+}
+
+/// Write the signals of each cycle in the VCD format, where the time of a
+/// cycle is its cycle count (in ns).
+pub struct VcdWriter<W: Write> {
+    out: W,
+    /// name of the top scope, e.g. the architecture
+    scope: String,
+    /// values of the last cycle, `None` before the first cycle
+    last: Option<Vec<u128>>,
+}
+
+impl<W: Write> VcdWriter<W> {
+    pub fn new(out: W, scope: &str) -> Self {
+        Self {
+            out,
+            scope: scope.to_string(),
+            last: None,
+        }
+    }
+
+    /// Write the signals of a cycle, which should be the same signals (in the
+    /// same order) as the first cycle.
+    pub fn write_cycle(&mut self, cycle: u64, signals: &[Signal]) -> std::io::Result<()> {
+        let last = match &mut self.last {
+            Some(last) => last,
+            None => {
+                self.write_header(signals)?;
+                writeln!(self.out, "#{cycle}")?;
+                writeln!(self.out, "$dumpvars")?;
+                for (index, signal) in signals.iter().enumerate() {
+                    write_value(&mut self.out, index, signal)?;
+                }
+                writeln!(self.out, "$end")?;
+                self.last = Some(signals.iter().map(|s| s.value).collect());
+                return Ok(());
+            }
+        };
+        debug_assert_eq!(last.len(), signals.len());
+        writeln!(self.out, "#{cycle}")?;
+        for (index, (signal, last)) in signals.iter().zip(last).enumerate() {
+            if signal.value != *last {
+                write_value(&mut self.out, index, signal)?;
+                *last = signal.value;
+            }
+        }
+        Ok(())
+    }
+
+    /// End the dump at `time`, i.e. the end of the last cycle.
+    pub fn finish(mut self, time: u64) -> std::io::Result<()> {
+        writeln!(self.out, "#{time}")?;
+        self.out.flush()
+    }
+
+    fn write_header(&mut self, signals: &[Signal]) -> std::io::Result<()> {
+        let out = &mut self.out;
+        writeln!(out, "$version ysim {} $end", env!("CARGO_PKG_VERSION"))?;
+        writeln!(out, "$timescale 1ns $end")?;
+        writeln!(out, "$scope module {} $end", self.scope)?;
+        let mut scopes: Vec<&str> = Vec::new();
+        for (index, signal) in signals.iter().enumerate() {
+            let mut path: Vec<&str> = signal.name.split('.').collect();
+            let name = path.pop().unwrap_or_default();
+            let common = scopes.iter().zip(&path).take_while(|(a, b)| a == b).count();
+            for _ in common..scopes.len() {
+                writeln!(out, "$upscope $end")?;
+            }
+            scopes.truncate(common);
+            for scope in &path[common..] {
+                writeln!(out, "$scope module {scope} $end")?;
+                scopes.push(scope);
+            }
+            writeln!(
+                out,
+                "$var wire {} {} {} $end",
+                signal.width,
+                id(index),
+                name
+            )?;
+        }
+        for _ in 0..=scopes.len() {
+            writeln!(out, "$upscope $end")?;
+        }
+        writeln!(out, "$enddefinitions $end")
+    }
+}
+
+/// Identifier code of the `index`-th signal, which consists of printable
+/// ASCII characters.
+fn id(mut index: usize) -> String {
+    let mut id = String::new();
+    loop {
+        id.push((b'!' + (index % 94) as u8) as char);
+        index /= 94;
+        if index == 0 {
+            return id;
+        }
+        index -= 1;
+    }
+}
+
+fn write_value(out: &mut impl Write, index: usize, signal: &Signal) -> std::io::Result<()> {
+    match signal.width {
+        1 => writeln!(out, "{}{}", signal.value, id(index)),
+        _ => writeln!(out, "b{:b} {}", signal.value, id(index)),
+    }
+}
+
+/// A serializer that appends the signals of a value to `out`.
+struct Flatten<'a> {
+    path: String,
+    out: &'a mut Vec<Signal>,
+}
+
+impl<'a> Flatten<'a> {
+    fn new(out: &'a mut Vec<Signal>) -> Self {
+        Self {
+            path: String::new(),
+            out,
+        }
+    }
+
+    fn field(&mut self, name: &str) -> Flatten<'_> {
+        let path = match self.path.is_empty() {
+            true => name.to_string(),
+            false => format!("{}.{name}", self.path),
+        };
+        Flatten {
+            path,
+            out: &mut *self.out,
+        }
+    }
+
+    fn push(self, width: u32, value: u128) -> Result<(), Error> {
+        self.out.push(Signal {
+            name: self.path,
+            width,
+            value,
+        });
+        Ok(())
+    }
+
+    fn unsupported(&self) -> Error {
+        Error(format!("unsupported type of signal `{}`", self.path))
+    }
+}
+
+macro_rules! serialize_int {
+    ($($method:ident: $ty:ty => $uty:ty),*) => {$(
+        fn $method(self, v: $ty) -> Result<(), Error> {
+            self.push(<$ty>::BITS, v as $uty as u128)
+        }
+    )*};
+}
+
+impl<'a> ser::Serializer for Flatten<'a> {
+    type Ok = ();
+    type Error = Error;
+    type SerializeSeq = Pack<'a>;
+    type SerializeTuple = Pack<'a>;
+    type SerializeTupleStruct = Pack<'a>;
+    type SerializeTupleVariant = ser::Impossible<(), Error>;
+    type SerializeMap = ser::Impossible<(), Error>;
+    type SerializeStruct = Self;
+    type SerializeStructVariant = ser::Impossible<(), Error>;
+
+    fn serialize_bool(self, v: bool) -> Result<(), Error> {
+        self.push(1, v as u128)
+    }
+
+    serialize_int! {
+        serialize_i8: i8 => u8, serialize_i16: i16 => u16,
+        serialize_i32: i32 => u32, serialize_i64: i64 => u64,
+        serialize_i128: i128 => u128,
+        serialize_u8: u8 => u8, serialize_u16: u16 => u16,
+        serialize_u32: u32 => u32, serialize_u64: u64 => u64,
+        serialize_u128: u128 => u128
+    }
+
+    fn serialize_f32(self, _: f32) -> Result<(), Error> {
+        Err(self.unsupported())
+    }
+
+    fn serialize_f64(self, _: f64) -> Result<(), Error> {
+        Err(self.unsupported())
+    }
+
+    fn serialize_char(self, _: char) -> Result<(), Error> {
+        Err(self.unsupported())
+    }
+
+    fn serialize_str(self, _: &str) -> Result<(), Error> {
+        Err(self.unsupported())
+    }
+
+    fn serialize_bytes(self, _: &[u8]) -> Result<(), Error> {
+        Err(self.unsupported())
+    }
+
+    fn serialize_none(self) -> Result<(), Error> {
+        Err(self.unsupported())
+    }
+
+    fn serialize_some<T: ?Sized + Serialize>(self, _: &T) -> Result<(), Error> {
+        Err(self.unsupported())
+    }
+
+    fn serialize_unit(self) -> Result<(), Error> {
+        Err(self.unsupported())
+    }
+
+    fn serialize_unit_struct(self, _: &'static str) -> Result<(), Error> {
+        Err(self.unsupported())
+    }
+
+    fn serialize_unit_variant(
+        self,
+        name: &'static str,
+        variant_index: u32,
+        _: &'static str,
+    ) -> Result<(), Error> {
+        self.push(enum_width(name), variant_index as u128)
+    }
+
+    fn serialize_newtype_struct<T: ?Sized + Serialize>(
+        self,
+        _: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: ?Sized + Serialize>(
+        self,
+        _: &'static str,
+        _: u32,
+        _: &'static str,
+        _: &T,
+    ) -> Result<(), Error> {
+        Err(self.unsupported())
+    }
+
+    fn serialize_seq(self, _: Option<usize>) -> Result<Pack<'a>, Error> {
+        Ok(Pack {
+            signal: self,
+            width: 0,
+            value: 0,
+        })
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<Pack<'a>, Error> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(self, _: &'static str, len: usize) -> Result<Pack<'a>, Error> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        _: &'static str,
+        _: usize,
+    ) -> Result<Self::SerializeTupleVariant, Error> {
+        Err(self.unsupported())
+    }
+
+    fn serialize_map(self, _: Option<usize>) -> Result<Self::SerializeMap, Error> {
+        Err(self.unsupported())
+    }
+
+    fn serialize_struct(self, _: &'static str, _: usize) -> Result<Self, Error> {
+        Ok(self)
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        _: &'static str,
+        _: usize,
+    ) -> Result<Self::SerializeStructVariant, Error> {
+        Err(self.unsupported())
+    }
+}
+
+impl ser::SerializeStruct for Flatten<'_> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: ?Sized + Serialize>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        value.serialize(self.field(key))
+    }
+
+    fn end(self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// Pack the elements of an array into a single signal.
+struct Pack<'a> {
+    signal: Flatten<'a>,
+    width: u32,
+    value: u128,
+}
+
+impl Pack<'_> {
+    fn element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), Error> {
+        let mut elements = Vec::new();
+        value.serialize(Flatten::new(&mut elements))?;
+        match elements[..] {
+            [Signal { width, value, .. }] if self.width + width <= 128 => {
+                self.value = self.value.checked_shl(width).unwrap_or(0) | value;
+                self.width += width;
+                Ok(())
+            }
+            _ => Err(self.signal.unsupported()),
+        }
+    }
+}
+
+macro_rules! impl_pack {
+    ($($trait:ident :: $method:ident),*) => {$(
+        impl ser::$trait for Pack<'_> {
+            type Ok = ();
+            type Error = Error;
+
+            fn $method<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), Error> {
+                self.element(value)
+            }
+
+            fn end(self) -> Result<(), Error> {
+                self.signal.push(self.width, self.value)
+            }
+        }
+    )*};
+}
+
+impl_pack!(
+    SerializeSeq::serialize_element,
+    SerializeTuple::serialize_element,
+    SerializeTupleStruct::serialize_field
+);
+
+#[cfg(test)]
+mod tests {
+    use super::{id, VcdWriter};
+    use crate::framework::MemData;
+
+    const SRC: &str = "    irmovq $3, %rax
+    irmovq $4, %rbx
+    addq %rax, %rbx
+    halt
+";
+
+    #[test]
+    fn test_vcd() {
+        let a = crate::assemble(SRC, crate::AssembleOption::default()).unwrap();
+        let mem = MemData::init(a.obj.init_mem());
+        let mut sim = crate::architectures::create_sim("pipe_std".into(), mem, false);
+        let mut out = Vec::new();
+        let mut vcd = VcdWriter::new(&mut out, "pipe_std");
+        let mut cycles = Vec::new();
+        loop {
+            sim.propagate_signals();
+            let signals = sim.vcd_signals().unwrap();
+            vcd.write_cycle(sim.cycle_count(), &signals).unwrap();
+            cycles.push(signals);
+            if sim.is_terminate() {
+                break;
+            }
+            sim.initiate_next_cycle();
+        }
+        vcd.finish(sim.cycle_count() + 1).unwrap();
+
+        let get = |cycle: usize, name: &str| {
+            let signal = cycles[cycle - 1].iter().find(|s| s.name == name);
+            signal.map(|s| (s.width, s.value)).unwrap()
+        };
+        // the addq is in the E stage in cycle 5, and the stages are bubbled
+        // in the first cycle
+        assert_eq!(get(5, "E.icode"), (8, 6));
+        assert_eq!(get(5, "E.valA"), (64, 3));
+        assert_eq!(get(5, "alu.e"), (64, 7));
+        assert_eq!(get(1, "W.stat"), (3, 1));
+        assert_eq!(get(5, "e_valE"), (64, 7));
+        assert_eq!(get(6, "reg_cc.cc.zf"), (1, 0));
+        assert_eq!(get(1, "imem.align").0, 72);
+        assert_eq!(get(1, "imem.align").1 >> 64, 0xf0);
+        assert!(cycles.iter().any(|c| c.iter().any(|s| s.name == "D.stall")));
+
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("$scope module E $end"));
+        assert!(out.contains("$var wire 64 "));
+        assert!(out.contains("$enddefinitions $end\n#1\n$dumpvars\n"));
+        assert!(out.ends_with(&format!("#{}\n", cycles.len() + 1)));
+        assert_eq!(
+            out.matches("$scope").count(),
+            out.matches("$upscope").count()
+        );
+
+        assert_eq!(id(0), "!");
+        assert_eq!(id(93), "~");
+        assert_eq!(id(94), "!!");
+    }
+}
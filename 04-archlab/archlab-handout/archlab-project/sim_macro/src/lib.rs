@@ -11,6 +11,10 @@ struct HclData {
     hardware: syn::ExprPath,
     program_counter: LValue,
     termination: LValue,
+    /// signal of the program status, if declared
+    status: Option<LValue>,
+    /// variants of `crate::isa::Extension` implemented by the architecture
+    extensions: Vec<syn::Ident>,
     /// (cur, pre)
     stage_alias: items::StageAlias,
     stage_decls: Vec<items::StageDecl>,
@@ -88,6 +92,34 @@ impl Parse for HclData {
             })
             .unwrap();
 
+        let status = attrs.iter().find_map(|attr| {
+            if attr.path().is_ident("status") {
+                let value = &attr.meta.require_name_value().unwrap().value;
+                // parse value as path
+                let syn::Expr::Path(path) = value else {
+                    panic!("status attribute must be a path");
+                };
+
+                Some(parse_quote!(#path))
+            } else {
+                None
+            }
+        });
+
+        let extensions = attrs
+            .iter()
+            .find_map(|attr| {
+                if attr.path().is_ident("extensions") {
+                    let names = attr
+                        .parse_args_with(Punctuated::<syn::Ident, Token![,]>::parse_terminated)
+                        .unwrap();
+                    Some(names.into_iter().collect())
+                } else {
+                    None
+                }
+            })
+            .unwrap_or_default();
+
         let mut use_items = Vec::new();
         let mut intermediate_signals = Vec::new();
         let mut stage_decls = Vec::new();
@@ -167,6 +199,8 @@ impl Parse for HclData {
             hardware,
             program_counter,
             termination,
+            status,
+            extensions,
             use_items,
             intermediate_signals,
             stage_decls,
@@ -534,6 +568,15 @@ impl HclData {
         let get_stage_info_fn = self.render_get_stage_info();
         let pc_name = &self.program_counter;
         let termination = &self.termination;
+        let status = match &self.status {
+            Some(status) => quote! { Some(self.cur_inter.#status) },
+            None => quote! { None },
+        };
+        let extensions = &self.extensions;
+        let stage_alias = self.stage_alias.0.iter().map(|(cur, pre)| {
+            let (cur, pre) = (cur.to_string(), pre.to_string());
+            quote! { (#cur, #pre) }
+        });
 
         quote! {
             use #hardware::*;
@@ -582,19 +625,57 @@ impl HclData {
                 fn is_terminate(&self) -> bool {
                     self.terminate
                 }
+                fn status(&self) -> Option<crate::isa::Stat> {
+                    #status
+                }
                 fn cycle_count(&self) -> u64 {
                     self.cycle_count
                 }
                 fn cycle_cost(&self) -> u64 {
                     self.circuit.order.max_dist as u64
                 }
+                fn extensions(&self) -> &'static [crate::isa::Extension] {
+                    &[#(crate::isa::Extension::#extensions),*]
+                }
                 fn registers(&self) -> crate::isa::RegFile {
                     use crate::framework::HardwareUnits;
                     self.units.register_file()
                 }
+                fn io_devices(&self) -> crate::mmio::IoDevices {
+                    use crate::framework::HardwareUnits;
+                    self.units.io_devices()
+                }
 
                 #get_stage_info_fn
 
+                fn stage_controls(&self) -> Vec<crate::framework::StageControl> {
+                    self.nex_state.controls()
+                }
+
+                #[cfg(feature = "serde")]
+                fn trace_record(&self) -> serde_json::Value {
+                    serde_json::json!({
+                        "cycle": self.cycle_count,
+                        "pc": self.program_counter(),
+                        "stages": self.cur_state,
+                        "signals": self.cur_inter,
+                        "unit_in": self.cur_unit_in,
+                        "unit_out": self.cur_unit_out,
+                    })
+                }
+
+                #[cfg(feature = "serde")]
+                fn vcd_signals(&self) -> Result<Vec<crate::vcd::Signal>, crate::vcd::Error> {
+                    crate::vcd::signals(
+                        &self.cur_state,
+                        &self.nex_state.controls(),
+                        &[#(#stage_alias),*],
+                        &self.cur_inter,
+                        &self.cur_unit_in,
+                        &self.cur_unit_out,
+                    )
+                }
+
                 fn step(&mut self) {
                     use binutils::clap::builder::styling::*;
                     let title_style = Style::new().bold();
@@ -645,6 +726,18 @@ impl HclData {
                 fn proporder(&self) -> &crate::framework::PropOrder {
                     &self.circuit.order
                 }
+
+                fn snapshot(&self) -> Box<dyn crate::framework::SimSnapshot> {
+                    Box::new(self.save())
+                }
+
+                fn restore(&mut self, snapshot: &dyn crate::framework::SimSnapshot) {
+                    let snapshot = snapshot
+                        .as_any()
+                        .downcast_ref::<crate::framework::PipeSnapshot<Arch>>()
+                        .expect("snapshot of another architecture");
+                    self.load(snapshot);
+                }
             }
         }
     }